@@ -0,0 +1,135 @@
+use crate::userdb::SentimentResult;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::{error, info};
+
+/// A sentiment backend that scores a single message.
+///
+/// Implementations are shared between the event handler and the commands, so
+/// any per-thread state (such as a model session) has to be managed inside
+/// the implementation.
+pub trait SentimentAnalyzer: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn analyze(&self, msg: &str) -> SentimentResult;
+}
+
+pub struct VaderAnalyzer;
+
+impl SentimentAnalyzer for VaderAnalyzer {
+    fn name(&self) -> &str {
+        "vader"
+    }
+
+    fn analyze(&self, msg: &str) -> SentimentResult {
+        thread_local! {
+            static ANALYZER: vader_sentiment::SentimentIntensityAnalyzer<'static> =
+                             vader_sentiment::SentimentIntensityAnalyzer::new();
+        }
+
+        ANALYZER.with(|analyzer| {
+            SentimentResult::from_hashmap(analyzer.polarity_scores(msg))
+        })
+    }
+}
+
+/// Scores messages against a word list, for languages VADER does not cover.
+///
+/// The lexicon file uses the VADER format: one entry per line with the token
+/// and its valence (-4.0 to 4.0) separated by a tab. Any further columns are
+/// ignored.
+pub struct LexiconAnalyzer {
+    lexicon: HashMap<String, f64>,
+}
+
+impl LexiconAnalyzer {
+    // Same normalization constant as VADER so compound scores are comparable.
+    const NORMALIZATION_ALPHA: f64 = 15.0;
+
+    pub fn from_file(path: &str) -> std::io::Result<LexiconAnalyzer> {
+        let contents = std::fs::read_to_string(path)?;
+        Ok(LexiconAnalyzer::from_lexicon(&contents))
+    }
+
+    pub fn from_lexicon(contents: &str) -> LexiconAnalyzer {
+        let mut lexicon = HashMap::new();
+        for line in contents.lines() {
+            let mut columns = line.split('\t');
+            if let (Some(token), Some(valence)) =
+                (columns.next(), columns.next())
+            {
+                if let Ok(valence) = valence.trim().parse::<f64>() {
+                    lexicon.insert(token.trim().to_lowercase(), valence);
+                }
+            }
+        }
+        LexiconAnalyzer { lexicon }
+    }
+}
+
+impl SentimentAnalyzer for LexiconAnalyzer {
+    fn name(&self) -> &str {
+        "lexicon"
+    }
+
+    fn analyze(&self, msg: &str) -> SentimentResult {
+        let mut positive_sum = 0.0;
+        let mut negative_sum = 0.0;
+        let mut neutral_count = 0.0;
+
+        for word in msg.split_whitespace() {
+            let token = word
+                .trim_matches(|c: char| !c.is_alphanumeric())
+                .to_lowercase();
+            match self.lexicon.get(&token) {
+                Some(&valence) if valence > 0.0 => positive_sum += valence,
+                Some(&valence) if valence < 0.0 => negative_sum += valence,
+                _ => neutral_count += 1.0,
+            }
+        }
+
+        let sum = positive_sum + negative_sum;
+        let compound =
+            sum / (sum * sum + LexiconAnalyzer::NORMALIZATION_ALPHA).sqrt();
+        let total = positive_sum + negative_sum.abs() + neutral_count;
+        if total == 0.0 {
+            return SentimentResult::new(0.0, 1.0, 0.0, 0.0);
+        }
+
+        SentimentResult::new(
+            negative_sum.abs() / total,
+            neutral_count / total,
+            positive_sum / total,
+            compound,
+        )
+    }
+}
+
+/// Creates the sentiment backend named by `backend`, falling back to VADER
+/// if it cannot be created.
+pub fn create_analyzer(
+    backend: &str,
+    lexicon_path: Option<&str>,
+) -> Arc<dyn SentimentAnalyzer> {
+    let analyzer: Arc<dyn SentimentAnalyzer> = match backend {
+        "vader" => Arc::new(VaderAnalyzer),
+        "lexicon" => match lexicon_path.map(LexiconAnalyzer::from_file) {
+            Some(Ok(analyzer)) => Arc::new(analyzer),
+            Some(Err(why)) => {
+                error!("Failed to load sentiment lexicon: {}", why);
+                Arc::new(VaderAnalyzer)
+            }
+            None => {
+                error!("The lexicon backend requires a lexicon file.");
+                Arc::new(VaderAnalyzer)
+            }
+        },
+        unknown => {
+            error!("Unknown sentiment backend '{}'.", unknown);
+            Arc::new(VaderAnalyzer)
+        }
+    };
+
+    info!("Using the '{}' sentiment backend.", analyzer.name());
+    analyzer
+}
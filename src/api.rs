@@ -0,0 +1,336 @@
+//! A JSON API over the user profile database for other tools, authenticated
+//! with a bearer token from the config. The routes are described by the
+//! OpenAPI document served at `/openapi.json`.
+
+use crate::bot::BotState;
+use crate::leaderboard::{self, Order};
+use crate::userdb::{ComputedData, ProfileScope, SentimentResult};
+use crate::webhook::{PurgeReason, WebhookEvent};
+use hyper::header::{self, HeaderValue};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::Serialize;
+use serenity::model::prelude::*;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::{error, info};
+
+pub const OPENAPI: &str = include_str!("openapi.json");
+/// The most leaderboard entries returned at once.
+pub const MAX_PER_PAGE: usize = 100;
+
+#[derive(Serialize)]
+struct UserResponse<'a> {
+    guild_id: String,
+    user_id: String,
+    user_tag: &'a str,
+    computed: ComputedData,
+    history: Vec<&'a SentimentResult>,
+}
+
+#[derive(Serialize)]
+struct LeaderboardEntry<'a> {
+    rank: usize,
+    user_id: String,
+    user_tag: &'a str,
+    crime_coefficient: f64,
+}
+
+#[derive(Serialize)]
+struct LeaderboardResponse<'a> {
+    guild_id: String,
+    order: &'static str,
+    page: usize,
+    pages: usize,
+    per_page: usize,
+    excluded: usize,
+    min_samples: usize,
+    entries: Vec<LeaderboardEntry<'a>>,
+}
+
+#[derive(Serialize)]
+struct Deleted {
+    deleted: usize,
+}
+
+#[derive(Serialize)]
+struct ApiError<'a> {
+    error: &'a str,
+}
+
+/// Compares tokens in constant time.
+pub(crate) fn same_token(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Whether the request carries `token` in an `Authorization: Bearer`
+/// header.
+pub(crate) fn has_bearer(token: &str, request: &Request<Body>) -> bool {
+    request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|bearer| {
+            same_token(bearer.trim().as_bytes(), token.as_bytes())
+        })
+}
+
+/// Answers a request to the API.
+pub async fn handle(
+    state: &BotState,
+    token: &str,
+    request: Request<Body>,
+) -> Response<Body> {
+    let path = request.uri().path().to_string();
+    let segments: Vec<&str> = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect();
+    let method = request.method().clone();
+
+    match (&method, segments.as_slice()) {
+        (&Method::GET, ["openapi.json"]) => {
+            let mut response = Response::new(Body::from(OPENAPI));
+            response.headers_mut().insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
+            );
+            response
+        }
+        _ if !has_bearer(token, &request) => {
+            let mut response =
+                error_response(StatusCode::UNAUTHORIZED, "Invalid token");
+            response.headers_mut().insert(
+                header::WWW_AUTHENTICATE,
+                HeaderValue::from_static("Bearer"),
+            );
+            response
+        }
+        (&Method::GET, ["guilds", guild, "users", user]) => {
+            match (parse_id(guild), parse_id(user)) {
+                (Some(guild), Some(user)) => {
+                    get_user(state, GuildId(guild), UserId(user)).await
+                }
+                _ => error_response(StatusCode::BAD_REQUEST, "Invalid id"),
+            }
+        }
+        (&Method::DELETE, ["guilds", guild, "users", user]) => {
+            match (parse_id(guild), parse_id(user)) {
+                (Some(guild), Some(user)) => {
+                    delete_user(state, Some(GuildId(guild)), UserId(user)).await
+                }
+                _ => error_response(StatusCode::BAD_REQUEST, "Invalid id"),
+            }
+        }
+        (&Method::DELETE, ["users", user]) => match parse_id(user) {
+            Some(user) => delete_user(state, None, UserId(user)).await,
+            None => error_response(StatusCode::BAD_REQUEST, "Invalid id"),
+        },
+        (&Method::GET, ["guilds", guild, "leaderboard"]) => {
+            let guild = match parse_id(guild) {
+                Some(guild) => GuildId(guild),
+                None => {
+                    return error_response(
+                        StatusCode::BAD_REQUEST,
+                        "Invalid id",
+                    )
+                }
+            };
+            let order = match query_param(&request, "order") {
+                Some(order) => match Order::parse(&order) {
+                    Some(order) => order,
+                    None => {
+                        return error_response(
+                            StatusCode::BAD_REQUEST,
+                            "The order must be highest or lowest",
+                        )
+                    }
+                },
+                None => Order::Highest,
+            };
+            let page = query_param(&request, "page")
+                .and_then(|page| page.parse().ok())
+                .unwrap_or(1);
+            let per_page = query_param(&request, "per_page")
+                .and_then(|per_page| per_page.parse().ok())
+                .unwrap_or(leaderboard::PAGE_SIZE);
+            get_leaderboard(state, guild, order, page, per_page).await
+        }
+        _ => error_response(StatusCode::NOT_FOUND, "Not found"),
+    }
+}
+
+/// Serves the API at `addr` until the program exits.
+pub async fn serve(addr: SocketAddr, state: Arc<BotState>, token: String) {
+    let token = Arc::new(token);
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+        let token = token.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let state = state.clone();
+                let token = token.clone();
+                async move {
+                    Ok::<_, Infallible>(handle(&state, &token, request).await)
+                }
+            }))
+        }
+    });
+
+    let server = match Server::try_bind(&addr) {
+        Ok(builder) => builder.serve(make_service),
+        Err(why) => {
+            error!("Failed to serve the API on {}: {}", addr, why);
+            return;
+        }
+    };
+    info!("Serving the API on http://{}/", addr);
+    if let Err(why) = server.await {
+        error!("API server error: {}", why);
+    }
+}
+
+async fn get_user(
+    state: &BotState,
+    guild_id: GuildId,
+    user_id: UserId,
+) -> Response<Body> {
+    let scope = ProfileScope::Guild(guild_id);
+    let db = state.database.read().await;
+    let profile = match db.get_user_profile(scope, &user_id) {
+        Some(profile) => profile,
+        None => {
+            return error_response(StatusCode::NOT_FOUND, "No such profile")
+        }
+    };
+
+    json(
+        StatusCode::OK,
+        &UserResponse {
+            guild_id: guild_id.to_string(),
+            user_id: user_id.to_string(),
+            user_tag: profile.user_tag(),
+            computed: profile.get_cymatic_data(&db.cymatic_parameters(scope)),
+            history: profile.history(),
+        },
+    )
+}
+
+async fn get_leaderboard(
+    state: &BotState,
+    guild_id: GuildId,
+    order: Order,
+    page: usize,
+    per_page: usize,
+) -> Response<Body> {
+    let scope = ProfileScope::Guild(guild_id);
+    let db = state.database.read().await;
+    if db.guild_ids().binary_search(&guild_id).is_err() {
+        return error_response(StatusCode::NOT_FOUND, "No such guild");
+    }
+    let ranking = leaderboard::rank(&db, scope, order);
+
+    let per_page = per_page.clamp(1, MAX_PER_PAGE);
+    let pages = ranking.ranked.len().max(1).div_ceil(per_page);
+    let page = page.clamp(1, pages);
+    let entries = ranking
+        .ranked
+        .iter()
+        .enumerate()
+        .skip((page - 1) * per_page)
+        .take(per_page)
+        .map(|(position, entry)| LeaderboardEntry {
+            rank: position + 1,
+            user_id: entry.id.to_string(),
+            user_tag: &entry.tag,
+            crime_coefficient: entry.crime_coefficient,
+        })
+        .collect();
+
+    json(
+        StatusCode::OK,
+        &LeaderboardResponse {
+            guild_id: guild_id.to_string(),
+            order: order.name(),
+            page,
+            pages,
+            per_page,
+            excluded: ranking.excluded,
+            min_samples: ranking.min_samples,
+            entries,
+        },
+    )
+}
+
+/// Deletes the profile of a user in one guild, or every profile of the user
+/// without one.
+async fn delete_user(
+    state: &BotState,
+    guild_id: Option<GuildId>,
+    user_id: UserId,
+) -> Response<Body> {
+    let deleted = {
+        let mut db = state.database.write().await;
+        match guild_id {
+            Some(id) => {
+                db.delete_profile(ProfileScope::Guild(id), &user_id) as usize
+            }
+            None => db.delete_user(&user_id),
+        }
+    };
+    if deleted == 0 {
+        return error_response(StatusCode::NOT_FOUND, "No such profile");
+    }
+    info!(
+        "Deleted {} profile(s) of {} through the API",
+        deleted, user_id
+    );
+    state.webhooks.notify(WebhookEvent::Purged {
+        user_id: user_id.to_string(),
+        guild_id: guild_id.map(|id| id.to_string()),
+        profiles_deleted: deleted,
+        reason: PurgeReason::Api,
+    });
+    json(StatusCode::OK, &Deleted { deleted })
+}
+
+fn json(status: StatusCode, value: &impl Serialize) -> Response<Body> {
+    let body = match serde_json::to_vec(value) {
+        Ok(body) => body,
+        Err(why) => {
+            error!("Failed to serialize an API response: {}", why);
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::empty())
+                .unwrap();
+        }
+    };
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    response
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    json(status, &ApiError { error: message })
+}
+
+fn parse_id(segment: &str) -> Option<u64> {
+    segment.parse().ok()
+}
+
+fn query_param(request: &Request<Body>, name: &str) -> Option<String> {
+    request
+        .uri()
+        .query()?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.to_string())
+}
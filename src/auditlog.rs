@@ -0,0 +1,141 @@
+use crate::transport::{Embed, IncomingMessage, OutgoingMessage, Transport};
+use crate::userdb::ComputedData;
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+use tracing::error;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AuditLogSettings {
+    /// The channel the crossings are posted in, disabled if unset.
+    pub channel: Option<ChannelId>,
+    /// Crime coefficients that are reported when crossed in either direction.
+    pub boundaries: Vec<f64>,
+}
+
+impl Default for AuditLogSettings {
+    fn default() -> AuditLogSettings {
+        AuditLogSettings {
+            channel: None,
+            // Latent criminal and lethal eliminator thresholds.
+            boundaries: vec![100.0, 300.0],
+        }
+    }
+}
+
+/// A crime coefficient crossing one of the boundaries of a guild.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Crossing {
+    pub guild_id: GuildId,
+    pub user_id: UserId,
+    pub user_tag: String,
+    pub boundary: f64,
+    pub old: f64,
+    pub new: f64,
+    /// An RFC 3339 timestamp of the message that caused it.
+    pub timestamp: String,
+    pub link: String,
+}
+
+impl AuditLogSettings {
+    /// The boundary closest to `new` that lies between the two coefficients.
+    pub fn crossed_boundary(&self, old: f64, new: f64) -> Option<f64> {
+        let (low, high) = if old <= new { (old, new) } else { (new, old) };
+        let crossed = self
+            .boundaries
+            .iter()
+            .copied()
+            .filter(|boundary| low < *boundary && *boundary <= high);
+
+        let order = |a: &f64, b: &f64| {
+            a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal)
+        };
+        if new >= old {
+            crossed.max_by(order)
+        } else {
+            crossed.min_by(order)
+        }
+    }
+}
+
+/// Posts an embed in the audit log channel of the guild if the crime
+/// coefficient of the author of `msg` crossed one of its boundaries.
+pub async fn report_crossing(
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    settings: &AuditLogSettings,
+    old: &ComputedData,
+    new: &ComputedData,
+) {
+    let channel = match settings.channel {
+        Some(channel) => channel,
+        None => return,
+    };
+    let boundary = match settings
+        .crossed_boundary(old.crime_coefficient, new.crime_coefficient)
+    {
+        Some(boundary) => boundary,
+        None => return,
+    };
+
+    let direction = if new.crime_coefficient >= old.crime_coefficient {
+        "rose above"
+    } else {
+        "fell below"
+    };
+    let embed = Embed {
+        title: Some("Crime Coefficient Alert".to_string()),
+        description: Some(format!(
+            "{} {} {:.1}",
+            msg.author.mention(),
+            direction,
+            boundary
+        )),
+        colour: Some(new.hue.color),
+        fields: vec![
+            ("User".to_string(), msg.author.tag(), true),
+            (
+                "Old Coefficient".to_string(),
+                format!("{:.1}", old.crime_coefficient),
+                true,
+            ),
+            (
+                "New Coefficient".to_string(),
+                format!("{:.1}", new.crime_coefficient),
+                true,
+            ),
+            ("Message".to_string(), truncate(&msg.content, 1024), false),
+            (
+                "Jump".to_string(),
+                format!("[Go to message]({})", msg.link()),
+                false,
+            ),
+        ],
+        timestamp: Some(msg.timestamp.clone()),
+        ..Embed::default()
+    };
+
+    let message = OutgoingMessage {
+        embed: Some(embed),
+        ..OutgoingMessage::default()
+    };
+    if let Err(why) = transport.send(channel, message).await {
+        error!(
+            "Failed to post to the audit log channel {}: {}",
+            channel, why
+        );
+    }
+}
+
+/// Shortens `text` to fit in an embed field.
+fn truncate(text: &str, max_chars: usize) -> String {
+    if text.is_empty() {
+        return "*(no text)*".to_string();
+    }
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    let mut truncated: String = text.chars().take(max_chars - 1).collect();
+    truncated.push('…');
+    truncated
+}
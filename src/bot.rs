@@ -0,0 +1,329 @@
+use crate::analyzer::SentimentAnalyzer;
+use crate::auditlog::{self, Crossing};
+use crate::config::RetentionConfig;
+use crate::dominator::{self, Dominator};
+use crate::metrics::Metrics;
+use crate::scope::{ChannelInfo, ScopeSettings};
+use crate::transport::{IncomingMessage, Transport};
+use crate::userdb::{ProfileScope, RetentionSummary, UserProfilesDatabase};
+use crate::webhook::{PurgeReason, WebhookEvent, Webhooks};
+use serenity::model::prelude::{ChannelId, GuildId, MessageId, UserId};
+use serenity::prelude::{Mutex, TypeMapKey};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::RwLock;
+use tracing::{error, info};
+
+pub type DatabaseLock = Arc<RwLock<UserProfilesDatabase>>;
+
+/// How many boundary crossings are kept for the dashboard.
+const RECENT_CROSSINGS: usize = 100;
+
+/// The state shared by the event handler and the commands.
+pub struct BotState {
+    pub database: DatabaseLock,
+    pub analyzer: Arc<dyn SentimentAnalyzer>,
+    pub dominator: Mutex<Dominator>,
+    pub metrics: Metrics,
+    pub webhooks: Arc<Webhooks>,
+    /// The latest boundary crossings of every guild, newest last.
+    crossings: Mutex<VecDeque<Crossing>>,
+
+    /// The channels looked up for scoping, until they change.
+    channels: RwLock<HashMap<ChannelId, ChannelInfo>>,
+}
+
+pub struct BotStateContainer;
+impl TypeMapKey for BotStateContainer {
+    type Value = Arc<BotState>;
+}
+
+impl BotState {
+    pub fn new(
+        database: DatabaseLock,
+        analyzer: Arc<dyn SentimentAnalyzer>,
+    ) -> BotState {
+        BotState {
+            database,
+            analyzer,
+            dominator: Mutex::new(Dominator::default()),
+            metrics: Metrics::default(),
+            webhooks: Arc::new(Webhooks::default()),
+            crossings: Mutex::new(VecDeque::new()),
+
+            channels: RwLock::new(HashMap::default()),
+        }
+    }
+
+    pub fn with_webhooks(mut self, webhooks: Webhooks) -> BotState {
+        self.webhooks = Arc::new(webhooks);
+        self
+    }
+
+    /// Applies the retention policy, notifying the webhooks of the inactive
+    /// profiles it deleted.
+    pub async fn apply_retention(
+        &self,
+        retention: &RetentionConfig,
+        now: u64,
+    ) -> RetentionSummary {
+        let summary =
+            self.database.write().await.apply_retention(retention, now);
+        for (scope, user_id) in summary.inactive_profiles.iter() {
+            self.webhooks.notify(WebhookEvent::Purged {
+                user_id: user_id.to_string(),
+                guild_id: scope.guild_id().map(|id| id.to_string()),
+                profiles_deleted: 1,
+                reason: PurgeReason::Inactive,
+            });
+        }
+        summary
+    }
+
+    /// Deletes the profile of a member who left a guild, returning whether
+    /// there was one.
+    pub async fn remove_departed_member(
+        &self,
+        guild_id: GuildId,
+        user_id: UserId,
+    ) -> bool {
+        let mut database = self.database.write().await;
+        let removed = database.remove_departed_member(guild_id, user_id);
+        drop(database);
+        if removed {
+            self.webhooks.notify(WebhookEvent::Purged {
+                user_id: user_id.to_string(),
+                guild_id: Some(guild_id.to_string()),
+                profiles_deleted: 1,
+                reason: PurgeReason::Departed,
+            });
+        }
+        removed
+    }
+
+    /// Drops the cached information about a channel that was changed or
+    /// deleted.
+    pub async fn forget_channel(&self, channel_id: ChannelId) {
+        self.channels.write().await.remove(&channel_id);
+    }
+
+    async fn channel_info(
+        &self,
+        transport: &dyn Transport,
+        channel_id: ChannelId,
+    ) -> Option<ChannelInfo> {
+        if let Some(info) = self.channels.read().await.get(&channel_id) {
+            return Some(*info);
+        }
+        match transport.channel_info(channel_id).await {
+            Ok(info) => {
+                self.channels.write().await.insert(channel_id, info);
+                Some(info)
+            }
+            Err(why) => {
+                error!("Failed to look up channel {}: {}", channel_id, why);
+                None
+            }
+        }
+    }
+
+    /// Whether the guild's scope settings allow analyzing `msg`.
+    async fn in_scope(
+        &self,
+        transport: &dyn Transport,
+        msg: &IncomingMessage,
+        scope: &ScopeSettings,
+    ) -> bool {
+        let info = if scope.needs_channel_info() {
+            // Skip the message rather than guess whether it is allowed.
+            match self.channel_info(transport, msg.channel_id).await {
+                Some(info) => Some(info),
+                None => return false,
+            }
+        } else {
+            None
+        };
+        scope.allows(msg.channel_id, info, &msg.roles)
+    }
+
+    pub async fn count_command(
+        &self,
+        guild_id: Option<GuildId>,
+        command_name: &str,
+    ) {
+        let scope = ProfileScope::from_guild(guild_id);
+        self.metrics.count_command(command_name);
+        let mut database = self.database.write().await;
+        database.count_command(scope, command_name);
+    }
+
+    /// The latest boundary crossings, newest first, of one guild or of every
+    /// guild.
+    pub async fn recent_crossings(
+        &self,
+        guild_id: Option<GuildId>,
+    ) -> Vec<Crossing> {
+        let crossings = self.crossings.lock().await;
+        crossings
+            .iter()
+            .rev()
+            .filter(|c| guild_id.is_none_or(|id| c.guild_id == id))
+            .cloned()
+            .collect()
+    }
+
+    async fn record_crossing(&self, crossing: Crossing) {
+        let mut crossings = self.crossings.lock().await;
+        if crossings.len() == RECENT_CROSSINGS {
+            crossings.pop_front();
+        }
+        crossings.push_back(crossing);
+    }
+
+    /// Re-scores a recorded message after its author edited it to
+    /// `content`.
+    pub async fn record_edit(
+        &self,
+        guild_id: Option<GuildId>,
+        message_id: MessageId,
+        content: &str,
+    ) {
+        let scope = ProfileScope::from_guild(guild_id);
+        let sentiment_result = self.analyzer.analyze(content);
+        let mut database = self.database.write().await;
+        if let Some(user_id) =
+            database.rescore_message(scope, message_id, &sentiment_result)
+        {
+            info!("Re-scored edited message {} of {}", message_id, user_id);
+        }
+    }
+
+    /// Forgets the sentiment of deleted messages.
+    pub async fn record_deletions(
+        &self,
+        guild_id: Option<GuildId>,
+        message_ids: &[MessageId],
+    ) {
+        let scope = ProfileScope::from_guild(guild_id);
+        let mut database = self.database.write().await;
+        let removed = database.remove_messages(scope, message_ids);
+        if removed > 0 {
+            info!("Removed the sentiment of {} deleted message(s)", removed);
+        }
+    }
+
+    /// Records the sentiment of a message that is not a command, then runs
+    /// the audit log and enforcement for its author.
+    pub async fn record_message(
+        &self,
+        transport: &dyn Transport,
+        msg: &IncomingMessage,
+    ) {
+        if msg.author.bot {
+            return;
+        }
+
+        let scope = ProfileScope::from_guild(msg.guild_id);
+        let (prefix, opted_out, scope_settings) = {
+            let database = self.database.read().await;
+            (
+                database.guild_config(scope).prefix,
+                database.is_opted_out(&msg.author.id),
+                msg.guild_id.map(|id| database.guild_settings(id).scope),
+            )
+        };
+        // Opted out users are never analyzed.
+        if opted_out || msg.content.starts_with(&prefix) {
+            return;
+        }
+        if let Some(scope_settings) = scope_settings {
+            if !self.in_scope(transport, msg, &scope_settings).await {
+                return;
+            }
+        }
+
+        let started = Instant::now();
+        let sentiment_result =
+            self.analyzer.analyze(&msg.content).for_message(msg.id);
+        self.metrics.analysis_latency.observe(started.elapsed());
+        let (old_cdata, cdata, settings) = {
+            let mut database = self.database.write().await;
+            let parameters = database.cymatic_parameters(scope);
+            let old_cdata = database
+                .get_user_profile(scope, &msg.author.id)
+                .map(|profile| profile.get_cymatic_data(&parameters));
+
+            database.add_sentiment_result_for_user(
+                scope,
+                &msg.author,
+                sentiment_result,
+            );
+            database.count_message(scope);
+
+            let cdata = database
+                .get_user_profile(scope, &msg.author.id)
+                .map(|profile| profile.get_cymatic_data(&parameters));
+            let settings = msg.guild_id.map(|id| database.guild_settings(id));
+            (old_cdata, cdata, settings)
+        };
+
+        // Nobody is reported or enforced on before there are enough
+        // messages to judge them by, and crossings need enough on both
+        // sides.
+        let old_cdata = old_cdata.filter(|cdata| !cdata.insufficient_data);
+        let cdata = cdata.filter(|cdata| !cdata.insufficient_data);
+        if let (Some(guild_id), Some(cdata), Some(settings)) =
+            (msg.guild_id, cdata, settings)
+        {
+            if let Some(old_cdata) = old_cdata {
+                if let Some(boundary) = settings.audit_log.crossed_boundary(
+                    old_cdata.crime_coefficient,
+                    cdata.crime_coefficient,
+                ) {
+                    let crossing = Crossing {
+                        guild_id,
+                        user_id: msg.author.id,
+                        user_tag: msg.author.tag(),
+                        boundary,
+                        old: old_cdata.crime_coefficient,
+                        new: cdata.crime_coefficient,
+                        timestamp: msg.timestamp.clone(),
+                        link: msg.link(),
+                    };
+                    self.webhooks.notify(WebhookEvent::ThresholdCrossed {
+                        guild_id: guild_id.to_string(),
+                        user_id: msg.author.id.to_string(),
+                        user_tag: crossing.user_tag.clone(),
+                        boundary,
+                        old: crossing.old,
+                        new: crossing.new,
+                        message_link: crossing.link.clone(),
+                    });
+                    self.record_crossing(crossing).await;
+                }
+                auditlog::report_crossing(
+                    transport,
+                    msg,
+                    &settings.audit_log,
+                    &old_cdata,
+                    &cdata,
+                )
+                .await;
+            }
+            dominator::enforce(
+                self,
+                transport,
+                msg,
+                guild_id,
+                &settings.enforcement,
+                cdata.crime_coefficient,
+            )
+            .await;
+        }
+        self.metrics
+            .messages_analyzed
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        info!("Recorded message sentiment for {}", msg.author.tag());
+    }
+}
@@ -0,0 +1,173 @@
+//! PNG charts of the sentiment history of a profile.
+
+use crate::transport::Attachment;
+use crate::userdb::{HueState, SentimentResult};
+use image::ImageEncoder;
+use plotters::prelude::*;
+use plotters::style::{register_font, FontStyle};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, UNIX_EPOCH};
+
+pub const FILENAME: &str = "chart.png";
+const WIDTH: u32 = 800;
+const HEIGHT: u32 = 400;
+const FONT: &str = "sans-serif";
+/// Where common systems keep a font charts can use when none is configured.
+pub const DEFAULT_FONTS: &[&str] = &[
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/truetype/liberation/LiberationSans-Regular.ttf",
+    "/Library/Fonts/Arial.ttf",
+    "C:\\Windows\\Fonts\\arial.ttf",
+];
+/// Added to replies with a chart drawn without a font.
+pub const MISSING_FONT: &str =
+    "The chart has no labels, set a font in the [chart] section of the config.";
+
+// Text can only be drawn once a font has been loaded.
+static FONT_LOADED: AtomicBool = AtomicBool::new(false);
+
+/// Whether charts are drawn with a title and labels.
+pub fn has_labels() -> bool {
+    FONT_LOADED.load(Ordering::SeqCst)
+}
+
+/// Loads the TrueType font at `path` for the titles and labels of charts.
+pub fn load_font(path: &str) -> Result<(), String> {
+    let bytes = std::fs::read(path)
+        .map_err(|why| format!("Failed to read font '{}': {}", path, why))?;
+    // Fonts stay registered for the rest of the program.
+    register_font(FONT, FontStyle::Normal, Box::leak(bytes.into_boxed_slice()))
+        .map_err(|_| format!("'{}' is not a valid font", path))?;
+    FONT_LOADED.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Loads the first of `DEFAULT_FONTS` that is a valid font, returning its
+/// path.
+pub fn load_default_font() -> Option<&'static str> {
+    DEFAULT_FONTS
+        .iter()
+        .copied()
+        .find(|path| Path::new(path).exists() && load_font(path).is_ok())
+}
+
+/// Formats a unix timestamp on the time axis.
+fn time_label(timestamp: &f64) -> String {
+    let time = UNIX_EPOCH + Duration::from_secs(timestamp.max(0.0) as u64);
+    chrono::DateTime::<chrono::Utc>::from(time)
+        .format("%Y-%m-%d %H:%M")
+        .to_string()
+}
+
+/// A line of the chart: its name, colour and the score it draws.
+type Series = (&'static str, RGBColor, fn(&SentimentResult) -> f64);
+
+fn chart_error(why: impl std::fmt::Display) -> String {
+    format!("Failed to draw the chart: {}", why)
+}
+
+/// Draws the sentiment scores of a history ordered from oldest to newest
+/// against the time of each result, over bands in the colours of the hues,
+/// from crystal at a compound score of 1.0 to turbid at -1.0.
+pub fn render(
+    title: &str,
+    history: &[&SentimentResult],
+) -> Result<Attachment, String> {
+    if history.is_empty() {
+        return Err("There is no history to chart".to_string());
+    }
+    let labels = has_labels();
+    let times = history.iter().map(|sentiment| sentiment.timestamp());
+    let first = times.clone().min().unwrap_or(0);
+    // Results recorded at the same time still need a range to draw over.
+    let last = times.max().unwrap_or(0).max(first + 1);
+    let (first, last) = (first as f64, last as f64);
+
+    let mut buffer = vec![0u8; (WIDTH * HEIGHT * 3) as usize];
+    {
+        let root = BitMapBackend::with_buffer(&mut buffer, (WIDTH, HEIGHT))
+            .into_drawing_area();
+        root.fill(&WHITE).map_err(chart_error)?;
+
+        let mut builder = ChartBuilder::on(&root);
+        builder.margin(15);
+        if labels {
+            builder
+                .caption(title, (FONT, 20))
+                .x_label_area_size(35)
+                .y_label_area_size(45);
+        }
+        let mut chart = builder
+            .build_cartesian_2d(first..last, -1f64..1f64)
+            .map_err(chart_error)?;
+
+        let band = 2.0 / HueState::ALL.len() as f64;
+        for (index, state) in HueState::ALL.iter().enumerate() {
+            let top = 1.0 - index as f64 * band;
+            let (red, green, blue) = state.color();
+            let colour = RGBColor(red, green, blue).mix(0.35).filled();
+            chart
+                .draw_series(std::iter::once(Rectangle::new(
+                    [(first, top), (last, top - band)],
+                    colour,
+                )))
+                .map_err(chart_error)?;
+        }
+
+        let mut mesh = chart.configure_mesh();
+        mesh.disable_mesh();
+        if labels {
+            mesh.x_desc("Time (UTC)")
+                .y_desc("Score")
+                .x_labels(5)
+                .x_label_formatter(&time_label);
+        } else {
+            mesh.x_labels(0).y_labels(0);
+        }
+        mesh.draw().map_err(chart_error)?;
+
+        let series: [Series; 4] = [
+            ("positive", GREEN, SentimentResult::positive),
+            ("neutral", RGBColor(128, 128, 128), SentimentResult::neutral),
+            ("negative", RED, SentimentResult::negative),
+            ("compound", BLACK, SentimentResult::compound),
+        ];
+        for (name, colour, score) in series.iter() {
+            let points = history.iter().map(|sentiment| {
+                (sentiment.timestamp() as f64, score(sentiment))
+            });
+            let width = if *name == "compound" { 3 } else { 2 };
+            let style = colour.stroke_width(width);
+            chart
+                .draw_series(LineSeries::new(points, style))
+                .map_err(chart_error)?
+                .label(*name)
+                .legend(move |(x, y)| {
+                    PathElement::new(vec![(x, y), (x + 20, y)], style)
+                });
+        }
+
+        if labels {
+            chart
+                .configure_series_labels()
+                .label_font((FONT, 14))
+                .background_style(WHITE.mix(0.8))
+                .border_style(BLACK)
+                .draw()
+                .map_err(chart_error)?;
+        }
+        root.present().map_err(chart_error)?;
+    }
+
+    let mut data = Vec::new();
+    image::codecs::png::PngEncoder::new(&mut data)
+        .write_image(&buffer, WIDTH, HEIGHT, image::ColorType::Rgb8)
+        .map_err(chart_error)?;
+    Ok(Attachment {
+        filename: FILENAME.to_string(),
+        data,
+    })
+}
@@ -1,278 +1,1469 @@
-use crate::userdb::*;
-use crate::{
-    CommandCounter, MessageCount, ShardManagerContainer, UserDatabase,
+//! The commands of the bot, independent of how they are invoked.
+
+use crate::bot::BotState;
+use crate::chart;
+use crate::config::GuildSettings;
+use crate::dominator::{EnforcementAction, EnforcementTier};
+use crate::history;
+use crate::leaderboard::{self, Order};
+use crate::scope::{ScopeSettings, ScopeTarget};
+use crate::target::{implicit_target, resolve_target, Target};
+use crate::transport::{
+    Attachment, Embed, IncomingMessage, OutgoingMessage, Transport,
+    TransportResult,
 };
-use serenity::framework::standard::{macros::command, CommandResult};
+use crate::usage;
+use crate::userdb::*;
+use crate::webhook::{PurgeReason, WebhookEvent};
+use serenity::framework::standard::{Args, CommandResult};
 use serenity::model::prelude::*;
 use serenity::utils::MessageBuilder;
-use serenity::{framework::standard::Args, prelude::*};
 
-#[command]
-async fn analyze(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+pub async fn analyze(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    args: Args,
+) -> CommandResult {
     let content = args.rest();
     let sentiment_result = MessageBuilder::new()
         .push_bold_line("Sentiment Analysis")
         .push_codeblock(
-            analyze_message(content).to_formatted_json(),
+            state.analyzer.analyze(content).to_formatted_json(),
             Some("json"),
         )
         .build();
 
-    msg.channel_id
-        .send_message(&ctx.http, |m| {
-            m.content(sentiment_result);
-            m.tts(false);
-            m.embed(|e| {
-                e.title(msg.author.tag());
-                e.description(content);
-                e
-            });
-            m
-        })
-        .await?;
+    let message = OutgoingMessage {
+        content: sentiment_result,
+        embed: Some(Embed {
+            title: Some(msg.author.tag()),
+            description: Some(content.to_string()),
+            ..Embed::default()
+        }),
+        ..OutgoingMessage::default()
+    };
+    transport.send(msg.channel_id, message).await?;
 
     Ok(())
 }
 
-#[command]
-#[aliases("pp", "analyze_user", "scan")]
-async fn psycho_pass(
-    ctx: &Context,
-    msg: &Message,
+/// Splits the arguments into an optional user query and the `known` options
+/// present, or returns the first unknown option.
+fn parse_options(
     mut args: Args,
+    known: &[&'static str],
+) -> Result<(Option<String>, Vec<&'static str>), String> {
+    let mut query = None;
+    let mut options = Vec::new();
+    while let Ok(arg) = args.single_quoted::<String>() {
+        match known.iter().find(|option| **option == arg) {
+            Some(option) => options.push(*option),
+            None if query.is_none() && options.is_empty() => query = Some(arg),
+            None => return Err(arg),
+        }
+    }
+    Ok((query, options))
+}
+
+/// Resolves the user `query` refers to, or the implicit target without a
+/// query. Replies and returns `None` if there is no single match.
+async fn find_target(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    query: Option<String>,
+) -> TransportResult<Option<UserId>> {
+    let query = match query {
+        Some(query) => query,
+        None => return Ok(Some(implicit_target(msg))),
+    };
+
+    match resolve_target(&state.database, transport, msg, &query).await {
+        Target::Found(id) => Ok(Some(id)),
+        Target::Ambiguous(candidates) => {
+            let mut reply = MessageBuilder::new();
+            reply.push_line(format!(
+                "{} matches several users, did you mean:",
+                query
+            ));
+            for (id, tag) in candidates {
+                reply.push_line(format!("{} ({})", tag, id));
+            }
+            transport.reply(msg, reply).await?;
+            Ok(None)
+        }
+        Target::NotFound => {
+            let reply = format!("Failed to find user {}", query);
+            transport.reply(msg, reply).await?;
+            Ok(None)
+        }
+    }
+}
+
+pub async fn psycho_pass(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    args: Args,
 ) -> CommandResult {
-    let user_name = match args.single_quoted::<String>() {
-        Ok(x) => x,
-        Err(_) => {
-            msg.reply(ctx, "User name is required for analysis.")
-                .await?;
+    let (query, options) = match parse_options(args, &["global", "--chart"]) {
+        Ok(parsed) => parsed,
+        Err(option) => {
+            let reply = format!("Unknown option {}", &option);
+            transport.reply(msg, reply).await?;
             return Ok(());
         }
     };
+    let global = options.contains(&"global");
 
-    let user_id = match user_name.parse::<UserId>() {
-        Ok(id) => id,
-        Err(_) => {
-            let reply = format!("Failed to parse user name {}", &user_name);
-            msg.reply(ctx, reply).await?;
+    let db_lock = &state.database;
+    let user_id = match find_target(state, transport, msg, query).await? {
+        Some(id) => id,
+        None => return Ok(()),
+    };
+
+    let (user_name, cdata, history) = {
+        let db = db_lock.read().await;
+        let scope = ProfileScope::from_guild(msg.guild_id);
+        let parameters = db.cymatic_parameters(scope);
+        let profile = if global {
+            db.get_global_profile(&user_id)
+        } else {
+            db.get_user_profile(scope, &user_id).cloned()
+        };
+
+        if let Some(profile) = profile {
+            (
+                profile.user_tag().to_string(),
+                profile.get_cymatic_data(&parameters),
+                profile.history().into_iter().cloned().collect::<Vec<_>>(),
+            )
+        } else {
+            let reply = format!("No data recorded for {}", user_id.mention());
+            transport.reply(msg, reply).await?;
             return Ok(());
         }
     };
 
-    let data = ctx.data.read().await;
-    let db_lock = data
-        .get::<UserDatabase>()
-        .expect("Expected UserDatabase in TypeMap.")
-        .clone();
+    let (low, high) = cdata.interval;
+    let (reply, coefficient) = if cdata.insufficient_data {
+        (
+            format!(
+                "{} has too little data for a crime coefficient",
+                &user_name
+            ),
+            "Insufficient data".to_string(),
+        )
+    } else {
+        (
+            format!(
+                "{} has a crime coefficient of: {:.1}",
+                &user_name, cdata.crime_coefficient
+            ),
+            format!("{:.1}", cdata.crime_coefficient),
+        )
+    };
+    let hue = cdata.hue;
+    let title = if global {
+        "Psycho-Pass (all servers)"
+    } else {
+        "Psycho-Pass"
+    };
 
-    let cdata = {
-        let db = db_lock.read().await;
-        if let Some(profile) = db.get_user_profile(&user_id) {
-            profile.get_cymatic_data()
+    let mut message = OutgoingMessage::reply(msg, reply);
+    let mut image = None;
+    if options.contains(&"--chart") {
+        let history: Vec<&SentimentResult> = history.iter().collect();
+        match chart::render(&user_name, &history) {
+            Ok(attachment) => {
+                image = Some(attachment.filename.clone());
+                message.attachments.push(attachment);
+                if !chart::has_labels() {
+                    message.content =
+                        format!("{}\n{}", message.content, chart::MISSING_FONT);
+                }
+            }
+            Err(why) => transport.reply(msg, why).await?,
+        }
+    }
+    message.embed = Some(Embed {
+        title: Some(title.to_string()),
+        image,
+        colour: Some(hue.color),
+        fields: vec![
+            ("Crime Coefficient".to_string(), coefficient, true),
+            (
+                "Hue".to_string(),
+                format!("{} ({})", hue.state.name(), hue.hex_string()),
+                true,
+            ),
+            (
+                "Clouded".to_string(),
+                format!(
+                    "{:.0}% ({})",
+                    hue.clouded * 100.0,
+                    hue.trend_description()
+                ),
+                true,
+            ),
+            (
+                "Confidence".to_string(),
+                format!(
+                    "95% between {:.1} and {:.1}, from {} message(s)",
+                    low, high, cdata.samples
+                ),
+                false,
+            ),
+        ],
+        ..Embed::default()
+    });
+    transport.send(msg.channel_id, message).await?;
+
+    Ok(())
+}
+
+pub async fn chart(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    args: Args,
+) -> CommandResult {
+    let (query, options) = match parse_options(args, &["global"]) {
+        Ok(parsed) => parsed,
+        Err(option) => {
+            let reply = format!("Unknown option {}", &option);
+            transport.reply(msg, reply).await?;
+            return Ok(());
+        }
+    };
+    let user_id = match find_target(state, transport, msg, query).await? {
+        Some(id) => id,
+        None => return Ok(()),
+    };
+
+    let rendered = {
+        let db = state.database.read().await;
+        let profile = if options.contains(&"global") {
+            db.get_global_profile(&user_id)
         } else {
-            let reply = format!("Failed to find user {}", &user_name);
-            msg.reply(ctx, reply).await?;
+            let scope = ProfileScope::from_guild(msg.guild_id);
+            db.get_user_profile(scope, &user_id).cloned()
+        };
+        profile.map(|profile| {
+            let title = profile.user_tag().to_string();
+            (title.clone(), chart::render(&title, &profile.history()))
+        })
+    };
+
+    match rendered {
+        Some((user_name, Ok(attachment))) => {
+            let mut reply = format!("Sentiment history of {}", user_name);
+            if !chart::has_labels() {
+                reply = format!("{}\n{}", reply, chart::MISSING_FONT);
+            }
+            let mut message = OutgoingMessage::reply(msg, reply);
+            message.attachments.push(attachment);
+            transport.send(msg.channel_id, message).await?;
+        }
+        Some((_, Err(why))) => transport.reply(msg, why).await?,
+        None => {
+            let reply = format!("No data recorded for {}", user_id.mention());
+            transport.reply(msg, reply).await?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn history(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    mut args: Args,
+) -> CommandResult {
+    let mut query = args.single_quoted::<String>().ok();
+    let mut days = history::DEFAULT_PERIOD_DAYS;
+    if let Some(period) = args.single::<String>().ok().or_else(|| {
+        // A lone period applies to the implicit target.
+        let lone = query.as_deref().and_then(history::parse_period);
+        lone.and_then(|_| query.take())
+    }) {
+        match history::parse_period(&period) {
+            Some(parsed) => days = parsed,
+            None => {
+                let reply = format!("Unknown period {}", &period);
+                transport.reply(msg, reply).await?;
+                return Ok(());
+            }
+        }
+    }
+
+    let user_id = match find_target(state, transport, msg, query).await? {
+        Some(id) => id,
+        None => return Ok(()),
+    };
+
+    let (user_name, snapshots) = {
+        let db = state.database.read().await;
+        let scope = ProfileScope::from_guild(msg.guild_id);
+        match db.get_user_profile(scope, &user_id) {
+            Some(profile) => {
+                (profile.user_tag().to_string(), profile.snapshots().to_vec())
+            }
+            None => {
+                let reply =
+                    format!("No data recorded for {}", user_id.mention());
+                transport.reply(msg, reply).await?;
+                return Ok(());
+            }
+        }
+    };
+
+    let today = current_day();
+    let in_period = history::in_period(&snapshots, today, days);
+    let (first, latest) = match (in_period.first(), in_period.last()) {
+        (Some(first), Some(latest)) => (first, latest),
+        _ => {
+            let reply = format!(
+                "{} has no coefficient history in the last {} day(s)",
+                &user_name, days
+            );
+            transport.reply(msg, reply).await?;
+            return Ok(());
+        }
+    };
+    let coefficients = in_period.iter().map(|s| s.crime_coefficient);
+    let lowest = coefficients.clone().fold(f64::INFINITY, f64::min);
+    let highest = coefficients.fold(f64::NEG_INFINITY, f64::max);
+
+    let mut content = MessageBuilder::new();
+    content.push_bold_line(format!("{}, last {} day(s)", &user_name, days));
+    content
+        .push_codeblock(history::sparkline(&in_period, today, days), None)
+        .push_line("");
+    content.push_line(format!(
+        "From {:.1} to {:.1}, lowest {:.1}, highest {:.1}",
+        first.crime_coefficient, latest.crime_coefficient, lowest, highest
+    ));
+    match history::week_delta(&snapshots) {
+        Some(delta) => {
+            content.push_line(format!("Since last week: {:+.1}", delta))
+        }
+        None => content.push_line("Since last week: not enough history"),
+    };
+
+    transport.reply(msg, content).await?;
+    Ok(())
+}
+
+pub async fn leaderboard(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    args: Args,
+) -> CommandResult {
+    let mut order = Order::Highest;
+    let mut page = 1;
+    for arg in args.raw() {
+        if let Some(parsed) = Order::parse(arg) {
+            order = parsed;
+        } else if let Ok(parsed) = arg.parse::<usize>() {
+            page = parsed;
+        } else {
+            let reply = format!("Unknown option {}", arg);
+            transport.reply(msg, reply).await?;
+            return Ok(());
+        }
+    }
+
+    let guild_id = msg.guild_id.expect("only_in(guilds)");
+    let ranking = {
+        let db = state.database.read().await;
+        leaderboard::rank(&db, ProfileScope::Guild(guild_id), order)
+    };
+
+    let pages = leaderboard::page_count(ranking.ranked.len());
+    if page == 0 || page > pages {
+        let reply = format!("There are only {} page(s)", pages);
+        transport.reply(msg, reply).await?;
+        return Ok(());
+    }
+
+    let mut message = leaderboard::leaderboard_page(&ranking, order, page);
+    message.reply_to = Some(msg.id);
+    transport.send(msg.channel_id, message).await?;
+    Ok(())
+}
+
+/// Renders `page` of the leaderboard of `guild_id`, for the page buttons.
+pub async fn leaderboard_page(
+    state: &BotState,
+    guild_id: GuildId,
+    order: Order,
+    page: usize,
+) -> OutgoingMessage {
+    let db = state.database.read().await;
+    let ranking = leaderboard::rank(&db, ProfileScope::Guild(guild_id), order);
+    leaderboard::leaderboard_page(&ranking, order, page)
+}
+
+pub async fn distribution(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    _args: Args,
+) -> CommandResult {
+    let guild_id = msg.guild_id.expect("only_in(guilds)");
+    let ranking = {
+        let db = state.database.read().await;
+        leaderboard::rank(&db, ProfileScope::Guild(guild_id), Order::Lowest)
+    };
+
+    let coefficients: Vec<f64> = ranking
+        .ranked
+        .iter()
+        .map(|entry| entry.crime_coefficient)
+        .collect();
+    let mut content = MessageBuilder::new();
+    content.push_bold_line(format!(
+        "Crime coefficients of {} user(s)",
+        coefficients.len()
+    ));
+    content
+        .push_codeblock(leaderboard::histogram(&coefficients), None)
+        .push_line("");
+    if ranking.excluded > 0 {
+        content.push_line(format!(
+            "{} user(s) with fewer than {} messages left out",
+            ranking.excluded, ranking.min_samples
+        ));
+    }
+
+    transport.reply(msg, content).await?;
+    Ok(())
+}
+
+pub async fn stats(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    args: Args,
+) -> CommandResult {
+    let global = match args.current() {
+        None => msg.guild_id.is_none(),
+        Some("global") => true,
+        Some(option) => {
+            let reply = format!("Unknown option {}", option);
+            transport.reply(msg, reply).await?;
             return Ok(());
         }
     };
+    let guild_id = if global { None } else { msg.guild_id };
+    let summary = state
+        .database
+        .read()
+        .await
+        .usage()
+        .summary(guild_id, current_day());
+
+    let mut content = MessageBuilder::new();
+    content.push_line(format!(
+        "System has analyzed {} user message(s){}",
+        summary.total.messages,
+        if global { "" } else { " in this guild" }
+    ));
+    content.push_line(format!(
+        "Today: {} message(s), {} command(s)",
+        summary.today.messages,
+        summary.today.command_total()
+    ));
+
+    let mut week = format!("{:<12}{:>9}{:>9}\n", "Day", "Messages", "Commands");
+    for (day, usage) in summary.week.iter() {
+        week.push_str(&format!(
+            "{:<12}{:>9}{:>9}\n",
+            usage::day_name(*day),
+            usage.messages,
+            usage.command_total()
+        ));
+    }
+    content.push_line(format!("Last {} days:", usage::WEEK_DAYS));
+    content.push_codeblock(week.trim_end(), None);
+    content.push_line("");
+
+    content.push_line("Command usage counts:");
+    for (command, count) in summary.total.commands.iter() {
+        let today = summary.today.commands.get(command).unwrap_or(&0);
+        content
+            .push_line(format!("__{}__: {} ({} today)", command, count, today));
+    }
+
+    transport.reply(msg, content).await?;
+    Ok(())
+}
+
+pub async fn msg_count(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+) -> CommandResult {
+    let count = state
+        .database
+        .read()
+        .await
+        .usage()
+        .summary(None, current_day())
+        .total
+        .messages;
+
+    transport
+        .reply(
+            msg,
+            format!("System has analyzed {} user message(s)", count),
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn optout(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    _args: Args,
+) -> CommandResult {
+    let deleted = state.database.write().await.opt_out(msg.author.id);
+    state.webhooks.notify(WebhookEvent::OptedOut {
+        user_id: msg.author.id.to_string(),
+        profiles_deleted: deleted,
+    });
 
     let reply = format!(
-        "{} has a crime coefficient of: {:.1}",
-        &user_name, cdata.crime_coefficient
+        "Your messages are no longer analyzed and {} profile(s) were \
+        deleted. Use optin to be analyzed again.",
+        deleted
     );
-    msg.reply(ctx, reply).await?;
+    transport.reply(msg, reply).await?;
+    Ok(())
+}
+
+pub async fn optin(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    _args: Args,
+) -> CommandResult {
+    let reply = if state.database.write().await.opt_in(msg.author.id) {
+        "Your messages are analyzed again"
+    } else {
+        "You have not opted out"
+    };
+    transport.reply(msg, reply).await?;
+    Ok(())
+}
 
+pub async fn mydata(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    _args: Args,
+) -> CommandResult {
+    let export = {
+        let db = state.database.read().await;
+        let profiles: serde_json::Map<String, serde_json::Value> = db
+            .user_data(&msg.author.id)
+            .into_iter()
+            .map(|(scope, profile)| {
+                let profile = serde_json::to_value(profile).unwrap();
+                (scope.to_string(), profile)
+            })
+            .collect();
+        serde_json::json!({
+            "user_id": msg.author.id,
+            "opted_out": db.is_opted_out(&msg.author.id),
+            "profiles": profiles,
+        })
+    };
+
+    let mut message =
+        OutgoingMessage::new("Everything the bot has stored about you");
+    message.attachments.push(Attachment {
+        filename: "mydata.json".to_string(),
+        data: serde_json::to_vec_pretty(&export)?,
+    });
+    let channel = transport.direct_message_channel(msg.author.id).await?;
+    transport.send(channel, message).await?;
+
+    if msg.guild_id.is_some() {
+        transport
+            .reply(msg, "Sent your data in a direct message")
+            .await?;
+    }
     Ok(())
 }
 
-#[command]
-async fn stats(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
-    let (command_counter, msg_count) = {
-        let data_read = ctx.data.read().await;
-        let command_counter_lock = data_read
-            .get::<CommandCounter>()
-            .expect("Expected CommandCounter in TypeMap.")
-            .clone();
+pub async fn forgetme(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    _args: Args,
+) -> CommandResult {
+    let deleted = state.database.write().await.delete_user(&msg.author.id);
+    if deleted > 0 {
+        state.webhooks.notify(WebhookEvent::Purged {
+            user_id: msg.author.id.to_string(),
+            guild_id: None,
+            profiles_deleted: deleted,
+            reason: PurgeReason::Requested,
+        });
+    }
 
-        let raw_count = data_read
-            .get::<MessageCount>()
-            .expect("Expected MessageCount in TypeMap.")
-            .clone();
+    let reply = format!(
+        "Deleted {} profile(s). New messages are still analyzed unless you \
+        use optout.",
+        deleted
+    );
+    transport.reply(msg, reply).await?;
+    Ok(())
+}
+
+pub async fn config_show(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    _args: Args,
+) -> CommandResult {
+    let guild_id = msg.guild_id.expect("only_in(guilds)");
+    let db_lock = &state.database;
+
+    let (settings, config) = {
+        let db = db_lock.read().await;
+        (
+            db.guild_settings(guild_id),
+            db.guild_config(ProfileScope::Guild(guild_id)),
+        )
+    };
+
+    let mut content = MessageBuilder::new();
+    content.push_line("Guild settings:");
+    for key in GuildSettings::KEYS.iter() {
+        let value = config.value_of(key).unwrap_or_default();
+        if settings.is_overridden(key) {
+            content.push_line(format!("__{}__: {} (overridden)", key, value));
+        } else {
+            content.push_line(format!("__{}__: {}", key, value));
+        }
+    }
+
+    transport.reply(msg, content).await?;
+    Ok(())
+}
+
+pub async fn config_set(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    mut args: Args,
+) -> CommandResult {
+    let (key, value) = match (
+        args.single_quoted::<String>(),
+        args.single_quoted::<String>(),
+    ) {
+        (Ok(key), Ok(value)) => (key, value),
+        _ => {
+            transport
+                .reply(msg, "A setting name and a value are required.")
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let guild_id = msg.guild_id.expect("only_in(guilds)");
+    let db_lock = &state.database;
+
+    let result = {
+        let mut db = db_lock.write().await;
+        let mut settings = db.guild_settings(guild_id);
+        let result = settings.set(&key, &value);
+        if result.is_ok() {
+            db.set_guild_settings(guild_id, settings);
+        }
+        result
+    };
+
+    match result {
+        Ok(()) => {
+            transport
+                .reply(msg, format!("{} set to {}", &key, &value))
+                .await?
+        }
+        Err(why) => transport.reply(msg, why).await?,
+    };
+    Ok(())
+}
+
+pub async fn config_reset(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    mut args: Args,
+) -> CommandResult {
+    let key = match args.single_quoted::<String>() {
+        Ok(key) => key,
+        Err(_) => {
+            transport.reply(msg, "A setting name is required.").await?;
+            return Ok(());
+        }
+    };
+
+    let guild_id = msg.guild_id.expect("only_in(guilds)");
+    let db_lock = &state.database;
+
+    let found = {
+        let mut db = db_lock.write().await;
+        let mut settings = db.guild_settings(guild_id);
+        let found = settings.reset(&key);
+        if found {
+            db.set_guild_settings(guild_id, settings);
+        }
+        found
+    };
+
+    if found {
+        transport
+            .reply(msg, format!("{} reset to the default", &key))
+            .await?;
+    } else {
+        transport
+            .reply(msg, format!("Unknown setting '{}'", &key))
+            .await?;
+    }
+    Ok(())
+}
 
-        let command_counter = command_counter_lock.read().await;
-        let count = raw_count.load(std::sync::atomic::Ordering::Relaxed);
+/// Applies `change` to the enforcement settings of the guild `msg` was sent
+/// in and replies with its result.
+async fn update_enforcement<F>(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    change: F,
+) -> CommandResult
+where
+    F: FnOnce(&mut crate::dominator::EnforcementSettings) -> String,
+{
+    let guild_id = msg.guild_id.expect("only_in(guilds)");
+    let db_lock = &state.database;
 
-        (command_counter.clone(), count)
+    let reply = {
+        let mut db = db_lock.write().await;
+        let mut settings = db.guild_settings(guild_id);
+        let reply = change(&mut settings.enforcement);
+        db.set_guild_settings(guild_id, settings);
+        reply
     };
 
+    transport.reply(msg, reply).await?;
+    Ok(())
+}
+
+pub async fn dominator_show(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    _args: Args,
+) -> CommandResult {
+    let guild_id = msg.guild_id.expect("only_in(guilds)");
+    let db_lock = &state.database;
+    let settings = db_lock.read().await.guild_settings(guild_id).enforcement;
+
     let mut content = MessageBuilder::new();
     content.push_line(format!(
-        "System has analyzed {} user message(s)",
-        msg_count
+        "Enforcement is {}{}, cooldown {}s",
+        if settings.enabled {
+            "enabled"
+        } else {
+            "disabled"
+        },
+        if settings.dry_run { " (dry run)" } else { "" },
+        settings.cooldown_secs
     ));
-    content.push_line("Command usage counts:");
+    content.push_line("Tiers:");
+    for tier in settings.tiers.iter() {
+        content.push_line(format!(
+            "__{:.1}__ and above: {}",
+            tier.min_coefficient, tier.action
+        ));
+    }
+    let exempt_users: Vec<String> = settings
+        .exempt_users
+        .iter()
+        .map(|id| id.mention().to_string())
+        .collect();
+    let exempt_roles: Vec<String> = settings
+        .exempt_roles
+        .iter()
+        .map(|id| id.mention().to_string())
+        .collect();
+    content.push_line(format!("Exempt users: {}", exempt_users.join(", ")));
+    content.push_line(format!("Exempt roles: {}", exempt_roles.join(", ")));
+
+    transport.reply(msg, content).await?;
+    Ok(())
+}
+
+pub async fn dominator_enable(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    _args: Args,
+) -> CommandResult {
+    update_enforcement(state, transport, msg, |settings| {
+        settings.enabled = true;
+        "Enforcement enabled".to_string()
+    })
+    .await
+}
+
+pub async fn dominator_disable(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    _args: Args,
+) -> CommandResult {
+    update_enforcement(state, transport, msg, |settings| {
+        settings.enabled = false;
+        "Enforcement disabled".to_string()
+    })
+    .await
+}
+
+pub async fn dominator_dry_run(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    mut args: Args,
+) -> CommandResult {
+    let dry_run = match args.single::<String>().as_deref() {
+        Ok("on") => true,
+        Ok("off") => false,
+        _ => {
+            transport.reply(msg, "Dry run requires on or off.").await?;
+            return Ok(());
+        }
+    };
+
+    update_enforcement(state, transport, msg, |settings| {
+        settings.dry_run = dry_run;
+        format!("Dry run {}", if dry_run { "on" } else { "off" })
+    })
+    .await
+}
+
+pub async fn dominator_cooldown(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    mut args: Args,
+) -> CommandResult {
+    let cooldown = match args.single::<u64>() {
+        Ok(x) => x,
+        Err(_) => {
+            transport
+                .reply(msg, "I require a cooldown in seconds.")
+                .await?;
+            return Ok(());
+        }
+    };
+
+    update_enforcement(state, transport, msg, |settings| {
+        settings.cooldown_secs = cooldown;
+        format!("Cooldown set to {}s", cooldown)
+    })
+    .await
+}
+
+pub async fn dominator_tier(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    mut args: Args,
+) -> CommandResult {
+    let (min_coefficient, kind) =
+        match (args.single::<f64>(), args.single::<String>()) {
+            (Ok(min), Ok(kind)) if min.is_finite() => (min, kind),
+            _ => {
+                transport
+                    .reply(
+                        msg,
+                        concat!(
+                            "Usage: tier <coefficient> ",
+                            "warn|role <role>|timeout <minutes>"
+                        ),
+                    )
+                    .await?;
+                return Ok(());
+            }
+        };
+    let argument = args.single::<String>().ok();
+
+    let action = match EnforcementAction::parse(&kind, argument.as_deref()) {
+        Ok(action) => action,
+        Err(why) => {
+            transport.reply(msg, why).await?;
+            return Ok(());
+        }
+    };
+
+    update_enforcement(state, transport, msg, |settings| {
+        let reply = format!("{:.1} and above: {}", min_coefficient, action);
+        settings.set_tier(EnforcementTier {
+            min_coefficient,
+            action,
+        });
+        reply
+    })
+    .await
+}
 
-    for (command, count) in command_counter {
-        content.push_line(format!("__{}__: {}", command, count));
+pub async fn dominator_untier(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    mut args: Args,
+) -> CommandResult {
+    let min_coefficient = match args.single::<f64>() {
+        Ok(x) => x,
+        Err(_) => {
+            transport
+                .reply(msg, "I require the coefficient of a tier.")
+                .await?;
+            return Ok(());
+        }
+    };
+
+    update_enforcement(state, transport, msg, |settings| {
+        if settings.remove_tier(min_coefficient) {
+            format!("Removed the {:.1} tier", min_coefficient)
+        } else {
+            format!("There is no {:.1} tier", min_coefficient)
+        }
+    })
+    .await
+}
+
+enum ExemptTarget {
+    User(UserId),
+    Role(RoleId),
+}
+
+async fn parse_exempt_target(
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    args: &mut Args,
+) -> TransportResult<Option<ExemptTarget>> {
+    let target = match args.single::<String>().as_deref() {
+        Ok("user") => args.single::<UserId>().ok().map(ExemptTarget::User),
+        Ok("role") => args.single::<RoleId>().ok().map(ExemptTarget::Role),
+        _ => None,
+    };
+
+    if target.is_none() {
+        transport
+            .reply(msg, "Usage: exempt user <user> or exempt role <role>")
+            .await?;
     }
+    Ok(target)
+}
 
-    msg.reply(ctx, content).await?;
+pub async fn dominator_exempt(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    mut args: Args,
+) -> CommandResult {
+    let target = match parse_exempt_target(transport, msg, &mut args).await? {
+        Some(target) => target,
+        None => return Ok(()),
+    };
+
+    update_enforcement(state, transport, msg, |settings| match target {
+        ExemptTarget::User(id) => {
+            settings.exempt_users.insert(id);
+            format!("{} is exempt", id.mention())
+        }
+        ExemptTarget::Role(id) => {
+            settings.exempt_roles.insert(id);
+            format!("{} is exempt", id.mention())
+        }
+    })
+    .await
+}
+
+pub async fn dominator_unexempt(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    mut args: Args,
+) -> CommandResult {
+    let target = match parse_exempt_target(transport, msg, &mut args).await? {
+        Some(target) => target,
+        None => return Ok(()),
+    };
+
+    update_enforcement(state, transport, msg, |settings| match target {
+        ExemptTarget::User(id) => {
+            settings.exempt_users.remove(&id);
+            format!("{} is no longer exempt", id.mention())
+        }
+        ExemptTarget::Role(id) => {
+            settings.exempt_roles.remove(&id);
+            format!("{} is no longer exempt", id.mention())
+        }
+    })
+    .await
+}
+
+pub async fn auditlog_show(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    _args: Args,
+) -> CommandResult {
+    let guild_id = msg.guild_id.expect("only_in(guilds)");
+    let db_lock = &state.database;
+    let settings = db_lock.read().await.guild_settings(guild_id).audit_log;
+
+    let channel = match settings.channel {
+        Some(channel) => channel.mention().to_string(),
+        None => "disabled".to_string(),
+    };
+    let boundaries: Vec<String> = settings
+        .boundaries
+        .iter()
+        .map(|boundary| format!("{:.1}", boundary))
+        .collect();
+
+    let mut content = MessageBuilder::new();
+    content.push_line(format!("Audit log channel: {}", channel));
+    content.push_line(format!("Boundaries: {}", boundaries.join(", ")));
+
+    transport.reply(msg, content).await?;
     Ok(())
 }
 
-#[command]
-async fn msg_count(ctx: &Context, msg: &Message) -> CommandResult {
-    let raw_count = {
-        let data_read = ctx.data.read().await;
-        data_read
-            .get::<MessageCount>()
-            .expect("Expected MessageCount in TypeMap.")
-            .clone()
+pub async fn auditlog_channel(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    mut args: Args,
+) -> CommandResult {
+    let channel = match args.single::<String>() {
+        Ok(option) if option == "off" => None,
+        Ok(option) => match option.parse::<ChannelId>() {
+            Ok(channel) => Some(channel),
+            Err(_) => {
+                let reply = format!("Failed to parse channel {}", &option);
+                transport.reply(msg, reply).await?;
+                return Ok(());
+            }
+        },
+        Err(_) => {
+            transport
+                .reply(msg, "A channel or off is required.")
+                .await?;
+            return Ok(());
+        }
     };
 
-    let count = raw_count.load(std::sync::atomic::Ordering::Relaxed);
+    let guild_id = msg.guild_id.expect("only_in(guilds)");
+    let db_lock = &state.database;
 
-    msg.reply(
-        ctx,
-        format!("System has analyzed {} user message(s)", count),
-    )
-    .await?;
+    {
+        let mut db = db_lock.write().await;
+        let mut settings = db.guild_settings(guild_id);
+        settings.audit_log.channel = channel;
+        db.set_guild_settings(guild_id, settings);
+    }
 
+    let reply = match channel {
+        Some(channel) => format!("Audit log posted in {}", channel.mention()),
+        None => "Audit log disabled".to_string(),
+    };
+    transport.reply(msg, reply).await?;
     Ok(())
 }
 
-#[command("debug")]
-#[sub_commands(debug_user, test_convertcc, quit)]
-async fn debug(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
-    msg.reply(&ctx.http, "Debug command requires an argument")
+pub async fn auditlog_boundaries(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    args: Args,
+) -> CommandResult {
+    let mut boundaries = Vec::new();
+    for arg in args.raw() {
+        match arg.parse::<f64>() {
+            Ok(boundary) if boundary.is_finite() => boundaries.push(boundary),
+            _ => {
+                transport
+                    .reply(msg, format!("'{}' is not a number", arg))
+                    .await?;
+                return Ok(());
+            }
+        }
+    }
+    if boundaries.is_empty() {
+        transport
+            .reply(msg, "At least one boundary is required.")
+            .await?;
+        return Ok(());
+    }
+    boundaries
+        .sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    boundaries.dedup();
+
+    let guild_id = msg.guild_id.expect("only_in(guilds)");
+    let db_lock = &state.database;
+
+    {
+        let mut db = db_lock.write().await;
+        let mut settings = db.guild_settings(guild_id);
+        settings.audit_log.boundaries = boundaries.clone();
+        db.set_guild_settings(guild_id, settings);
+    }
+
+    let boundaries: Vec<String> =
+        boundaries.iter().map(|b| format!("{:.1}", b)).collect();
+    transport
+        .reply(msg, format!("Boundaries set to {}", boundaries.join(", ")))
         .await?;
+    Ok(())
+}
 
+/// Applies `change` to the scope settings of the guild `msg` was sent in and
+/// replies with its result.
+async fn update_scope<F>(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    change: F,
+) -> CommandResult
+where
+    F: FnOnce(&mut ScopeSettings) -> String,
+{
+    let guild_id = msg.guild_id.expect("only_in(guilds)");
+    let db_lock = &state.database;
+
+    let reply = {
+        let mut db = db_lock.write().await;
+        let mut settings = db.guild_settings(guild_id);
+        let reply = change(&mut settings.scope);
+        db.set_guild_settings(guild_id, settings);
+        reply
+    };
+
+    transport.reply(msg, reply).await?;
     Ok(())
 }
 
-#[command]
-#[owners_only]
-#[aliases("user")]
-#[sub_commands(debug_user_dump, debug_user_reset)]
-async fn debug_user(
-    ctx: &Context,
-    msg: &Message,
+async fn parse_scope_target(
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    args: &mut Args,
+) -> TransportResult<Option<ScopeTarget>> {
+    let target = match (args.single::<String>(), args.single::<String>()) {
+        (Ok(kind), Ok(id)) => ScopeTarget::parse(&kind, &id),
+        _ => None,
+    };
+
+    if target.is_none() {
+        transport
+            .reply(
+                msg,
+                "Usage: channel <channel>, category <id> or role <role>",
+            )
+            .await?;
+    }
+    Ok(target)
+}
+
+pub async fn scope_show(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
     _args: Args,
 ) -> CommandResult {
-    msg.reply(&ctx.http, "Debug user command requires an argument")
-        .await?;
+    fn list<T: Copy + Ord>(
+        ids: &std::collections::HashSet<T>,
+        target: fn(T) -> ScopeTarget,
+    ) -> String {
+        let mut ids: Vec<T> = ids.iter().copied().collect();
+        ids.sort();
+        let names: Vec<String> =
+            ids.into_iter().map(|id| target(id).to_string()).collect();
+        if names.is_empty() {
+            "none".to_string()
+        } else {
+            names.join(", ")
+        }
+    }
+
+    let guild_id = msg.guild_id.expect("only_in(guilds)");
+    let db_lock = &state.database;
+    let scope = db_lock.read().await.guild_settings(guild_id).scope;
+
+    let mut content = MessageBuilder::new();
+    content.push_line(format!(
+        "Allowed channels: {}",
+        list(&scope.allowed_channels, ScopeTarget::Channel)
+    ));
+    content.push_line(format!(
+        "Denied channels: {}",
+        list(&scope.denied_channels, ScopeTarget::Channel)
+    ));
+    content.push_line(format!(
+        "Allowed categories: {}",
+        list(&scope.allowed_categories, ScopeTarget::Category)
+    ));
+    content.push_line(format!(
+        "Denied categories: {}",
+        list(&scope.denied_categories, ScopeTarget::Category)
+    ));
+    content.push_line(format!(
+        "Allowed roles: {}",
+        list(&scope.allowed_roles, ScopeTarget::Role)
+    ));
+    content.push_line(format!(
+        "Denied roles: {}",
+        list(&scope.denied_roles, ScopeTarget::Role)
+    ));
+    content.push_line(format!(
+        "NSFW channels: {}",
+        if scope.nsfw { "analyzed" } else { "skipped" }
+    ));
 
+    transport.reply(msg, content).await?;
     Ok(())
 }
 
-#[command]
-#[owners_only]
-#[aliases("dump")]
-async fn debug_user_dump(
-    ctx: &Context,
-    msg: &Message,
+pub async fn scope_allow(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    mut args: Args,
+) -> CommandResult {
+    let target = match parse_scope_target(transport, msg, &mut args).await? {
+        Some(target) => target,
+        None => return Ok(()),
+    };
+
+    update_scope(state, transport, msg, |scope| {
+        scope.allow(target);
+        format!("{} is allowed", target)
+    })
+    .await
+}
+
+pub async fn scope_deny(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    mut args: Args,
+) -> CommandResult {
+    let target = match parse_scope_target(transport, msg, &mut args).await? {
+        Some(target) => target,
+        None => return Ok(()),
+    };
+
+    update_scope(state, transport, msg, |scope| {
+        scope.deny(target);
+        format!("{} is denied", target)
+    })
+    .await
+}
+
+pub async fn scope_remove(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    mut args: Args,
+) -> CommandResult {
+    let target = match parse_scope_target(transport, msg, &mut args).await? {
+        Some(target) => target,
+        None => return Ok(()),
+    };
+
+    update_scope(state, transport, msg, |scope| {
+        if scope.remove(target) {
+            format!("{} is no longer listed", target)
+        } else {
+            format!("{} is not listed", target)
+        }
+    })
+    .await
+}
+
+pub async fn scope_nsfw(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    mut args: Args,
+) -> CommandResult {
+    let nsfw = match args.single::<String>().as_deref() {
+        Ok("on") => true,
+        Ok("off") => false,
+        _ => {
+            transport.reply(msg, "NSFW requires on or off.").await?;
+            return Ok(());
+        }
+    };
+
+    update_scope(state, transport, msg, |scope| {
+        scope.nsfw = nsfw;
+        format!(
+            "Messages in NSFW channels are {}",
+            if nsfw { "analyzed" } else { "skipped" }
+        )
+    })
+    .await
+}
+
+pub async fn debug_user_dump(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
     mut args: Args,
 ) -> CommandResult {
     let user_name = args.single_quoted::<String>()?;
     let user_id = user_name.parse::<UserId>()?;
 
-    let data = ctx.data.read().await;
-    let db_lock = data
-        .get::<UserDatabase>()
-        .expect("Expected UserDatabase in TypeMap.")
-        .clone();
+    let db_lock = &state.database;
 
     let full_profile = {
         let db = db_lock.read().await;
-        if let Some(profile) = db.get_user_profile(&user_id) {
+        let scope = ProfileScope::from_guild(msg.guild_id);
+        if let Some(profile) = db.get_user_profile(scope, &user_id) {
             serde_json::to_string(profile).unwrap()
         } else {
             let reply = format!("Failed to find user {}", &user_name);
-            msg.reply(ctx, reply).await?;
+            transport.reply(msg, reply).await?;
             return Ok(());
         }
     };
 
-    msg.reply(
-        ctx,
-        MessageBuilder::new().push_codeblock(full_profile, Some("json")),
-    )
-    .await?;
+    transport
+        .reply(
+            msg,
+            MessageBuilder::new().push_codeblock(full_profile, Some("json")),
+        )
+        .await?;
     Ok(())
 }
 
-#[command]
-#[owners_only]
-#[aliases("reset")]
-async fn debug_user_reset(
-    ctx: &Context,
-    msg: &Message,
+pub async fn debug_user_reset(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
     mut args: Args,
 ) -> CommandResult {
     let user_name = args.single_quoted::<String>()?;
     let user_id = user_name.parse::<UserId>()?;
 
-    let data = ctx.data.read().await;
-    let db_lock = data
-        .get::<UserDatabase>()
-        .expect("Expected UserDatabase in TypeMap.")
-        .clone();
+    let db_lock = &state.database;
 
     {
         let mut db = db_lock.write().await;
-        if let Some(profile) = db.get_user_profile_as_mut(&user_id) {
+        let scope = ProfileScope::from_guild(msg.guild_id);
+        if let Some(profile) = db.get_user_profile_as_mut(scope, &user_id) {
             profile.delete_data();
         } else {
             let reply = format!("Failed to find user {}", &user_name);
-            msg.reply(ctx, reply).await?;
+            transport.reply(msg, reply).await?;
             return Ok(());
         }
     }
 
-    msg.reply(ctx, format!("{} profile reset", &user_name))
+    state.webhooks.notify(WebhookEvent::Purged {
+        user_id: user_id.to_string(),
+        guild_id: msg.guild_id.map(|id| id.to_string()),
+        profiles_deleted: 1,
+        reason: PurgeReason::Reset,
+    });
+
+    transport
+        .reply(msg, format!("{} profile reset", &user_name))
         .await?;
     Ok(())
 }
 
-#[command]
-#[aliases("convert")]
-async fn test_convertcc(
-    ctx: &Context,
-    msg: &Message,
+pub async fn debug_migrate(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
     mut args: Args,
 ) -> CommandResult {
-    let value = match args.single_quoted::<f64>() {
-        Ok(x) => x,
-        Err(_) => {
-            msg.reply(ctx, "I require a float to convert.").await?;
+    let guild_id = match args.single_quoted::<String>() {
+        Ok(guild_name) => match guild_name.parse::<u64>() {
+            Ok(id) => Some(GuildId(id)),
+            Err(_) => {
+                let reply = format!("Failed to parse guild id {}", &guild_name);
+                transport.reply(msg, reply).await?;
+                return Ok(());
+            }
+        },
+        Err(_) => msg.guild_id,
+    };
+
+    let guild_id = match guild_id {
+        Some(id) => id,
+        None => {
+            transport
+                .reply(msg, "A guild id is required outside of a guild.")
+                .await?;
             return Ok(());
         }
     };
 
-    let value = ComputedData::convert_compound_to_cc(value);
-    msg.reply(ctx, format!("{:.1}", value)).await?;
+    let db_lock = &state.database;
+
+    let count = db_lock
+        .write()
+        .await
+        .migrate_unscoped_profiles(ProfileScope::Guild(guild_id));
 
+    transport
+        .reply(
+            msg,
+            format!("Migrated {} profile(s) to {}", count, guild_id),
+        )
+        .await?;
     Ok(())
 }
 
-#[command]
-#[owners_only]
-async fn quit(ctx: &Context, msg: &Message) -> CommandResult {
-    let data = ctx.data.read().await;
+pub async fn test_convertcc(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    mut args: Args,
+) -> CommandResult {
+    let value = match args.single_quoted::<f64>() {
+        Ok(x) => x,
+        Err(_) => {
+            transport
+                .reply(msg, "I require a float to convert.")
+                .await?;
+            return Ok(());
+        }
+    };
 
-    if let (Some(db), Some(manager)) = (
-        data.get::<UserDatabase>(),
-        data.get::<ShardManagerContainer>(),
-    ) {
-        msg.reply(ctx, "Shutting down!").await?;
-        db.write().await.to_disk();
-        manager.lock().await.shutdown_all().await;
-    } else {
-        msg.reply(ctx, "There was a problem getting the shard manager")
-            .await?;
+    let parameters = {
+        let db_lock = &state.database;
+        let db = db_lock.read().await;
+        db.cymatic_parameters(ProfileScope::from_guild(msg.guild_id))
+    };
 
-        return Ok(());
-    }
+    let value = ComputedData::convert_compound_to_cc(value, &parameters);
+    transport.reply(msg, format!("{:.1}", value)).await?;
 
     Ok(())
 }
@@ -0,0 +1,456 @@
+use crate::auditlog::AuditLogSettings;
+use crate::dominator::EnforcementSettings;
+use crate::scope::ScopeSettings;
+use crate::userdb::CymaticParameters;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::{info, warn};
+
+pub const CONFIG_FILE: &str = "sibyl.toml";
+/// The longest half-life accepted, ten years.
+pub const MAX_HALF_LIFE_HOURS: f64 = 10.0 * 365.0 * 24.0;
+
+/// The half-life of `hours`, if it is between zero and
+/// `MAX_HALF_LIFE_HOURS`.
+fn half_life(hours: f64) -> Option<Duration> {
+    if !(0.0..=MAX_HALF_LIFE_HOURS).contains(&hours) {
+        return None;
+    }
+    Duration::try_from_secs_f64(hours * 3600.0).ok()
+}
+
+/// Settings loaded from the TOML config file. Every field is optional and
+/// falls back to the defaults below.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Config {
+    pub prefix: String,
+    pub database: DatabaseConfig,
+    pub analysis: AnalysisConfig,
+    pub profile: ProfileConfig,
+    pub coefficient: CoefficientConfig,
+    pub chart: ChartConfig,
+    pub retention: RetentionConfig,
+    pub metrics: MetricsConfig,
+    pub dashboard: DashboardConfig,
+    pub api: ApiConfig,
+    pub webhooks: WebhookConfig,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct DatabaseConfig {
+    /// Either "json" or "sqlite".
+    pub backend: String,
+    pub file: String,
+    pub sqlite_file: String,
+    pub backups: usize,
+    pub flush_interval_secs: u64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AnalysisConfig {
+    /// Either "vader" or "lexicon".
+    pub backend: String,
+    pub lexicon: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ProfileConfig {
+    pub max_history: usize,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RetentionConfig {
+    /// Sentiment results older than this are deleted, 0 keeps them forever.
+    pub entry_days: u64,
+    /// Profiles without a message for this long are deleted, 0 keeps them.
+    pub inactive_days: u64,
+    /// Whether the profile of a member who leaves a guild is deleted.
+    pub forget_departed: bool,
+    pub sweep_interval_secs: u64,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct MetricsConfig {
+    /// The address the `/metrics` endpoint listens on, disabled if unset.
+    pub listen: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct DashboardConfig {
+    /// The address the dashboard listens on, disabled if unset. Requires
+    /// the `dashboard` feature.
+    pub listen: Option<String>,
+    /// The bearer token moderators sign in with.
+    pub token: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct ApiConfig {
+    /// The address the JSON API listens on, disabled if unset.
+    pub listen: Option<String>,
+    /// The bearer token clients authenticate with.
+    pub token: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct WebhookConfig {
+    pub endpoints: Vec<WebhookEndpoint>,
+    /// Where undelivered notifications are kept across restarts.
+    pub queue_file: String,
+    /// Attempts before a notification is dropped.
+    pub max_attempts: u32,
+    /// The delay before the first retry, doubled after each failure.
+    pub retry_secs: u64,
+    pub max_retry_secs: u64,
+    pub timeout_secs: u64,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct WebhookEndpoint {
+    pub url: String,
+    /// The key payloads are signed with.
+    pub secret: String,
+    /// The events sent to this endpoint, every event if empty.
+    pub events: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct ChartConfig {
+    /// A TrueType font for the labels of charts, which have none without it.
+    pub font: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CoefficientConfig {
+    pub neutral: f64,
+    pub poly3: f64,
+    pub poly2: f64,
+    pub poly1: f64,
+    pub half_life_hours: f64,
+    pub min_samples: usize,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            prefix: "$".to_string(),
+            database: DatabaseConfig::default(),
+            analysis: AnalysisConfig::default(),
+            profile: ProfileConfig::default(),
+            coefficient: CoefficientConfig::default(),
+            chart: ChartConfig::default(),
+            retention: RetentionConfig::default(),
+            metrics: MetricsConfig::default(),
+            dashboard: DashboardConfig::default(),
+            api: ApiConfig::default(),
+            webhooks: WebhookConfig::default(),
+        }
+    }
+}
+
+impl Default for WebhookConfig {
+    fn default() -> WebhookConfig {
+        WebhookConfig {
+            endpoints: Vec::new(),
+            queue_file: "webhooks.json".to_string(),
+            max_attempts: 10,
+            retry_secs: 30,
+            max_retry_secs: 3600,
+            timeout_secs: 10,
+        }
+    }
+}
+
+impl WebhookConfig {
+    /// The delay before retrying a notification that failed `attempts`
+    /// times.
+    pub fn retry_delay(&self, attempts: u32) -> u64 {
+        let factor = 2u64.saturating_pow(attempts.saturating_sub(1));
+        self.retry_secs
+            .saturating_mul(factor)
+            .min(self.max_retry_secs)
+    }
+}
+
+impl Default for RetentionConfig {
+    fn default() -> RetentionConfig {
+        RetentionConfig {
+            entry_days: 0,
+            inactive_days: 0,
+            forget_departed: false,
+            sweep_interval_secs: 3600,
+        }
+    }
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> DatabaseConfig {
+        DatabaseConfig {
+            backend: "json".to_string(),
+            file: "userccs.db".to_string(),
+            sqlite_file: "userccs.sqlite".to_string(),
+            backups: 3,
+            flush_interval_secs: 60,
+        }
+    }
+}
+
+impl Default for AnalysisConfig {
+    fn default() -> AnalysisConfig {
+        AnalysisConfig {
+            backend: "vader".to_string(),
+            lexicon: None,
+        }
+    }
+}
+
+impl Default for ProfileConfig {
+    fn default() -> ProfileConfig {
+        ProfileConfig { max_history: 100 }
+    }
+}
+
+impl Default for CoefficientConfig {
+    fn default() -> CoefficientConfig {
+        CoefficientConfig {
+            neutral: 75.0,
+            poly3: 1.0,
+            poly2: 2.0,
+            poly1: 5.0,
+            half_life_hours: 72.0,
+            min_samples: 5,
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config file at `path`, using the defaults if it is missing.
+    pub fn load(path: &str) -> Config {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => {
+                let config = toml::from_str(&contents).unwrap_or_else(|why| {
+                    panic!("Failed to parse config file '{}': {}", path, why)
+                });
+                info!("Config file loaded from '{}'.", path);
+                config
+            }
+            Err(why) => {
+                warn!("Failed to load config file '{}': {}.", path, why);
+                Config::default()
+            }
+        }
+    }
+
+    pub fn flush_interval(&self) -> Duration {
+        Duration::from_secs(self.database.flush_interval_secs)
+    }
+
+    /// The settings of a guild without any overrides.
+    pub fn guild_defaults(&self) -> GuildConfig {
+        let hours = self.coefficient.half_life_hours.max(0.0);
+        let half_life = half_life(hours).unwrap_or_else(|| {
+            warn!(
+                "Ignoring the half-life of {} hours, it must be at most {}.",
+                hours, MAX_HALF_LIFE_HOURS
+            );
+            let hours = CoefficientConfig::default().half_life_hours;
+            Duration::from_secs_f64(hours * 3600.0)
+        });
+        GuildConfig {
+            prefix: self.prefix.clone(),
+            max_history: self.profile.max_history.max(1),
+            cymatic: CymaticParameters {
+                half_life,
+                cc_neutral: self.coefficient.neutral,
+                cc_poly3: self.coefficient.poly3,
+                cc_poly2: self.coefficient.poly2,
+                cc_poly1: self.coefficient.poly1,
+                min_samples: self.coefficient.min_samples,
+            },
+        }
+    }
+}
+
+/// The effective settings of a guild, with its overrides applied.
+#[derive(Debug, Clone)]
+pub struct GuildConfig {
+    pub prefix: String,
+    pub max_history: usize,
+    pub cymatic: CymaticParameters,
+}
+
+/// Per-guild overrides of the config file, stored in the user database.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct GuildSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_history: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cc_neutral: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cc_poly3: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cc_poly2: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cc_poly1: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub half_life_hours: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_samples: Option<usize>,
+
+    #[serde(default)]
+    pub enforcement: EnforcementSettings,
+    #[serde(default)]
+    pub audit_log: AuditLogSettings,
+    #[serde(default)]
+    pub scope: ScopeSettings,
+}
+
+impl GuildSettings {
+    pub const KEYS: [&'static str; 8] = [
+        "prefix",
+        "max_history",
+        "cc_neutral",
+        "cc_poly3",
+        "cc_poly2",
+        "cc_poly1",
+        "half_life_hours",
+        "min_samples",
+    ];
+
+    pub fn apply(&self, defaults: &GuildConfig) -> GuildConfig {
+        let mut config = defaults.clone();
+        if let Some(prefix) = &self.prefix {
+            config.prefix = prefix.clone();
+        }
+        if let Some(max_history) = self.max_history {
+            config.max_history = max_history;
+        }
+        // Out of range overrides stored before they were rejected keep the
+        // default.
+        if let Some(half_life) = self.half_life_hours.and_then(half_life) {
+            config.cymatic.half_life = half_life;
+        }
+        let cymatic = &mut config.cymatic;
+        cymatic.cc_neutral = self.cc_neutral.unwrap_or(cymatic.cc_neutral);
+        cymatic.cc_poly3 = self.cc_poly3.unwrap_or(cymatic.cc_poly3);
+        cymatic.cc_poly2 = self.cc_poly2.unwrap_or(cymatic.cc_poly2);
+        cymatic.cc_poly1 = self.cc_poly1.unwrap_or(cymatic.cc_poly1);
+        cymatic.min_samples = self.min_samples.unwrap_or(cymatic.min_samples);
+        config
+    }
+
+    /// Overrides `key` with `value`, returning a message on invalid input.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        fn parse_number(value: &str) -> Result<f64, String> {
+            match value.parse::<f64>() {
+                Ok(number) if number.is_finite() => Ok(number),
+                _ => Err(format!("'{}' is not a number", value)),
+            }
+        }
+
+        match key {
+            "prefix" => {
+                if value.is_empty() || value.contains(char::is_whitespace) {
+                    return Err("The prefix cannot contain spaces".to_string());
+                }
+                self.prefix = Some(value.to_string());
+            }
+            "max_history" => match value.parse::<usize>() {
+                Ok(length) if length > 0 => self.max_history = Some(length),
+                _ => {
+                    return Err(format!(
+                        "'{}' is not a positive whole number",
+                        value
+                    ))
+                }
+            },
+            "cc_neutral" => self.cc_neutral = Some(parse_number(value)?),
+            "cc_poly3" => self.cc_poly3 = Some(parse_number(value)?),
+            "cc_poly2" => self.cc_poly2 = Some(parse_number(value)?),
+            "cc_poly1" => self.cc_poly1 = Some(parse_number(value)?),
+            "half_life_hours" => {
+                let hours = parse_number(value)?;
+                if hours < 0.0 {
+                    return Err("The half-life cannot be negative".to_string());
+                }
+                if hours > MAX_HALF_LIFE_HOURS {
+                    return Err(format!(
+                        "The half-life cannot be longer than {} hours",
+                        MAX_HALF_LIFE_HOURS
+                    ));
+                }
+                self.half_life_hours = Some(hours);
+            }
+            "min_samples" => match value.parse::<usize>() {
+                Ok(count) => self.min_samples = Some(count),
+                _ => return Err(format!("'{}' is not a whole number", value)),
+            },
+            _ => return Err(format!("Unknown setting '{}'", key)),
+        }
+        Ok(())
+    }
+
+    /// Removes the override of `key`, returning whether `key` exists.
+    pub fn reset(&mut self, key: &str) -> bool {
+        match key {
+            "prefix" => self.prefix = None,
+            "max_history" => self.max_history = None,
+            "cc_neutral" => self.cc_neutral = None,
+            "cc_poly3" => self.cc_poly3 = None,
+            "cc_poly2" => self.cc_poly2 = None,
+            "cc_poly1" => self.cc_poly1 = None,
+            "half_life_hours" => self.half_life_hours = None,
+            "min_samples" => self.min_samples = None,
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn is_overridden(&self, key: &str) -> bool {
+        match key {
+            "prefix" => self.prefix.is_some(),
+            "max_history" => self.max_history.is_some(),
+            "cc_neutral" => self.cc_neutral.is_some(),
+            "cc_poly3" => self.cc_poly3.is_some(),
+            "cc_poly2" => self.cc_poly2.is_some(),
+            "cc_poly1" => self.cc_poly1.is_some(),
+            "half_life_hours" => self.half_life_hours.is_some(),
+            "min_samples" => self.min_samples.is_some(),
+            _ => false,
+        }
+    }
+}
+
+impl GuildConfig {
+    /// Formats the effective value of a `GuildSettings` key.
+    pub fn value_of(&self, key: &str) -> Option<String> {
+        let cymatic = &self.cymatic;
+        Some(match key {
+            "prefix" => self.prefix.clone(),
+            "max_history" => self.max_history.to_string(),
+            "cc_neutral" => cymatic.cc_neutral.to_string(),
+            "cc_poly3" => cymatic.cc_poly3.to_string(),
+            "cc_poly2" => cymatic.cc_poly2.to_string(),
+            "cc_poly1" => cymatic.cc_poly1.to_string(),
+            "half_life_hours" => {
+                (cymatic.half_life.as_secs_f64() / 3600.0).to_string()
+            }
+            "min_samples" => cymatic.min_samples.to_string(),
+            _ => return None,
+        })
+    }
+}
@@ -0,0 +1,591 @@
+//! A read-only web dashboard for moderators. Every page except the sign in
+//! form requires the bearer token from the config, either in an
+//! `Authorization` header or in the cookie set by signing in.
+
+use crate::api::{has_bearer, same_token};
+use crate::auditlog::Crossing;
+use crate::bot::BotState;
+use crate::leaderboard::{self, Order};
+use crate::usage::{self, UsageSummary};
+use crate::userdb::{current_day, ProfileScope};
+use crate::{chart, history};
+use hyper::body::HttpBody;
+use hyper::header::{self, HeaderValue};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serenity::model::prelude::*;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+use tracing::{error, info};
+
+const COOKIE: &str = "sibyl_token";
+/// The largest sign in form that is read.
+const MAX_FORM_BYTES: usize = 4096;
+const HISTORY_ROWS: usize = 50;
+const STYLE: &str =
+    "body{font-family:sans-serif;margin:2em auto;max-width:60em}\
+    table{border-collapse:collapse;margin-bottom:1em}\
+    td,th{border-bottom:1px solid #ccc;padding:.2em .8em;text-align:left}\
+    .number{text-align:right}";
+
+/// Answers a request to the dashboard.
+pub async fn handle(
+    state: &BotState,
+    token: &str,
+    request: Request<Body>,
+) -> Response<Body> {
+    let path = request.uri().path().to_string();
+    let segments: Vec<&str> = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect();
+    let method = request.method().clone();
+
+    match (&method, segments.as_slice()) {
+        (&Method::GET, ["login"]) => login_page(StatusCode::OK, None),
+        (&Method::POST, ["login"]) => login(token, request).await,
+        _ if !is_authorized(token, &request) => {
+            let mut response = login_page(
+                StatusCode::UNAUTHORIZED,
+                Some("Sign in to view the dashboard."),
+            );
+            response.headers_mut().insert(
+                header::WWW_AUTHENTICATE,
+                HeaderValue::from_static("Bearer"),
+            );
+            response
+        }
+        (&Method::GET, []) => overview(state).await,
+        (&Method::GET, ["guilds", guild]) => match parse_id(guild) {
+            Some(id) => {
+                let order = query_param(&request, "order")
+                    .and_then(|order| Order::parse(&order))
+                    .unwrap_or(Order::Highest);
+                let page = query_param(&request, "page")
+                    .and_then(|page| page.parse().ok())
+                    .unwrap_or(1);
+                guild_page(state, GuildId(id), order, page).await
+            }
+            None => not_found(),
+        },
+        (&Method::GET, ["guilds", guild, "users", user]) => {
+            match (parse_id(guild), parse_id(user)) {
+                (Some(guild), Some(user)) => {
+                    user_page(state, GuildId(guild), UserId(user)).await
+                }
+                _ => not_found(),
+            }
+        }
+        (&Method::GET, ["guilds", guild, "users", user, "chart.png"]) => {
+            match (parse_id(guild), parse_id(user)) {
+                (Some(guild), Some(user)) => {
+                    user_chart(state, GuildId(guild), UserId(user)).await
+                }
+                _ => not_found(),
+            }
+        }
+        _ => not_found(),
+    }
+}
+
+/// Serves the dashboard at `addr` until the program exits.
+pub async fn serve(addr: SocketAddr, state: Arc<BotState>, token: String) {
+    let token = Arc::new(token);
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+        let token = token.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let state = state.clone();
+                let token = token.clone();
+                async move {
+                    Ok::<_, Infallible>(handle(&state, &token, request).await)
+                }
+            }))
+        }
+    });
+
+    let server = match Server::try_bind(&addr) {
+        Ok(builder) => builder.serve(make_service),
+        Err(why) => {
+            error!("Failed to serve the dashboard on {}: {}", addr, why);
+            return;
+        }
+    };
+    info!("Serving the dashboard on http://{}/", addr);
+    if let Err(why) = server.await {
+        error!("Dashboard server error: {}", why);
+    }
+}
+
+/// The cookie holds the token hex-encoded so any token is a valid value.
+fn cookie_value(token: &str) -> String {
+    token.bytes().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn is_authorized(token: &str, request: &Request<Body>) -> bool {
+    if has_bearer(token, request) {
+        return true;
+    }
+
+    let expected = cookie_value(token);
+    request
+        .headers()
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .any(|(name, value)| {
+            name == COOKIE && same_token(value.as_bytes(), expected.as_bytes())
+        })
+}
+
+async fn login(token: &str, request: Request<Body>) -> Response<Body> {
+    let mut body = request.into_body();
+    let mut form = Vec::new();
+    while let Some(chunk) = body.data().await {
+        match chunk {
+            Ok(chunk) if form.len() + chunk.len() <= MAX_FORM_BYTES => {
+                form.extend_from_slice(&chunk)
+            }
+            _ => {
+                return login_page(
+                    StatusCode::BAD_REQUEST,
+                    Some("Invalid form."),
+                )
+            }
+        }
+    }
+
+    let form = String::from_utf8_lossy(&form);
+    let submitted = form_value(&form, "token").unwrap_or_default();
+    if !same_token(submitted.as_bytes(), token.as_bytes()) {
+        return login_page(StatusCode::UNAUTHORIZED, Some("Wrong token."));
+    }
+
+    let cookie = format!(
+        "{}={}; HttpOnly; SameSite=Strict; Path=/",
+        COOKIE,
+        cookie_value(token)
+    );
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::SEE_OTHER;
+    let headers = response.headers_mut();
+    headers.insert(header::LOCATION, HeaderValue::from_static("/"));
+    if let Ok(cookie) = HeaderValue::from_str(&cookie) {
+        headers.insert(header::SET_COOKIE, cookie);
+    }
+    response
+}
+
+fn login_page(status: StatusCode, notice: Option<&str>) -> Response<Body> {
+    let mut body = String::new();
+    if let Some(notice) = notice {
+        let _ = write!(body, "<p>{}</p>", escape(notice));
+    }
+    body.push_str(
+        "<form method=\"post\" action=\"/login\">\
+        <input type=\"password\" name=\"token\" placeholder=\"Token\">\
+        <button type=\"submit\">Sign in</button></form>",
+    );
+    let mut response = page("Sign in", &body);
+    *response.status_mut() = status;
+    response
+}
+
+async fn overview(state: &BotState) -> Response<Body> {
+    let (summary, profiles, guilds) = {
+        let db = state.database.read().await;
+        let today = current_day();
+        let guilds: Vec<(GuildId, usize, UsageSummary)> = db
+            .guild_ids()
+            .into_iter()
+            .map(|id| {
+                let scope = ProfileScope::Guild(id);
+                let profiles = db.user_profiles(scope).count();
+                (id, profiles, db.usage().summary(Some(id), today))
+            })
+            .collect();
+        (db.usage().summary(None, today), db.profile_count(), guilds)
+    };
+
+    let mut body = String::new();
+    let _ = write!(body, "<p>{} profile(s)</p>", profiles);
+    body.push_str(&usage_section(&summary));
+
+    body.push_str(
+        "<h2>Guilds</h2><table><tr><th>Guild</th>\
+        <th class=\"number\">Profiles</th>\
+        <th class=\"number\">Messages today</th></tr>",
+    );
+    for (id, profiles, summary) in guilds {
+        let _ = write!(
+            body,
+            "<tr><td><a href=\"/guilds/{0}\">{0}</a></td>\
+            <td class=\"number\">{1}</td><td class=\"number\">{2}</td></tr>",
+            id, profiles, summary.today.messages
+        );
+    }
+    body.push_str("</table>");
+
+    body.push_str(&crossings_section(&state.recent_crossings(None).await));
+    page("Sibyl System", &body)
+}
+
+async fn guild_page(
+    state: &BotState,
+    guild_id: GuildId,
+    order: Order,
+    page_number: usize,
+) -> Response<Body> {
+    let scope = ProfileScope::Guild(guild_id);
+    let (ranking, summary) = {
+        let db = state.database.read().await;
+        if db.guild_ids().binary_search(&guild_id).is_err() {
+            return not_found();
+        }
+        (
+            leaderboard::rank(&db, scope, order),
+            db.usage().summary(Some(guild_id), current_day()),
+        )
+    };
+
+    let pages = leaderboard::page_count(ranking.ranked.len());
+    let page_number = page_number.clamp(1, pages);
+    let start = (page_number - 1) * leaderboard::PAGE_SIZE;
+
+    let mut body = String::new();
+    let _ = write!(
+        body,
+        "<h2>Leaderboard ({})</h2><p><a href=\"?order=highest\">Highest</a> \
+        | <a href=\"?order=lowest\">Lowest</a></p>\
+        <table><tr><th class=\"number\">#</th><th>User</th>\
+        <th class=\"number\">Crime coefficient</th></tr>",
+        order.name()
+    );
+    for (position, entry) in ranking
+        .ranked
+        .iter()
+        .enumerate()
+        .skip(start)
+        .take(leaderboard::PAGE_SIZE)
+    {
+        let _ = write!(
+            body,
+            "<tr><td class=\"number\">{}</td>\
+            <td><a href=\"/guilds/{}/users/{}\">{}</a></td>\
+            <td class=\"number\">{:.1}</td></tr>",
+            position + 1,
+            guild_id,
+            entry.id,
+            escape(&entry.tag),
+            entry.crime_coefficient
+        );
+    }
+    body.push_str("</table>");
+    let _ = write!(body, "<p>Page {} of {}", page_number, pages);
+    if page_number > 1 {
+        let _ = write!(
+            body,
+            " <a href=\"?order={}&page={}\">Previous</a>",
+            order.name(),
+            page_number - 1
+        );
+    }
+    if page_number < pages {
+        let _ = write!(
+            body,
+            " <a href=\"?order={}&page={}\">Next</a>",
+            order.name(),
+            page_number + 1
+        );
+    }
+    if ranking.excluded > 0 {
+        let _ = write!(
+            body,
+            ", {} user(s) with fewer than {} messages left out",
+            ranking.excluded, ranking.min_samples
+        );
+    }
+    body.push_str("</p>");
+
+    let coefficients: Vec<f64> =
+        ranking.ranked.iter().map(|r| r.crime_coefficient).collect();
+    let _ = write!(
+        body,
+        "<h2>Distribution</h2><pre>{}</pre>",
+        escape(&leaderboard::histogram(&coefficients))
+    );
+
+    body.push_str(&usage_section(&summary));
+    body.push_str(&crossings_section(
+        &state.recent_crossings(Some(guild_id)).await,
+    ));
+    page(&format!("Guild {}", guild_id), &body)
+}
+
+async fn user_page(
+    state: &BotState,
+    guild_id: GuildId,
+    user_id: UserId,
+) -> Response<Body> {
+    let scope = ProfileScope::Guild(guild_id);
+    let db = state.database.read().await;
+    let profile = match db.get_user_profile(scope, &user_id) {
+        Some(profile) => profile,
+        None => return not_found(),
+    };
+    let cdata = profile.get_cymatic_data(&db.cymatic_parameters(scope));
+
+    let mut body = String::new();
+    let _ = write!(
+        body,
+        "<p><a href=\"/guilds/{0}\">Guild {0}</a></p>",
+        guild_id
+    );
+    if cdata.insufficient_data {
+        let _ = write!(
+            body,
+            "<p>Insufficient data: {} message(s) analyzed.</p>",
+            cdata.samples
+        );
+    } else {
+        let _ = write!(
+            body,
+            "<table><tr><th>Crime coefficient</th><td>{:.1}</td></tr>\
+            <tr><th>95% interval</th><td>{:.1} to {:.1}</td></tr>\
+            <tr><th>Hue</th><td>{} ({})</td></tr>\
+            <tr><th>Messages</th><td>{}</td></tr></table>",
+            cdata.crime_coefficient,
+            cdata.interval.0,
+            cdata.interval.1,
+            cdata.hue.state.name(),
+            cdata.hue.trend_description(),
+            cdata.samples
+        );
+    }
+
+    let snapshots = profile.snapshots();
+    let today = current_day();
+    let days = history::DEFAULT_PERIOD_DAYS;
+    let _ = write!(
+        body,
+        "<h2>Coefficient history</h2><pre>{}</pre>\
+        <table><tr><th>Day</th><th class=\"number\">Crime coefficient</th></tr>",
+        history::sparkline(&history::in_period(snapshots, today, days), today, days)
+    );
+    for snapshot in snapshots.iter().rev().take(HISTORY_ROWS) {
+        let _ = write!(
+            body,
+            "<tr><td>{}</td><td class=\"number\">{:.1}</td></tr>",
+            usage::day_name(snapshot.day),
+            snapshot.crime_coefficient
+        );
+    }
+    body.push_str("</table>");
+
+    let _ = write!(
+        body,
+        "<h2>Sentiment history</h2>\
+        <img src=\"/guilds/{}/users/{}/chart.png\" alt=\"Sentiment chart\">\
+        <table><tr><th>Time</th><th class=\"number\">Compound</th>\
+        <th class=\"number\">Positive</th><th class=\"number\">Neutral</th>\
+        <th class=\"number\">Negative</th></tr>",
+        guild_id, user_id
+    );
+    for result in profile.history().iter().rev().take(HISTORY_ROWS) {
+        let _ = write!(
+            body,
+            "<tr><td>{}</td><td class=\"number\">{:.3}</td>\
+            <td class=\"number\">{:.3}</td><td class=\"number\">{:.3}</td>\
+            <td class=\"number\">{:.3}</td></tr>",
+            time_name(result.timestamp()),
+            result.compound(),
+            result.positive(),
+            result.neutral(),
+            result.negative()
+        );
+    }
+    body.push_str("</table>");
+    page(profile.user_tag(), &body)
+}
+
+async fn user_chart(
+    state: &BotState,
+    guild_id: GuildId,
+    user_id: UserId,
+) -> Response<Body> {
+    let rendered = {
+        let db = state.database.read().await;
+        match db.get_user_profile(ProfileScope::Guild(guild_id), &user_id) {
+            Some(profile) => {
+                chart::render(profile.user_tag(), &profile.history())
+            }
+            None => return not_found(),
+        }
+    };
+
+    match rendered {
+        Ok(attachment) => {
+            let mut response = Response::new(Body::from(attachment.data));
+            let headers = response.headers_mut();
+            headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("image/png"),
+            );
+            headers.insert(
+                header::CACHE_CONTROL,
+                HeaderValue::from_static("no-store"),
+            );
+            response
+        }
+        Err(_) => not_found(),
+    }
+}
+
+fn usage_section(summary: &UsageSummary) -> String {
+    let mut section = String::new();
+    let _ = write!(
+        section,
+        "<h2>Stats</h2><p>{} message(s) analyzed, {} today with {} command(s)</p>\
+        <table><tr><th>Day</th><th class=\"number\">Messages</th>\
+        <th class=\"number\">Commands</th></tr>",
+        summary.total.messages,
+        summary.today.messages,
+        summary.today.command_total()
+    );
+    for (day, usage) in summary.week.iter().rev() {
+        let _ = write!(
+            section,
+            "<tr><td>{}</td><td class=\"number\">{}</td>\
+            <td class=\"number\">{}</td></tr>",
+            usage::day_name(*day),
+            usage.messages,
+            usage.command_total()
+        );
+    }
+    section.push_str("</table>");
+    section
+}
+
+fn crossings_section(crossings: &[Crossing]) -> String {
+    let mut section = String::from("<h2>Recent threshold crossings</h2>");
+    if crossings.is_empty() {
+        section.push_str("<p>None since the bot started.</p>");
+        return section;
+    }
+    section.push_str(
+        "<table><tr><th>Time</th><th>User</th>\
+        <th class=\"number\">Boundary</th><th class=\"number\">Old</th>\
+        <th class=\"number\">New</th><th></th></tr>",
+    );
+    for crossing in crossings {
+        let _ = write!(
+            section,
+            "<tr><td>{}</td>\
+            <td><a href=\"/guilds/{}/users/{}\">{}</a></td>\
+            <td class=\"number\">{:.1}</td><td class=\"number\">{:.1}</td>\
+            <td class=\"number\">{:.1}</td>\
+            <td><a href=\"{}\">Message</a></td></tr>",
+            escape(&crossing.timestamp),
+            crossing.guild_id,
+            crossing.user_id,
+            escape(&crossing.user_tag),
+            crossing.boundary,
+            crossing.old,
+            crossing.new,
+            escape(&crossing.link)
+        );
+    }
+    section.push_str("</table>");
+    section
+}
+
+fn page(title: &str, body: &str) -> Response<Body> {
+    let html = format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+        <title>{0}</title><style>{1}</style></head><body>\
+        <nav><a href=\"/\">Sibyl System</a></nav><h1>{0}</h1>{2}</body></html>",
+        escape(title),
+        STYLE,
+        body
+    );
+    let mut response = Response::new(Body::from(html));
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/html; charset=utf-8"),
+    );
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    headers.insert(
+        header::REFERRER_POLICY,
+        HeaderValue::from_static("no-referrer"),
+    );
+    response
+}
+
+fn not_found() -> Response<Body> {
+    let mut response = page("Not found", "");
+    *response.status_mut() = StatusCode::NOT_FOUND;
+    response
+}
+
+fn parse_id(segment: &str) -> Option<u64> {
+    segment.parse().ok()
+}
+
+fn query_param(request: &Request<Body>, name: &str) -> Option<String> {
+    form_value(request.uri().query()?, name)
+}
+
+/// Finds `name` in an `application/x-www-form-urlencoded` string.
+fn form_value(form: &str, name: &str) -> Option<String> {
+    form.split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| decode(key) == name)
+        .map(|(_, value)| decode(value))
+}
+
+fn decode(encoded: &str) -> String {
+    let bytes = encoded.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'+' => decoded.push(b' '),
+            b'%' if index + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[index + 1..index + 3]);
+                match hex.map(|hex| u8::from_str_radix(hex, 16)) {
+                    Ok(Ok(byte)) => {
+                        decoded.push(byte);
+                        index += 2;
+                    }
+                    _ => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        index += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// Formats a unix timestamp in UTC.
+fn time_name(timestamp: u64) -> String {
+    let time = UNIX_EPOCH + Duration::from_secs(timestamp);
+    chrono::DateTime::<chrono::Utc>::from(time)
+        .format("%Y-%m-%d %H:%M")
+        .to_string()
+}
@@ -0,0 +1,235 @@
+use crate::bot::BotState;
+use crate::transport::{IncomingMessage, OutgoingMessage, Transport};
+use crate::userdb::current_timestamp;
+use crate::webhook::WebhookEvent;
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+use std::collections::{HashMap, HashSet};
+use tracing::{error, info, warn};
+
+/// What happens to a user whose crime coefficient reaches a tier.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EnforcementAction {
+    Warn,
+    AssignRole { role: RoleId },
+    Timeout { minutes: u64 },
+}
+
+impl EnforcementAction {
+    /// Parses `warn`, `role <role>` or `timeout <minutes>`.
+    pub fn parse(kind: &str, argument: Option<&str>) -> Result<Self, String> {
+        match (kind, argument) {
+            ("warn", _) => Ok(EnforcementAction::Warn),
+            ("role", Some(role)) => match role.parse::<RoleId>() {
+                Ok(role) => Ok(EnforcementAction::AssignRole { role }),
+                Err(_) => Err(format!("'{}' is not a role", role)),
+            },
+            ("timeout", Some(minutes)) => match minutes.parse::<u64>() {
+                // Discord caps timeouts at 28 days.
+                Ok(minutes) if minutes > 0 && minutes <= 28 * 24 * 60 => {
+                    Ok(EnforcementAction::Timeout { minutes })
+                }
+                _ => Err(format!("'{}' is not a valid timeout", minutes)),
+            },
+            ("role", None) => Err("A role is required".to_string()),
+            ("timeout", None) => Err("A duration is required".to_string()),
+            _ => Err(format!("Unknown action '{}'", kind)),
+        }
+    }
+}
+
+impl std::fmt::Display for EnforcementAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EnforcementAction::Warn => write!(f, "warn"),
+            EnforcementAction::AssignRole { role } => {
+                write!(f, "assign role {}", role.mention())
+            }
+            EnforcementAction::Timeout { minutes } => {
+                write!(f, "timeout for {} minute(s)", minutes)
+            }
+        }
+    }
+}
+
+/// An action applied from `min_coefficient` up to the next tier.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EnforcementTier {
+    pub min_coefficient: f64,
+    pub action: EnforcementAction,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct EnforcementSettings {
+    pub enabled: bool,
+    /// Only log what would have been done.
+    pub dry_run: bool,
+    /// Minimum time between two actions against the same user.
+    pub cooldown_secs: u64,
+    /// Sorted by `min_coefficient`.
+    pub tiers: Vec<EnforcementTier>,
+    pub exempt_users: HashSet<UserId>,
+    pub exempt_roles: HashSet<RoleId>,
+}
+
+impl Default for EnforcementSettings {
+    fn default() -> EnforcementSettings {
+        EnforcementSettings {
+            enabled: false,
+            dry_run: true,
+            cooldown_secs: 600,
+            tiers: Vec::new(),
+            exempt_users: HashSet::new(),
+            exempt_roles: HashSet::new(),
+        }
+    }
+}
+
+impl EnforcementSettings {
+    /// Adds a tier, replacing any tier with the same threshold.
+    pub fn set_tier(&mut self, tier: EnforcementTier) {
+        self.remove_tier(tier.min_coefficient);
+        self.tiers.push(tier);
+        self.tiers.sort_by(|a, b| {
+            a.min_coefficient
+                .partial_cmp(&b.min_coefficient)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+    }
+
+    pub fn remove_tier(&mut self, min_coefficient: f64) -> bool {
+        let count = self.tiers.len();
+        self.tiers
+            .retain(|tier| tier.min_coefficient != min_coefficient);
+        self.tiers.len() != count
+    }
+
+    /// The tier the crime coefficient falls into, if any.
+    pub fn tier_for(&self, crime_coefficient: f64) -> Option<&EnforcementTier> {
+        self.tiers
+            .iter()
+            .rev()
+            .find(|tier| crime_coefficient >= tier.min_coefficient)
+    }
+
+    pub fn is_exempt(&self, user_id: UserId, roles: &[RoleId]) -> bool {
+        self.exempt_users.contains(&user_id)
+            || roles.iter().any(|role| self.exempt_roles.contains(role))
+    }
+}
+
+/// Tracks when users were last enforced against, to apply the cooldown.
+#[derive(Default)]
+pub struct Dominator {
+    last_enforced: HashMap<(GuildId, UserId), u64>,
+}
+
+impl Dominator {
+    /// Decides which action, if any, to take against a user at `now`.
+    pub fn judge(
+        &mut self,
+        settings: &EnforcementSettings,
+        guild_id: GuildId,
+        user_id: UserId,
+        roles: &[RoleId],
+        crime_coefficient: f64,
+        now: u64,
+    ) -> Option<EnforcementAction> {
+        if !settings.enabled || settings.is_exempt(user_id, roles) {
+            return None;
+        }
+
+        let tier = settings.tier_for(crime_coefficient)?;
+        if let Some(last) = self.last_enforced.get(&(guild_id, user_id)) {
+            if now.saturating_sub(*last) < settings.cooldown_secs {
+                return None;
+            }
+        }
+
+        self.last_enforced.insert((guild_id, user_id), now);
+        Some(tier.action.clone())
+    }
+}
+
+/// Evaluates the enforcement tiers for the author of `msg` after their
+/// crime coefficient was updated, and carries out the resulting action.
+pub async fn enforce(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    guild_id: GuildId,
+    settings: &EnforcementSettings,
+    crime_coefficient: f64,
+) {
+    let action = state.dominator.lock().await.judge(
+        settings,
+        guild_id,
+        msg.author.id,
+        &msg.roles,
+        crime_coefficient,
+        current_timestamp(),
+    );
+    let action = match action {
+        Some(action) => action,
+        None => return,
+    };
+
+    if settings.dry_run {
+        info!(
+            "[dry run] Would {} {} in {} (crime coefficient {:.1})",
+            action,
+            msg.author.tag(),
+            guild_id,
+            crime_coefficient
+        );
+        return;
+    }
+
+    warn!(
+        "Enforcing '{}' on {} in {} (crime coefficient {:.1})",
+        action,
+        msg.author.tag(),
+        guild_id,
+        crime_coefficient
+    );
+    let result = match &action {
+        EnforcementAction::Warn => {
+            let warning = format!(
+                "Your crime coefficient is {:.1}. \
+                Please moderate your behavior.",
+                crime_coefficient
+            );
+            let mut reply = OutgoingMessage::reply(msg, warning);
+            reply.ping = true;
+            transport.send(msg.channel_id, reply).await
+        }
+        EnforcementAction::AssignRole { role } => {
+            transport
+                .add_member_role(guild_id, msg.author.id, *role)
+                .await
+        }
+        EnforcementAction::Timeout { minutes } => {
+            transport
+                .timeout_member(guild_id, msg.author.id, *minutes)
+                .await
+        }
+    };
+
+    match result {
+        Ok(()) => state.webhooks.notify(WebhookEvent::Enforced {
+            guild_id: guild_id.to_string(),
+            user_id: msg.author.id.to_string(),
+            user_tag: msg.author.tag(),
+            action,
+            crime_coefficient,
+        }),
+        Err(why) => error!(
+            "Failed to enforce '{}' on {}: {}",
+            action,
+            msg.author.tag(),
+            why
+        ),
+    }
+}
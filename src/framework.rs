@@ -0,0 +1,558 @@
+use crate::ShardManagerContainer;
+use serenity::framework::standard::{
+    macros::{check, command},
+    Args, CommandOptions, CommandResult, Reason,
+};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use sibyl_system::bot::{BotState, BotStateContainer};
+use sibyl_system::commands;
+use sibyl_system::transport::SerenityTransport;
+use std::sync::Arc;
+
+pub async fn bot_state(ctx: &Context) -> Arc<BotState> {
+    let data_read = ctx.data.read().await;
+    data_read
+        .get::<BotStateContainer>()
+        .expect("Expected BotStateContainer in TypeMap.")
+        .clone()
+}
+
+#[command]
+async fn analyze(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::analyze(&state, &transport, &msg.into(), args).await
+}
+
+#[command]
+#[aliases("pp", "analyze_user", "scan")]
+async fn psycho_pass(
+    ctx: &Context,
+    msg: &Message,
+    args: Args,
+) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::psycho_pass(&state, &transport, &msg.into(), args).await
+}
+
+#[command]
+async fn chart(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::chart(&state, &transport, &msg.into(), args).await
+}
+
+#[command]
+#[aliases("trend")]
+async fn history(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::history(&state, &transport, &msg.into(), args).await
+}
+
+#[command]
+#[only_in(guilds)]
+#[aliases("lb")]
+async fn leaderboard(
+    ctx: &Context,
+    msg: &Message,
+    args: Args,
+) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::leaderboard(&state, &transport, &msg.into(), args).await
+}
+
+#[command]
+#[only_in(guilds)]
+async fn distribution(
+    ctx: &Context,
+    msg: &Message,
+    args: Args,
+) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::distribution(&state, &transport, &msg.into(), args).await
+}
+
+#[command]
+async fn optout(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::optout(&state, &transport, &msg.into(), args).await
+}
+
+#[command]
+async fn optin(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::optin(&state, &transport, &msg.into(), args).await
+}
+
+#[command]
+async fn mydata(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::mydata(&state, &transport, &msg.into(), args).await
+}
+
+#[command]
+async fn forgetme(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::forgetme(&state, &transport, &msg.into(), args).await
+}
+
+#[command]
+async fn stats(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::stats(&state, &transport, &msg.into(), args).await
+}
+
+#[command]
+async fn msg_count(ctx: &Context, msg: &Message) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::msg_count(&state, &transport, &msg.into()).await
+}
+
+#[check]
+#[name = "GuildOwner"]
+async fn guild_owner_check(
+    ctx: &Context,
+    msg: &Message,
+    _: &mut Args,
+    _: &CommandOptions,
+) -> Result<(), Reason> {
+    let guild_id = match msg.guild_id {
+        Some(id) => id,
+        None => return Err(Reason::User("Only usable in a guild".to_string())),
+    };
+
+    match guild_id.to_partial_guild(&ctx.http).await {
+        Ok(guild) if guild.owner_id == msg.author.id => Ok(()),
+        Ok(_) => {
+            Err(Reason::User("Only the guild owner can do this".to_string()))
+        }
+        Err(why) => Err(Reason::Log(format!("Failed to get guild: {}", why))),
+    }
+}
+
+#[command("config")]
+#[only_in(guilds)]
+#[checks(GuildOwner)]
+#[sub_commands(config_show, config_set, config_reset)]
+async fn config(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    msg.reply(&ctx.http, "Config command requires an argument")
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(GuildOwner)]
+#[aliases("show")]
+async fn config_show(
+    ctx: &Context,
+    msg: &Message,
+    args: Args,
+) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::config_show(&state, &transport, &msg.into(), args).await
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(GuildOwner)]
+#[aliases("set")]
+async fn config_set(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::config_set(&state, &transport, &msg.into(), args).await
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(GuildOwner)]
+#[aliases("reset")]
+async fn config_reset(
+    ctx: &Context,
+    msg: &Message,
+    args: Args,
+) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::config_reset(&state, &transport, &msg.into(), args).await
+}
+
+#[command("dominator")]
+#[only_in(guilds)]
+#[checks(GuildOwner)]
+#[sub_commands(
+    dominator_show,
+    dominator_enable,
+    dominator_disable,
+    dominator_dry_run,
+    dominator_cooldown,
+    dominator_tier,
+    dominator_untier,
+    dominator_exempt,
+    dominator_unexempt
+)]
+async fn dominator(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    msg.reply(&ctx.http, "Dominator command requires an argument")
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(GuildOwner)]
+#[aliases("show")]
+async fn dominator_show(
+    ctx: &Context,
+    msg: &Message,
+    args: Args,
+) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::dominator_show(&state, &transport, &msg.into(), args).await
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(GuildOwner)]
+#[aliases("enable")]
+async fn dominator_enable(
+    ctx: &Context,
+    msg: &Message,
+    args: Args,
+) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::dominator_enable(&state, &transport, &msg.into(), args).await
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(GuildOwner)]
+#[aliases("disable")]
+async fn dominator_disable(
+    ctx: &Context,
+    msg: &Message,
+    args: Args,
+) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::dominator_disable(&state, &transport, &msg.into(), args).await
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(GuildOwner)]
+#[aliases("dry_run", "dryrun")]
+async fn dominator_dry_run(
+    ctx: &Context,
+    msg: &Message,
+    args: Args,
+) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::dominator_dry_run(&state, &transport, &msg.into(), args).await
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(GuildOwner)]
+#[aliases("cooldown")]
+async fn dominator_cooldown(
+    ctx: &Context,
+    msg: &Message,
+    args: Args,
+) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::dominator_cooldown(&state, &transport, &msg.into(), args).await
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(GuildOwner)]
+#[aliases("tier")]
+async fn dominator_tier(
+    ctx: &Context,
+    msg: &Message,
+    args: Args,
+) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::dominator_tier(&state, &transport, &msg.into(), args).await
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(GuildOwner)]
+#[aliases("untier")]
+async fn dominator_untier(
+    ctx: &Context,
+    msg: &Message,
+    args: Args,
+) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::dominator_untier(&state, &transport, &msg.into(), args).await
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(GuildOwner)]
+#[aliases("exempt")]
+async fn dominator_exempt(
+    ctx: &Context,
+    msg: &Message,
+    args: Args,
+) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::dominator_exempt(&state, &transport, &msg.into(), args).await
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(GuildOwner)]
+#[aliases("unexempt")]
+async fn dominator_unexempt(
+    ctx: &Context,
+    msg: &Message,
+    args: Args,
+) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::dominator_unexempt(&state, &transport, &msg.into(), args).await
+}
+
+#[command("auditlog")]
+#[only_in(guilds)]
+#[checks(GuildOwner)]
+#[sub_commands(auditlog_show, auditlog_channel, auditlog_boundaries)]
+async fn auditlog(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    msg.reply(&ctx.http, "Audit log command requires an argument")
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(GuildOwner)]
+#[aliases("show")]
+async fn auditlog_show(
+    ctx: &Context,
+    msg: &Message,
+    args: Args,
+) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::auditlog_show(&state, &transport, &msg.into(), args).await
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(GuildOwner)]
+#[aliases("channel")]
+async fn auditlog_channel(
+    ctx: &Context,
+    msg: &Message,
+    args: Args,
+) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::auditlog_channel(&state, &transport, &msg.into(), args).await
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(GuildOwner)]
+#[aliases("boundaries")]
+async fn auditlog_boundaries(
+    ctx: &Context,
+    msg: &Message,
+    args: Args,
+) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::auditlog_boundaries(&state, &transport, &msg.into(), args).await
+}
+
+#[command("scope")]
+#[only_in(guilds)]
+#[checks(GuildOwner)]
+#[sub_commands(scope_show, scope_allow, scope_deny, scope_remove, scope_nsfw)]
+async fn scope(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    msg.reply(&ctx.http, "Scope command requires an argument")
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(GuildOwner)]
+#[aliases("show")]
+async fn scope_show(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::scope_show(&state, &transport, &msg.into(), args).await
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(GuildOwner)]
+#[aliases("allow")]
+async fn scope_allow(
+    ctx: &Context,
+    msg: &Message,
+    args: Args,
+) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::scope_allow(&state, &transport, &msg.into(), args).await
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(GuildOwner)]
+#[aliases("deny")]
+async fn scope_deny(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::scope_deny(&state, &transport, &msg.into(), args).await
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(GuildOwner)]
+#[aliases("remove")]
+async fn scope_remove(
+    ctx: &Context,
+    msg: &Message,
+    args: Args,
+) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::scope_remove(&state, &transport, &msg.into(), args).await
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(GuildOwner)]
+#[aliases("nsfw")]
+async fn scope_nsfw(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::scope_nsfw(&state, &transport, &msg.into(), args).await
+}
+
+#[command("debug")]
+#[sub_commands(debug_user, debug_migrate, test_convertcc, quit)]
+async fn debug(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    msg.reply(&ctx.http, "Debug command requires an argument")
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+#[owners_only]
+#[aliases("user")]
+#[sub_commands(debug_user_dump, debug_user_reset)]
+async fn debug_user(
+    ctx: &Context,
+    msg: &Message,
+    _args: Args,
+) -> CommandResult {
+    msg.reply(&ctx.http, "Debug user command requires an argument")
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+#[owners_only]
+#[aliases("dump")]
+async fn debug_user_dump(
+    ctx: &Context,
+    msg: &Message,
+    args: Args,
+) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::debug_user_dump(&state, &transport, &msg.into(), args).await
+}
+
+#[command]
+#[owners_only]
+#[aliases("reset")]
+async fn debug_user_reset(
+    ctx: &Context,
+    msg: &Message,
+    args: Args,
+) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::debug_user_reset(&state, &transport, &msg.into(), args).await
+}
+
+#[command]
+#[owners_only]
+#[aliases("migrate")]
+async fn debug_migrate(
+    ctx: &Context,
+    msg: &Message,
+    args: Args,
+) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::debug_migrate(&state, &transport, &msg.into(), args).await
+}
+
+#[command]
+#[aliases("convert")]
+async fn test_convertcc(
+    ctx: &Context,
+    msg: &Message,
+    args: Args,
+) -> CommandResult {
+    let state = bot_state(ctx).await;
+    let transport = SerenityTransport::new(ctx.http.clone());
+    commands::test_convertcc(&state, &transport, &msg.into(), args).await
+}
+
+#[command]
+#[owners_only]
+async fn quit(ctx: &Context, msg: &Message) -> CommandResult {
+    let data = ctx.data.read().await;
+
+    if let (Some(state), Some(manager)) = (
+        data.get::<BotStateContainer>(),
+        data.get::<ShardManagerContainer>(),
+    ) {
+        msg.reply(ctx, "Shutting down!").await?;
+        state.database.write().await.flush();
+        state.webhooks.save().await;
+        manager.lock().await.shutdown_all().await;
+    } else {
+        msg.reply(ctx, "There was a problem getting the shard manager")
+            .await?;
+
+        return Ok(());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,96 @@
+//! Rendering of the daily crime coefficient snapshots of a profile.
+
+use crate::userdb::{CoefficientSnapshot, UserProfileData};
+
+pub const DEFAULT_PERIOD_DAYS: u64 = 30;
+/// Longer periods are drawn with one character per week.
+const DAILY_SPARKLINE_DAYS: u64 = 60;
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Parses a period such as `14d` or `2w` into days. The unit is required so
+/// a period cannot be mistaken for a user id.
+pub fn parse_period(period: &str) -> Option<u64> {
+    let (number, unit) = match period.char_indices().last()? {
+        (index, 'd') => (&period[..index], 1u64),
+        (index, 'w') => (&period[..index], 7),
+        _ => return None,
+    };
+    match number.parse::<u64>() {
+        Ok(count) if count > 0 => Some(
+            count
+                .saturating_mul(unit)
+                .min(UserProfileData::MAX_SNAPSHOTS as u64),
+        ),
+        _ => None,
+    }
+}
+
+/// The snapshots of the `days` days up to and including `today`.
+pub fn in_period(
+    snapshots: &[CoefficientSnapshot],
+    today: u64,
+    days: u64,
+) -> Vec<CoefficientSnapshot> {
+    let first = today.saturating_sub(days - 1);
+    snapshots
+        .iter()
+        .filter(|snapshot| snapshot.day >= first && snapshot.day <= today)
+        .copied()
+        .collect()
+}
+
+/// Draws the coefficients of the `days` days up to `today`, one character
+/// per day or per week for long periods. Days without a snapshot are blank.
+pub fn sparkline(
+    snapshots: &[CoefficientSnapshot],
+    today: u64,
+    days: u64,
+) -> String {
+    let step = if days > DAILY_SPARKLINE_DAYS { 7 } else { 1 };
+    let first = today.saturating_sub(days - 1);
+
+    let mut buckets = Vec::new();
+    let mut start = first;
+    while start <= today {
+        let values: Vec<f64> = snapshots
+            .iter()
+            .filter(|s| s.day >= start && s.day < start + step)
+            .map(|s| s.crime_coefficient)
+            .collect();
+        buckets.push(if values.is_empty() {
+            None
+        } else {
+            Some(values.iter().sum::<f64>() / values.len() as f64)
+        });
+        start += step;
+    }
+
+    let known = buckets.iter().flatten();
+    let lowest = known.clone().copied().fold(f64::INFINITY, f64::min);
+    let highest = known.copied().fold(f64::NEG_INFINITY, f64::max);
+    let range = highest - lowest;
+
+    buckets
+        .iter()
+        .map(|bucket| match bucket {
+            None => ' ',
+            Some(_) if range <= 0.0 => SPARKS[SPARKS.len() / 2],
+            Some(value) => {
+                let level =
+                    (value - lowest) / range * (SPARKS.len() - 1) as f64;
+                SPARKS[level.round() as usize]
+            }
+        })
+        .collect()
+}
+
+/// The change of the latest snapshot since the newest snapshot at least a
+/// week older than it.
+pub fn week_delta(snapshots: &[CoefficientSnapshot]) -> Option<f64> {
+    let latest = snapshots.last()?;
+    let week_ago = snapshots
+        .iter()
+        .rev()
+        .find(|snapshot| snapshot.day + 7 <= latest.day)?;
+    Some(latest.crime_coefficient - week_ago.crime_coefficient)
+}
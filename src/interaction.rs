@@ -0,0 +1,104 @@
+//! Application commands and message components, run through the same
+//! implementations as the `$` commands.
+
+use crate::bot::BotState;
+use crate::commands;
+use crate::leaderboard;
+use crate::transport::{IncomingMessage, OutgoingMessage, Transport};
+use serde_json::Value;
+use serenity::framework::standard::{Args, CommandResult, Delimiter};
+use serenity::model::interactions::application_command::{
+    ApplicationCommandInteractionDataOption, ApplicationCommandOptionType,
+};
+use serenity::model::prelude::*;
+use std::collections::HashSet;
+
+/// The application commands only the bot owners can run.
+pub const OWNERS_ONLY: &[&str] =
+    &["debug user-dump", "debug user-reset", "debug migrate"];
+
+/// The full name of an application command, including its subcommand, and
+/// the options given to it.
+pub fn invocation<'a>(
+    name: &str,
+    options: &'a [ApplicationCommandInteractionDataOption],
+) -> (String, &'a [ApplicationCommandInteractionDataOption]) {
+    match options.first() {
+        Some(sub) if sub.kind == ApplicationCommandOptionType::SubCommand => {
+            (format!("{} {}", name, sub.name), &sub.options)
+        }
+        _ => (name.to_string(), options),
+    }
+}
+
+/// Formats the typed options as the arguments of the `$` command.
+pub fn option_args(
+    options: &[ApplicationCommandInteractionDataOption],
+) -> String {
+    let mut args = Vec::new();
+    for option in options {
+        match &option.value {
+            // Flags are passed by name, like `global`.
+            Some(Value::Bool(true)) => args.push(option.name.clone()),
+            // Users, like strings, are given by id.
+            Some(Value::String(value)) => args.push(value.clone()),
+            Some(Value::Number(value)) => args.push(value.to_string()),
+            _ => {}
+        }
+    }
+    args.join(" ")
+}
+
+/// Runs the application command `name` with the arguments `text`, unless
+/// it is only for the bot owners and `msg` was sent by someone else.
+pub async fn run(
+    state: &BotState,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    name: &str,
+    text: &str,
+    owners: &HashSet<UserId>,
+) -> CommandResult {
+    if OWNERS_ONLY.contains(&name) && !owners.contains(&msg.author.id) {
+        transport
+            .reply(msg, "Only the bot owners can do this")
+            .await?;
+        return Ok(());
+    }
+
+    let args = Args::new(text, &[Delimiter::Single(' ')]);
+    match name {
+        "analyze" => commands::analyze(state, transport, msg, args).await,
+        "psycho-pass" => {
+            commands::psycho_pass(state, transport, msg, args).await
+        }
+        "stats" => commands::stats(state, transport, msg, args).await,
+        "debug user-dump" => {
+            commands::debug_user_dump(state, transport, msg, args).await
+        }
+        "debug user-reset" => {
+            commands::debug_user_reset(state, transport, msg, args).await
+        }
+        "debug migrate" => {
+            commands::debug_migrate(state, transport, msg, args).await
+        }
+        "debug convert" => {
+            commands::test_convertcc(state, transport, msg, args).await
+        }
+        unknown => {
+            Err(format!("Unknown application command '{}'", unknown).into())
+        }
+    }
+}
+
+/// The message that replaces the one whose button `custom_id` was clicked
+/// in `guild_id`, if the button is known.
+pub async fn component(
+    state: &BotState,
+    guild_id: Option<GuildId>,
+    custom_id: &str,
+) -> Option<OutgoingMessage> {
+    let (order, page) = leaderboard::parse_page_button(custom_id)?;
+    let guild_id = guild_id?;
+    Some(commands::leaderboard_page(state, guild_id, order, page).await)
+}
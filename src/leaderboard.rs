@@ -0,0 +1,194 @@
+//! Guild-wide rankings and the distribution of crime coefficients.
+
+use crate::transport::{Button, Embed, OutgoingMessage};
+use crate::userdb::{ProfileScope, UserProfilesDatabase};
+use serenity::model::prelude::*;
+
+pub const PAGE_SIZE: usize = 10;
+
+const HISTOGRAM_BUCKET: f64 = 25.0;
+const HISTOGRAM_BUCKETS: usize = 12;
+const HISTOGRAM_WIDTH: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    Highest,
+    Lowest,
+}
+
+impl Order {
+    pub fn parse(name: &str) -> Option<Order> {
+        match name {
+            "highest" => Some(Order::Highest),
+            "lowest" => Some(Order::Lowest),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Order::Highest => "highest",
+            Order::Lowest => "lowest",
+        }
+    }
+}
+
+/// A profile with enough history to be ranked.
+#[derive(Debug, Clone)]
+pub struct Ranked {
+    pub id: UserId,
+    pub tag: String,
+    pub crime_coefficient: f64,
+}
+
+/// The profiles of a scope ordered by crime coefficient.
+#[derive(Debug, Clone)]
+pub struct Ranking {
+    pub ranked: Vec<Ranked>,
+    /// The number of profiles left out for having too little data.
+    pub excluded: usize,
+    pub min_samples: usize,
+}
+
+/// Ranks every profile in `scope` with enough messages for a meaningful
+/// crime coefficient by `order`.
+pub fn rank(
+    db: &UserProfilesDatabase,
+    scope: ProfileScope,
+    order: Order,
+) -> Ranking {
+    let parameters = db.cymatic_parameters(scope);
+    let mut excluded = 0;
+    let mut ranked = Vec::new();
+    for (id, profile) in db.user_profiles(scope) {
+        let cdata = profile.get_cymatic_data(&parameters);
+        if cdata.insufficient_data {
+            excluded += 1;
+            continue;
+        }
+        ranked.push(Ranked {
+            id: *id,
+            tag: profile.user_tag().to_string(),
+            crime_coefficient: cdata.crime_coefficient,
+        });
+    }
+
+    ranked.sort_by(|a, b| {
+        let ordering = a
+            .crime_coefficient
+            .partial_cmp(&b.crime_coefficient)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then(b.id.cmp(&a.id));
+        match order {
+            Order::Highest => ordering.reverse(),
+            Order::Lowest => ordering,
+        }
+    });
+    Ranking {
+        ranked,
+        excluded,
+        min_samples: parameters.min_samples,
+    }
+}
+
+pub fn page_count(entries: usize) -> usize {
+    entries.div_ceil(PAGE_SIZE).max(1)
+}
+
+/// The `custom_id` of the button that shows `page` of a leaderboard.
+pub fn page_button_id(order: Order, page: usize) -> String {
+    format!("leaderboard:{}:{}", order.name(), page)
+}
+
+/// Parses the `custom_id` of a leaderboard page button.
+pub fn parse_page_button(custom_id: &str) -> Option<(Order, usize)> {
+    let mut parts = custom_id.split(':');
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some("leaderboard"), Some(order), Some(page), None) => {
+            Some((Order::parse(order)?, page.parse().ok()?))
+        }
+        _ => None,
+    }
+}
+
+/// Renders `page` (starting at 1) of a leaderboard, with buttons to the
+/// neighbouring pages.
+pub fn leaderboard_page(
+    ranking: &Ranking,
+    order: Order,
+    page: usize,
+) -> OutgoingMessage {
+    let ranked = &ranking.ranked;
+    let pages = page_count(ranked.len());
+    let page = page.clamp(1, pages);
+    let start = (page - 1) * PAGE_SIZE;
+
+    let mut description = String::new();
+    for (position, entry) in
+        ranked.iter().enumerate().skip(start).take(PAGE_SIZE)
+    {
+        description.push_str(&format!(
+            "`{:>3}.` {} - {:.1}\n",
+            position + 1,
+            entry.tag,
+            entry.crime_coefficient
+        ));
+    }
+    if ranked.is_empty() {
+        description.push_str("Nobody has enough messages to be ranked yet.\n");
+    }
+    description.push_str(&format!("\nPage {} of {}", page, pages));
+    if ranking.excluded > 0 {
+        description.push_str(&format!(
+            ", {} user(s) with fewer than {} messages left out",
+            ranking.excluded, ranking.min_samples
+        ));
+    }
+
+    let mut message = OutgoingMessage::new("");
+    message.embed = Some(Embed {
+        title: Some(format!("Crime Coefficients ({})", order.name())),
+        description: Some(description),
+        ..Embed::default()
+    });
+    if pages > 1 {
+        message.buttons = vec![
+            Button {
+                custom_id: page_button_id(order, page.saturating_sub(1)),
+                label: "Previous".to_string(),
+                disabled: page == 1,
+            },
+            Button {
+                custom_id: page_button_id(order, page + 1),
+                label: "Next".to_string(),
+                disabled: page == pages,
+            },
+        ];
+    }
+    message
+}
+
+/// Renders a text histogram of crime coefficients in buckets of
+/// `HISTOGRAM_BUCKET`, with everything above the last bucket counted in it.
+pub fn histogram(coefficients: &[f64]) -> String {
+    let mut counts = [0usize; HISTOGRAM_BUCKETS];
+    for coefficient in coefficients {
+        let bucket = (coefficient.max(0.0) / HISTOGRAM_BUCKET) as usize;
+        counts[bucket.min(HISTOGRAM_BUCKETS - 1)] += 1;
+    }
+
+    let largest = counts.iter().copied().max().unwrap_or(0).max(1);
+    let mut lines = Vec::new();
+    for (bucket, count) in counts.iter().enumerate() {
+        let low = bucket as f64 * HISTOGRAM_BUCKET;
+        let label = if bucket == HISTOGRAM_BUCKETS - 1 {
+            format!("{:>3}+    ", low)
+        } else {
+            format!("{:>3}-{:<4}", low, low + HISTOGRAM_BUCKET)
+        };
+        // Round up so a single profile still shows a bar.
+        let width = (count * HISTOGRAM_WIDTH).div_ceil(largest);
+        lines.push(format!("{}|{} {}", label, "#".repeat(width), count));
+    }
+    lines.join("\n")
+}
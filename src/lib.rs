@@ -0,0 +1,23 @@
+//! The bot logic of the Sibyl System, independent of the Discord connection.
+
+pub mod analyzer;
+pub mod api;
+pub mod auditlog;
+pub mod bot;
+pub mod chart;
+pub mod commands;
+pub mod config;
+#[cfg(feature = "dashboard")]
+pub mod dashboard;
+pub mod dominator;
+pub mod history;
+pub mod interaction;
+pub mod leaderboard;
+pub mod metrics;
+pub mod scope;
+pub mod storage;
+pub mod target;
+pub mod transport;
+pub mod usage;
+pub mod userdb;
+pub mod webhook;
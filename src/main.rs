@@ -1,59 +1,67 @@
-mod commands;
-mod userdb;
+mod framework;
+mod slash;
 
 use serenity::{
     async_trait,
-    client::bridge::gateway::ShardManager,
+    client::bridge::gateway::{GatewayIntents, ShardManager},
     framework::standard::{
         macros::{group, hook},
         StandardFramework,
     },
-    model::{channel::Message, gateway::Ready, id::UserId},
-    prelude::*,
-};
-use std::{
-    collections::{HashMap, HashSet},
-    env,
-    sync::{
-        atomic::{AtomicUsize, Ordering},
-        Arc,
+    model::{
+        channel::{Channel, Message},
+        event::MessageUpdateEvent,
+        gateway::Ready,
+        id::{ChannelId, GuildId, MessageId, UserId},
+        interactions::Interaction,
+        user::User,
     },
+    prelude::*,
 };
+use sibyl_system::bot::{BotState, BotStateContainer};
+use sibyl_system::transport::SerenityTransport;
+use sibyl_system::webhook::Webhooks;
+use sibyl_system::{analyzer, api, chart, config, metrics, storage, userdb};
+use std::{collections::HashSet, env, sync::Arc};
 use tokio::sync::RwLock;
 use tracing::{debug, error, info, warn};
 use tracing_subscriber::{EnvFilter, FmtSubscriber};
 
-struct CommandCounter;
-
-impl TypeMapKey for CommandCounter {
-    type Value = Arc<RwLock<HashMap<String, u64>>>;
-}
-
-struct MessageCount;
-
-impl TypeMapKey for MessageCount {
-    type Value = Arc<AtomicUsize>;
-}
-
 pub struct ShardManagerContainer;
 type ShardManagerLock = Arc<Mutex<ShardManager>>;
 impl TypeMapKey for ShardManagerContainer {
     type Value = ShardManagerLock;
 }
 
-pub struct UserDatabase;
-type DatabaseLock = Arc<RwLock<userdb::UserProfilesDatabase>>;
-impl TypeMapKey for UserDatabase {
-    type Value = DatabaseLock;
+pub struct OwnersContainer;
+impl TypeMapKey for OwnersContainer {
+    type Value = Arc<HashSet<UserId>>;
 }
 
-const COMMAND_PREFIX: &str = "$";
 const TERMINAL_TITLE_ESCAPE_BEGIN: &str = "\x1b]2;";
 const TERMINAL_TITLE_ESCAPE_END: &str = "\x07";
 
-use commands::*;
+use framework::*;
 #[group]
-#[commands(analyze, psycho_pass, stats, msg_count, debug)]
+#[commands(
+    analyze,
+    psycho_pass,
+    chart,
+    history,
+    leaderboard,
+    distribution,
+    optout,
+    optin,
+    mydata,
+    forgetme,
+    stats,
+    msg_count,
+    config,
+    dominator,
+    auditlog,
+    scope,
+    debug
+)]
 struct General;
 
 #[hook]
@@ -64,56 +72,106 @@ async fn before(ctx: &Context, msg: &Message, command_name: &str) -> bool {
         msg.author.tag()
     );
 
-    let counter_lock = {
-        let data_read = ctx.data.read().await;
-        data_read
-            .get::<CommandCounter>()
-            .expect("Expected CommandCounter in TypeMap.")
-            .clone()
-    };
-    {
-        let mut counter = counter_lock.write().await;
-        let entry = counter.entry(command_name.to_string()).or_insert(0);
-        *entry += 1;
-    }
+    let state = bot_state(ctx).await;
+    state.count_command(msg.guild_id, command_name).await;
 
     true
 }
 
-struct Handler;
+#[hook]
+async fn guild_prefix(ctx: &Context, msg: &Message) -> Option<String> {
+    let state = bot_state(ctx).await;
+
+    let scope = userdb::ProfileScope::from_guild(msg.guild_id);
+    let prefix = state.database.read().await.guild_config(scope).prefix;
+    Some(prefix)
+}
+
+struct Handler {
+    forget_departed: bool,
+}
 
 #[async_trait]
 impl EventHandler for Handler {
+    async fn message_update(&self, ctx: Context, event: MessageUpdateEvent) {
+        // Updates without content only add embeds.
+        if let Some(content) = &event.content {
+            bot_state(&ctx)
+                .await
+                .record_edit(event.guild_id, event.id, content)
+                .await;
+        }
+    }
+
+    async fn message_delete(
+        &self,
+        ctx: Context,
+        _channel_id: ChannelId,
+        message_id: MessageId,
+        guild_id: Option<GuildId>,
+    ) {
+        bot_state(&ctx)
+            .await
+            .record_deletions(guild_id, &[message_id])
+            .await;
+    }
+
+    async fn message_delete_bulk(
+        &self,
+        ctx: Context,
+        _channel_id: ChannelId,
+        message_ids: Vec<MessageId>,
+        guild_id: Option<GuildId>,
+    ) {
+        bot_state(&ctx)
+            .await
+            .record_deletions(guild_id, &message_ids)
+            .await;
+    }
+
+    async fn channel_update(&self, ctx: Context, channel: Channel) {
+        bot_state(&ctx).await.forget_channel(channel.id()).await;
+    }
+
+    async fn guild_member_removal(
+        &self,
+        ctx: Context,
+        guild_id: GuildId,
+        user: User,
+    ) {
+        if !self.forget_departed {
+            return;
+        }
+        let state = bot_state(&ctx).await;
+        if state.remove_departed_member(guild_id, user.id).await {
+            info!(
+                "Deleted the profile of {} who left {}",
+                user.tag(),
+                guild_id
+            );
+        }
+    }
+
     async fn message(&self, ctx: Context, msg: Message) {
-        if !msg.content.starts_with(COMMAND_PREFIX) && !msg.author.bot {
-            let sentiment_result = userdb::analyze_message(&msg.content);
-
-            let (db_lock, count) = {
-                let data_read = ctx.data.read().await;
-                (
-                    data_read
-                        .get::<UserDatabase>()
-                        .expect("Expected UserDatabase in TypeMap.")
-                        .clone(),
-                    data_read
-                        .get::<MessageCount>()
-                        .expect("Expected MessageCount in TypeMap.")
-                        .clone(),
-                )
-            };
-            {
-                let mut database = db_lock.write().await;
-                database.add_sentiment_result_for_user(
-                    &msg.author,
-                    sentiment_result,
-                );
+        let state = bot_state(&ctx).await;
+
+        let transport = SerenityTransport::new(ctx.http.clone());
+        state.record_message(&transport, &(&msg).into()).await;
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        match interaction {
+            Interaction::ApplicationCommand(command) => {
+                slash::handle(&ctx, &command).await
+            }
+            Interaction::MessageComponent(component) => {
+                slash::handle_component(&ctx, &component).await
             }
-            count.fetch_add(1, Ordering::SeqCst);
-            info!("Recorded message sentiment for {}", msg.author.tag());
+            _ => {}
         }
     }
 
-    async fn ready(&self, _: Context, ready: Ready) {
+    async fn ready(&self, ctx: Context, ready: Ready) {
         print!(
             "{}{} - Sibyl System Server{}",
             TERMINAL_TITLE_ESCAPE_BEGIN,
@@ -126,6 +184,10 @@ impl EventHandler for Handler {
                 info!("\x1b[1m{}\x1b[m is connected!", ready.user.name);
             },
         );
+
+        if let Err(why) = slash::register(&ctx).await {
+            error!("Failed to register application commands: {}", why);
+        }
     }
 }
 
@@ -142,7 +204,7 @@ fn print_vanity_ascii_header() {
     print!("\x1b[m");
 }
 
-fn initialize_enviroment() -> String {
+fn initialize_enviroment() -> config::Config {
     let _trace =
         tracing::subscriber::set_default(FmtSubscriber::builder().finish());
 
@@ -173,12 +235,33 @@ fn initialize_enviroment() -> String {
         }
     }
 
-    env::var("DISCORD_TOKEN").expect("Expected a token in the environment")
+    let config_path = env::var("SIBYL_CONFIG")
+        .unwrap_or_else(|_| config::CONFIG_FILE.to_string());
+    config::Config::load(&config_path)
+}
+
+fn open_user_database(config: &config::Config) -> userdb::UserProfilesDatabase {
+    let database = &config.database;
+    let storage: Box<dyn storage::ProfileStorage> =
+        match database.backend.as_str() {
+            "sqlite" => Box::new(
+                storage::SqliteStorage::open(&database.sqlite_file)
+                    .expect("Failed to open the SQLite database"),
+            ),
+            "json" => Box::new(storage::JsonFileStorage::new(
+                &database.file,
+                database.backups,
+            )),
+            unknown => panic!("Unknown database backend '{}'", unknown),
+        };
+
+    userdb::UserProfilesDatabase::open(storage, config.guild_defaults())
 }
 
+/// Gets the owners and the id of the application.
 async fn get_owners(
     discord_token: &str,
-) -> Result<HashSet<UserId>, serenity::Error> {
+) -> Result<(HashSet<UserId>, UserId), serenity::Error> {
     let http = serenity::http::Http::new_with_token(discord_token);
     let info = http.get_current_application_info().await?;
     let mut owners = HashSet::new();
@@ -189,54 +272,138 @@ async fn get_owners(
             owners.insert(member.user.id);
         }
     }
-    Ok(owners)
+    Ok((owners, info.id))
 }
 
 #[tokio::main]
 async fn main() {
-    let discord_token = initialize_enviroment();
-    let owners = get_owners(&discord_token)
+    let config = initialize_enviroment();
+
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("--import-json") {
+        let json_path = args.get(2).unwrap_or(&config.database.file);
+        if let Err(why) =
+            storage::import_json(json_path, &config.database.sqlite_file)
+        {
+            error!("Failed to import '{}': {}", json_path, why);
+        }
+        return;
+    }
+
+    match &config.chart.font {
+        Some(font) => {
+            if let Err(why) = chart::load_font(font) {
+                warn!("{}, charts are drawn without labels.", why);
+            }
+        }
+        None => match chart::load_default_font() {
+            Some(font) => info!("Labelling charts with the font '{}'.", font),
+            None => warn!("No font found, charts are drawn without labels."),
+        },
+    }
+
+    let discord_token =
+        env::var("DISCORD_TOKEN").expect("Expected a token in the environment");
+    let (owners, application_id) = get_owners(&discord_token)
         .await
         .expect("Could not access application info");
 
     let framework = StandardFramework::new()
         .configure(|c| {
-            c.owners(owners)
+            c.owners(owners.clone())
                 .with_whitespace(true)
-                .prefix(COMMAND_PREFIX)
+                .prefix("")
+                .dynamic_prefix(guild_prefix)
         })
         .before(before)
         .group(&GENERAL_GROUP);
 
+    let mut intents = GatewayIntents::non_privileged();
+    if config.retention.forget_departed {
+        // Member removals are only sent with the privileged intent.
+        intents |= GatewayIntents::GUILD_MEMBERS;
+    }
+
     let mut client = Client::builder(&discord_token)
-        .event_handler(Handler)
+        .application_id(application_id.0)
+        .intents(intents)
+        .event_handler(Handler {
+            forget_departed: config.retention.forget_departed,
+        })
         .framework(framework)
         .await
         .expect("Error creating client");
 
-    let database = Arc::new(RwLock::new(
-        userdb::UserProfilesDatabase::try_create_from_disk(),
-    ));
+    let database = Arc::new(RwLock::new(open_user_database(&config)));
+    let state = Arc::new(
+        BotState::new(
+            database,
+            analyzer::create_analyzer(
+                &config.analysis.backend,
+                config.analysis.lexicon.as_deref(),
+            ),
+        )
+        .with_webhooks(Webhooks::open(config.webhooks.clone())),
+    );
     {
         let mut data = client.data.write().await;
 
-        data.insert::<CommandCounter>(Arc::new(
-            RwLock::new(HashMap::default()),
-        ));
-        data.insert::<MessageCount>(Arc::new(AtomicUsize::new(0)));
         data.insert::<ShardManagerContainer>(client.shard_manager.clone());
-        data.insert::<UserDatabase>(database.clone());
+        data.insert::<OwnersContainer>(Arc::new(owners));
+        data.insert::<BotStateContainer>(state.clone());
+    }
+
+    if let Some(listen) = &config.metrics.listen {
+        match listen.parse() {
+            Ok(addr) => {
+                tokio::spawn(metrics::serve(addr, state.clone()));
+            }
+            Err(why) => error!("Invalid metrics address '{}': {}", listen, why),
+        }
+    }
+
+    if let Some(listen) = &config.api.listen {
+        match (listen.parse(), config.api.token.clone()) {
+            (Ok(addr), Some(token)) if !token.is_empty() => {
+                tokio::spawn(api::serve(addr, state.clone(), token));
+            }
+            (Ok(_), _) => error!("The API needs a token, not serving it."),
+            (Err(why), _) => {
+                error!("Invalid API address '{}': {}", listen, why)
+            }
+        }
+    }
+
+    if let Some(listen) = &config.dashboard.listen {
+        start_dashboard(listen, config.dashboard.token.clone(), state.clone());
+    }
+
+    if !config.webhooks.endpoints.is_empty() {
+        tokio::spawn(state.webhooks.clone().run());
     }
 
-    tokio::spawn(bg_worker(database.clone(), client.shard_manager.clone()));
+    tokio::spawn(bg_worker(
+        state,
+        client.shard_manager.clone(),
+        config.flush_interval(),
+        config.retention.clone(),
+    ));
 
     if let Err(why) = client.start().await {
         error!("Client error: {:?}", why);
     }
 }
 
-async fn bg_worker(database: DatabaseLock, shard_manager: ShardManagerLock) {
-    let duration = std::time::Duration::from_millis(60000);
+async fn bg_worker(
+    state: Arc<BotState>,
+    shard_manager: ShardManagerLock,
+    duration: std::time::Duration,
+    retention: config::RetentionConfig,
+) {
+    let sweep_interval =
+        std::time::Duration::from_secs(retention.sweep_interval_secs);
+    let mut last_sweep = std::time::Instant::now();
+    let database = &state.database;
     loop {
         match tokio::time::timeout(duration, tokio::signal::ctrl_c()).await {
             Ok(Ok(_)) => {
@@ -249,10 +416,71 @@ async fn bg_worker(database: DatabaseLock, shard_manager: ShardManagerLock) {
             }
             Err(_) => {
                 debug!("{:?} elapsed, flushing database to disk.", duration);
-                database.write().await.to_disk();
+                if last_sweep.elapsed() >= sweep_interval {
+                    last_sweep = std::time::Instant::now();
+                    let summary = state
+                        .apply_retention(
+                            &retention,
+                            userdb::current_timestamp(),
+                        )
+                        .await;
+                    info!("Retention sweep deleted {}.", summary);
+                }
+                let mut database = database.write().await;
+                let snapshots = database.take_snapshots();
+                if snapshots > 0 {
+                    info!("Took {} daily coefficient snapshot(s).", snapshots);
+                }
+                let started = std::time::Instant::now();
+                database.flush();
+                state.metrics.flush_duration.observe(started.elapsed());
+                if let Some(size) = database.storage_size() {
+                    state
+                        .metrics
+                        .database_size_bytes
+                        .store(size, std::sync::atomic::Ordering::Relaxed);
+                }
+                drop(database);
+                record_shard_latency(&state, &shard_manager).await;
             }
         }
     }
-    database.write().await.to_disk();
+    database.write().await.flush();
+    state.webhooks.save().await;
     shard_manager.lock().await.shutdown_all().await;
 }
+
+#[cfg(feature = "dashboard")]
+fn start_dashboard(listen: &str, token: Option<String>, state: Arc<BotState>) {
+    let token = match token.filter(|token| !token.is_empty()) {
+        Some(token) => token,
+        None => {
+            error!("The dashboard needs a token, not serving it.");
+            return;
+        }
+    };
+    match listen.parse() {
+        Ok(addr) => {
+            tokio::spawn(sibyl_system::dashboard::serve(addr, state, token));
+        }
+        Err(why) => error!("Invalid dashboard address '{}': {}", listen, why),
+    }
+}
+
+#[cfg(not(feature = "dashboard"))]
+fn start_dashboard(_: &str, _: Option<String>, _: Arc<BotState>) {
+    warn!("Built without the dashboard feature, not serving the dashboard.");
+}
+
+async fn record_shard_latency(
+    state: &BotState,
+    shard_manager: &ShardManagerLock,
+) {
+    let manager = shard_manager.lock().await;
+    let runners = manager.runners.lock().await;
+    for (id, runner) in runners.iter() {
+        if let Some(latency) = runner.latency {
+            state.metrics.set_shard_latency(id.0, latency);
+        }
+    }
+}
@@ -0,0 +1,225 @@
+//! Prometheus metrics of the bot, served over HTTP at `/metrics`.
+
+use crate::bot::BotState;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{error, info};
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Upper bounds in seconds of the histogram buckets.
+const BUCKETS: [f64; 12] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+];
+
+/// A histogram of durations.
+#[derive(Debug, Default)]
+pub struct Histogram {
+    buckets: [AtomicU64; BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        if let Some(index) = BUCKETS.iter().position(|le| seconds <= *le) {
+            self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        header(out, name, help, "histogram");
+        let mut cumulative = 0;
+        for (le, bucket) in BUCKETS.iter().zip(self.buckets.iter()) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(
+                out,
+                "{}_bucket{{le=\"{}\"}} {}",
+                name, le, cumulative
+            );
+        }
+        let _ =
+            writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, self.count());
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1e6;
+        let _ = writeln!(out, "{}_sum {}", name, sum);
+        let _ = writeln!(out, "{}_count {}", name, self.count());
+    }
+}
+
+/// Everything measured since the bot started. Counts that have to survive
+/// restarts are kept in the database instead.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub messages_analyzed: AtomicU64,
+    pub analysis_latency: Histogram,
+    commands: Mutex<BTreeMap<String, u64>>,
+    pub flush_duration: Histogram,
+    pub database_size_bytes: AtomicU64,
+    shard_latency: Mutex<BTreeMap<u64, Duration>>,
+}
+
+impl Metrics {
+    pub fn count_command(&self, command_name: &str) {
+        let mut commands = self.commands.lock().unwrap();
+        *commands.entry(command_name.to_string()).or_insert(0) += 1;
+    }
+
+    /// Records the latest heartbeat latency of a shard.
+    pub fn set_shard_latency(&self, shard: u64, latency: Duration) {
+        self.shard_latency.lock().unwrap().insert(shard, latency);
+    }
+
+    /// Renders the metrics in the Prometheus text format.
+    pub fn render(&self, profiles: usize) -> String {
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "sibyl_messages_analyzed_total",
+            "Messages whose sentiment was recorded.",
+            "counter",
+        );
+        let _ = writeln!(
+            out,
+            "sibyl_messages_analyzed_total {}",
+            self.messages_analyzed.load(Ordering::Relaxed)
+        );
+
+        self.analysis_latency.render(
+            &mut out,
+            "sibyl_analysis_duration_seconds",
+            "Time taken to analyze a message.",
+        );
+
+        header(
+            &mut out,
+            "sibyl_commands_total",
+            "Command invocations by name.",
+            "counter",
+        );
+        for (command, count) in self.commands.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "sibyl_commands_total{{command=\"{}\"}} {}",
+                escape(command),
+                count
+            );
+        }
+
+        self.flush_duration.render(
+            &mut out,
+            "sibyl_database_flush_duration_seconds",
+            "Time taken to flush the database to storage.",
+        );
+
+        header(
+            &mut out,
+            "sibyl_database_size_bytes",
+            "Size of the database storage after the last flush.",
+            "gauge",
+        );
+        let _ = writeln!(
+            out,
+            "sibyl_database_size_bytes {}",
+            self.database_size_bytes.load(Ordering::Relaxed)
+        );
+
+        header(
+            &mut out,
+            "sibyl_shard_latency_seconds",
+            "Gateway heartbeat latency by shard.",
+            "gauge",
+        );
+        for (shard, latency) in self.shard_latency.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "sibyl_shard_latency_seconds{{shard=\"{}\"}} {}",
+                shard,
+                latency.as_secs_f64()
+            );
+        }
+
+        header(
+            &mut out,
+            "sibyl_profiles",
+            "User profiles in the database.",
+            "gauge",
+        );
+        let _ = writeln!(out, "sibyl_profiles {}", profiles);
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Escapes a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Answers a request to the metrics server.
+pub async fn handle(
+    state: &BotState,
+    request: Request<Body>,
+) -> Response<Body> {
+    if request.method() != Method::GET || request.uri().path() != "/metrics" {
+        let mut response = Response::new(Body::from("Not found\n"));
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    }
+
+    let profiles = state.database.read().await.profile_count();
+    let mut response =
+        Response::new(Body::from(state.metrics.render(profiles)));
+    response.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static(CONTENT_TYPE),
+    );
+    response
+}
+
+/// Serves the metrics at `addr` until the program exits.
+pub async fn serve(addr: SocketAddr, state: Arc<BotState>) {
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let state = state.clone();
+                async move { Ok::<_, Infallible>(handle(&state, request).await) }
+            }))
+        }
+    });
+
+    let server = match Server::try_bind(&addr) {
+        Ok(builder) => builder.serve(make_service),
+        Err(why) => {
+            error!("Failed to serve metrics on {}: {}", addr, why);
+            return;
+        }
+    };
+    info!("Serving metrics on http://{}/metrics", addr);
+    if let Err(why) = server.await {
+        error!("Metrics server error: {}", why);
+    }
+}
@@ -0,0 +1,154 @@
+//! Which channels and members of a guild have their messages analyzed.
+
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+use std::collections::HashSet;
+
+/// The category and NSFW flag of a guild channel.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChannelInfo {
+    pub category: Option<ChannelId>,
+    pub nsfw: bool,
+}
+
+/// What a scope rule applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScopeTarget {
+    Channel(ChannelId),
+    Category(ChannelId),
+    Role(RoleId),
+}
+
+impl ScopeTarget {
+    /// Parses `channel <channel>`, `category <id>` or `role <role>`.
+    pub fn parse(kind: &str, id: &str) -> Option<ScopeTarget> {
+        match kind {
+            "channel" => id.parse().ok().map(ScopeTarget::Channel),
+            "category" => id.parse().ok().map(ScopeTarget::Category),
+            "role" => id.parse().ok().map(ScopeTarget::Role),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ScopeTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScopeTarget::Channel(id) => write!(f, "{}", id.mention()),
+            ScopeTarget::Category(id) => write!(f, "category {}", id),
+            ScopeTarget::Role(id) => write!(f, "{}", id.mention()),
+        }
+    }
+}
+
+/// Allow and deny lists of channels, categories and roles. A message is
+/// analyzed when nothing about it is denied and, for each kind with an allow
+/// list, it matches that list.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ScopeSettings {
+    pub allowed_channels: HashSet<ChannelId>,
+    pub denied_channels: HashSet<ChannelId>,
+    pub allowed_categories: HashSet<ChannelId>,
+    pub denied_categories: HashSet<ChannelId>,
+    pub allowed_roles: HashSet<RoleId>,
+    pub denied_roles: HashSet<RoleId>,
+    /// Whether messages in NSFW channels are analyzed.
+    pub nsfw: bool,
+}
+
+impl Default for ScopeSettings {
+    fn default() -> ScopeSettings {
+        ScopeSettings {
+            allowed_channels: HashSet::new(),
+            denied_channels: HashSet::new(),
+            allowed_categories: HashSet::new(),
+            denied_categories: HashSet::new(),
+            allowed_roles: HashSet::new(),
+            denied_roles: HashSet::new(),
+            nsfw: true,
+        }
+    }
+}
+
+impl ScopeSettings {
+    /// Whether deciding on a message requires the category or NSFW flag of
+    /// its channel.
+    pub fn needs_channel_info(&self) -> bool {
+        !self.nsfw
+            || !self.allowed_categories.is_empty()
+            || !self.denied_categories.is_empty()
+    }
+
+    /// Whether a message in `channel_id` by a member with `roles` is
+    /// analyzed. `info` may only be left out if `needs_channel_info` is
+    /// false.
+    pub fn allows(
+        &self,
+        channel_id: ChannelId,
+        info: Option<ChannelInfo>,
+        roles: &[RoleId],
+    ) -> bool {
+        let info = info.unwrap_or_default();
+        let category = info.category;
+        let in_category = |set: &HashSet<ChannelId>| {
+            category.is_some_and(|category| set.contains(&category))
+        };
+
+        if (info.nsfw && !self.nsfw)
+            || self.denied_channels.contains(&channel_id)
+            || in_category(&self.denied_categories)
+            || roles.iter().any(|role| self.denied_roles.contains(role))
+        {
+            return false;
+        }
+
+        let channel_listed = !self.allowed_channels.is_empty()
+            || !self.allowed_categories.is_empty();
+        if channel_listed
+            && !self.allowed_channels.contains(&channel_id)
+            && !in_category(&self.allowed_categories)
+        {
+            return false;
+        }
+        self.allowed_roles.is_empty()
+            || roles.iter().any(|role| self.allowed_roles.contains(role))
+    }
+
+    /// Adds `target` to its allow list, taking it off its deny list.
+    pub fn allow(&mut self, target: ScopeTarget) {
+        self.remove(target);
+        match target {
+            ScopeTarget::Channel(id) => self.allowed_channels.insert(id),
+            ScopeTarget::Category(id) => self.allowed_categories.insert(id),
+            ScopeTarget::Role(id) => self.allowed_roles.insert(id),
+        };
+    }
+
+    /// Adds `target` to its deny list, taking it off its allow list.
+    pub fn deny(&mut self, target: ScopeTarget) {
+        self.remove(target);
+        match target {
+            ScopeTarget::Channel(id) => self.denied_channels.insert(id),
+            ScopeTarget::Category(id) => self.denied_categories.insert(id),
+            ScopeTarget::Role(id) => self.denied_roles.insert(id),
+        };
+    }
+
+    /// Takes `target` off both lists, returning whether it was on either.
+    pub fn remove(&mut self, target: ScopeTarget) -> bool {
+        match target {
+            ScopeTarget::Channel(id) => {
+                self.allowed_channels.remove(&id)
+                    | self.denied_channels.remove(&id)
+            }
+            ScopeTarget::Category(id) => {
+                self.allowed_categories.remove(&id)
+                    | self.denied_categories.remove(&id)
+            }
+            ScopeTarget::Role(id) => {
+                self.allowed_roles.remove(&id) | self.denied_roles.remove(&id)
+            }
+        }
+    }
+}
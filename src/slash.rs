@@ -0,0 +1,329 @@
+use crate::framework::bot_state;
+use crate::OwnersContainer;
+use serenity::async_trait;
+use serenity::builder::CreateInteractionResponseFollowup;
+use serenity::http::AttachmentType;
+use serenity::model::interactions::application_command::{
+    ApplicationCommand, ApplicationCommandInteraction,
+    ApplicationCommandOptionType,
+};
+use serenity::model::interactions::message_component::MessageComponentInteraction;
+use serenity::model::interactions::{
+    InteractionApplicationCommandCallbackDataFlags, InteractionResponseType,
+};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use sibyl_system::interaction;
+use sibyl_system::scope::ChannelInfo;
+use sibyl_system::transport::{
+    Attachment, Button, IncomingMessage, MemberInfo, OutgoingMessage,
+    SerenityTransport, Transport, TransportResult,
+};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::{error, info};
+
+/// Registers the application commands, replacing any registered before.
+pub async fn register(ctx: &Context) -> serenity::Result<()> {
+    let registered = ApplicationCommand::set_global_application_commands(
+        &ctx.http,
+        |commands| {
+            commands
+                .create_application_command(|command| {
+                    command
+                        .name("analyze")
+                        .description("Run sentiment analysis on some text")
+                        .create_option(|option| {
+                            option
+                                .name("text")
+                                .description("The text to analyze")
+                                .kind(ApplicationCommandOptionType::String)
+                                .required(true)
+                        })
+                })
+                .create_application_command(|command| {
+                    command
+                        .name("psycho-pass")
+                        .description("Scan the Psycho-Pass of a user")
+                        .create_option(|option| {
+                            option
+                                .name("user")
+                                .description("The user to scan")
+                                .kind(ApplicationCommandOptionType::User)
+                                .required(true)
+                        })
+                        .create_option(|option| {
+                            option
+                                .name("global")
+                                .description("Combine the user's history from all servers")
+                                .kind(ApplicationCommandOptionType::Boolean)
+                        })
+                })
+                .create_application_command(|command| {
+                    command
+                        .name("stats")
+                        .description("Show message and command counts")
+                })
+                .create_application_command(|command| {
+                    command
+                        .name("debug")
+                        .description("Debugging tools")
+                        .create_option(|option| {
+                            option
+                                .name("user-dump")
+                                .description("Dump the stored profile of a user")
+                                .kind(ApplicationCommandOptionType::SubCommand)
+                                .create_sub_option(|sub| {
+                                    sub.name("user")
+                                        .description("The user to dump")
+                                        .kind(ApplicationCommandOptionType::User)
+                                        .required(true)
+                                })
+                        })
+                        .create_option(|option| {
+                            option
+                                .name("user-reset")
+                                .description("Delete the history of a user")
+                                .kind(ApplicationCommandOptionType::SubCommand)
+                                .create_sub_option(|sub| {
+                                    sub.name("user")
+                                        .description("The user to reset")
+                                        .kind(ApplicationCommandOptionType::User)
+                                        .required(true)
+                                })
+                        })
+                        .create_option(|option| {
+                            option
+                                .name("migrate")
+                                .description("Assign profiles without a guild to a guild")
+                                .kind(ApplicationCommandOptionType::SubCommand)
+                                .create_sub_option(|sub| {
+                                    sub.name("guild")
+                                        .description("The guild id, this guild by default")
+                                        .kind(ApplicationCommandOptionType::String)
+                                })
+                        })
+                        .create_option(|option| {
+                            option
+                                .name("convert")
+                                .description("Convert a compound score to a crime coefficient")
+                                .kind(ApplicationCommandOptionType::SubCommand)
+                                .create_sub_option(|sub| {
+                                    sub.name("value")
+                                        .description("A compound score from -1 to 1")
+                                        .kind(ApplicationCommandOptionType::Number)
+                                        .required(true)
+                                })
+                        })
+                })
+        },
+    )
+    .await?;
+
+    info!("Registered {} application command(s)", registered.len());
+    Ok(())
+}
+
+/// Answers the interaction, so every reply of a command is ephemeral.
+struct InteractionTransport<'a> {
+    interaction: &'a ApplicationCommandInteraction,
+    http: SerenityTransport,
+    ctx: &'a Context,
+    responded: AtomicBool,
+}
+
+#[async_trait]
+impl Transport for InteractionTransport<'_> {
+    async fn send(
+        &self,
+        channel_id: ChannelId,
+        message: OutgoingMessage,
+    ) -> TransportResult<()> {
+        if channel_id != self.interaction.channel_id {
+            return self.http.send(channel_id, message).await;
+        }
+
+        let flags = InteractionApplicationCommandCallbackDataFlags::EPHEMERAL;
+        let first = !self.responded.swap(true, Ordering::SeqCst);
+        if first && message.attachments.is_empty() {
+            self.interaction
+                .create_interaction_response(&self.ctx.http, |r| {
+                    r.kind(InteractionResponseType::ChannelMessageWithSource)
+                        .interaction_response_data(|d| {
+                            d.content(&message.content).flags(flags.clone());
+                            if let Some(embed) = &message.embed {
+                                d.create_embed(|e| embed.build(e));
+                            }
+                            d.components(|c| {
+                                Button::build_row(&message.buttons, c)
+                            })
+                        })
+                })
+                .await?;
+            return Ok(());
+        }
+
+        if first {
+            // Files can only be uploaded with a followup, which replaces
+            // the deferred response.
+            self.interaction
+                .create_interaction_response(&self.ctx.http, |r| {
+                    r.kind(
+                        InteractionResponseType::DeferredChannelMessageWithSource,
+                    )
+                    .interaction_response_data(|d| d.flags(flags.clone()))
+                })
+                .await?;
+        }
+        let mut followup = CreateInteractionResponseFollowup::default();
+        followup.content(&message.content).flags(flags);
+        if let Some(embed) = &message.embed {
+            followup.create_embed(|e| embed.build(e));
+        }
+        followup.components(|c| Button::build_row(&message.buttons, c));
+        let map = serenity::utils::hashmap_to_json_map(followup.0);
+        let files: Vec<AttachmentType> = message
+            .attachments
+            .iter()
+            .map(Attachment::to_serenity)
+            .collect();
+        // Followups are sent like the messages of a webhook.
+        self.ctx
+            .http
+            .execute_webhook_with_files(
+                self.interaction.application_id.0,
+                &self.interaction.token,
+                true,
+                files,
+                map,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn add_member_role(
+        &self,
+        guild_id: GuildId,
+        user_id: UserId,
+        role_id: RoleId,
+    ) -> TransportResult<()> {
+        self.http.add_member_role(guild_id, user_id, role_id).await
+    }
+
+    async fn timeout_member(
+        &self,
+        guild_id: GuildId,
+        user_id: UserId,
+        minutes: u64,
+    ) -> TransportResult<()> {
+        self.http.timeout_member(guild_id, user_id, minutes).await
+    }
+
+    async fn search_members(
+        &self,
+        guild_id: GuildId,
+        query: &str,
+    ) -> TransportResult<Vec<MemberInfo>> {
+        self.http.search_members(guild_id, query).await
+    }
+
+    async fn direct_message_channel(
+        &self,
+        user_id: UserId,
+    ) -> TransportResult<ChannelId> {
+        self.http.direct_message_channel(user_id).await
+    }
+
+    async fn channel_info(
+        &self,
+        channel_id: ChannelId,
+    ) -> TransportResult<ChannelInfo> {
+        self.http.channel_info(channel_id).await
+    }
+}
+
+/// Runs an application command through the same implementation as its `$`
+/// counterpart.
+pub async fn handle(
+    ctx: &Context,
+    interaction: &ApplicationCommandInteraction,
+) {
+    let data = &interaction.data;
+    let (name, options) = interaction::invocation(&data.name, &data.options);
+    let text = interaction::option_args(options);
+    info!(
+        "Running application command '{}' invoked by '{}'",
+        name,
+        interaction.user.tag()
+    );
+
+    let state = bot_state(ctx).await;
+    state.count_command(interaction.guild_id, &name).await;
+    let transport = InteractionTransport {
+        interaction,
+        http: SerenityTransport::new(ctx.http.clone()),
+        ctx,
+        responded: AtomicBool::new(false),
+    };
+    let msg = IncomingMessage {
+        id: MessageId(interaction.id.0),
+        channel_id: interaction.channel_id,
+        guild_id: interaction.guild_id,
+        author: interaction.user.clone(),
+        roles: interaction
+            .member
+            .as_ref()
+            .map(|member| member.roles.clone())
+            .unwrap_or_default(),
+        content: format!("/{} {}", name, text),
+        timestamp: chrono::Utc::now().to_rfc3339(),
+        referenced_author: None,
+    };
+    let owners = {
+        let data_read = ctx.data.read().await;
+        data_read
+            .get::<OwnersContainer>()
+            .expect("Expected OwnersContainer in TypeMap.")
+            .clone()
+    };
+
+    let result =
+        interaction::run(&state, &transport, &msg, &name, &text, &owners).await;
+    if let Err(why) = result {
+        error!("Application command '{}' returned error {:?}", name, why);
+    }
+}
+
+/// Answers a click on the buttons of a message by replacing the message.
+pub async fn handle_component(
+    ctx: &Context,
+    interaction: &MessageComponentInteraction,
+) {
+    let custom_id = &interaction.data.custom_id;
+    let state = bot_state(ctx).await;
+    let message =
+        match interaction::component(&state, interaction.guild_id, custom_id)
+            .await
+        {
+            Some(message) => message,
+            None => {
+                error!("Unknown component '{}'", custom_id);
+                return;
+            }
+        };
+    let result = interaction
+        .create_interaction_response(&ctx.http, |r| {
+            r.kind(InteractionResponseType::UpdateMessage)
+                .interaction_response_data(|d| {
+                    d.content(&message.content);
+                    if let Some(embed) = &message.embed {
+                        d.create_embed(|e| embed.build(e));
+                    }
+                    d.components(|c| Button::build_row(&message.buttons, c))
+                })
+        })
+        .await;
+
+    if let Err(why) = result {
+        error!("Failed to update '{}': {}", custom_id, why);
+    }
+}
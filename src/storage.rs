@@ -0,0 +1,626 @@
+use crate::userdb::{
+    CoefficientSnapshot, DatabaseContents, ProfileScope, SentimentResult,
+    UserProfileData,
+};
+use rusqlite::{params, Connection};
+use serenity::model::id::{GuildId, MessageId, UserId};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::{error, info, warn};
+
+pub type StorageResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Changes to the database that still have to be persisted.
+#[derive(Debug, Default)]
+pub struct PendingChanges {
+    pub profiles: HashSet<(ProfileScope, UserId)>,
+    pub guild_settings: HashSet<GuildId>,
+    /// Users who opted out or back in.
+    pub opted_out: HashSet<UserId>,
+    /// The days whose usage counts changed.
+    pub usage: HashSet<(ProfileScope, u64)>,
+}
+
+impl PendingChanges {
+    pub fn is_empty(&self) -> bool {
+        self.profiles.is_empty()
+            && self.guild_settings.is_empty()
+            && self.opted_out.is_empty()
+            && self.usage.is_empty()
+    }
+}
+
+/// Where the user profiles database is persisted.
+pub trait ProfileStorage: Send + Sync {
+    /// Describes the storage location for log messages.
+    fn name(&self) -> String;
+
+    /// The size in bytes of what is stored, if known.
+    fn size(&self) -> Option<u64> {
+        None
+    }
+
+    /// Loads everything stored, or `None` if nothing has been stored yet.
+    fn load(&mut self) -> StorageResult<Option<DatabaseContents>>;
+
+    /// Called for every new sentiment result after it was added to
+    /// `profile`. Returns whether it was persisted, otherwise the profile is
+    /// written on the next save.
+    fn record_sentiment(
+        &mut self,
+        _scope: ProfileScope,
+        _user_id: UserId,
+        _profile: &UserProfileData,
+        _result: &SentimentResult,
+    ) -> StorageResult<bool> {
+        Ok(false)
+    }
+
+    /// Persists the `changes`. Profiles missing from `contents` have been
+    /// deleted.
+    fn save(
+        &mut self,
+        contents: &DatabaseContents,
+        changes: &PendingChanges,
+    ) -> StorageResult<()>;
+}
+
+/// Stores the whole database as a single JSON document.
+///
+/// The file is replaced atomically on every save and the previous `backups`
+/// versions are kept next to it as `<path>.1` (newest) to `<path>.<backups>`.
+pub struct JsonFileStorage {
+    path: String,
+    backups: usize,
+}
+
+impl JsonFileStorage {
+    pub fn new(path: &str, backups: usize) -> JsonFileStorage {
+        JsonFileStorage {
+            path: path.to_string(),
+            backups,
+        }
+    }
+
+    fn backup_path(&self, index: usize) -> String {
+        format!("{}.{}", self.path, index)
+    }
+
+    fn read(path: &str) -> std::io::Result<DatabaseContents> {
+        let contents = std::fs::read_to_string(path)?;
+        serde_json::from_str(&contents).map_err(|why| why.into())
+    }
+
+    fn load_newest_backup(&self) -> Option<DatabaseContents> {
+        for index in 1..=self.backups {
+            let path = self.backup_path(index);
+            match JsonFileStorage::read(&path) {
+                Ok(contents) => {
+                    warn!("Recovered the database from backup '{}'.", path);
+                    return Some(contents);
+                }
+                Err(why) if why.kind() == std::io::ErrorKind::NotFound => {}
+                Err(why) => error!("Backup '{}' is unusable: {}", path, why),
+            }
+        }
+        None
+    }
+
+    fn rotate_backups(&self) -> std::io::Result<()> {
+        if self.backups == 0 {
+            return Ok(());
+        }
+
+        for index in (1..self.backups).rev() {
+            let from = self.backup_path(index);
+            if Path::new(&from).exists() {
+                std::fs::rename(&from, self.backup_path(index + 1))?;
+            }
+        }
+        // Copy rather than move so a readable database always exists.
+        if Path::new(&self.path).exists() {
+            std::fs::copy(&self.path, self.backup_path(1))?;
+        }
+        Ok(())
+    }
+}
+
+impl ProfileStorage for JsonFileStorage {
+    fn name(&self) -> String {
+        format!("'{}'", self.path)
+    }
+
+    fn size(&self) -> Option<u64> {
+        std::fs::metadata(&self.path).ok().map(|m| m.len())
+    }
+
+    fn load(&mut self) -> StorageResult<Option<DatabaseContents>> {
+        let why = match JsonFileStorage::read(&self.path) {
+            Ok(contents) => return Ok(Some(contents)),
+            Err(why) => why,
+        };
+
+        if why.kind() == std::io::ErrorKind::NotFound {
+            warn!("Database '{}' is missing, looking for backups.", self.path);
+            return Ok(self.load_newest_backup());
+        }
+
+        error!("Database '{}' is unreadable: {}", self.path, why);
+        match self.load_newest_backup() {
+            Some(contents) => {
+                // Keep the damaged file out of the backup rotation.
+                let corrupt_path = format!("{}.corrupt", self.path);
+                std::fs::rename(&self.path, &corrupt_path)?;
+                warn!("Moved the unreadable database to '{}'.", corrupt_path);
+                Ok(Some(contents))
+            }
+            None => Err(format!(
+                "'{}' is unreadable and no backup could be loaded: {}",
+                self.path, why
+            )
+            .into()),
+        }
+    }
+
+    fn save(
+        &mut self,
+        contents: &DatabaseContents,
+        _changes: &PendingChanges,
+    ) -> StorageResult<()> {
+        let serialized = serde_json::to_string(contents)?;
+
+        let temp_path = format!("{}.tmp", self.path);
+        {
+            let mut file = File::create(&temp_path)?;
+            file.write_all(serialized.as_bytes())?;
+            file.sync_all()?;
+        }
+
+        self.rotate_backups()?;
+        std::fs::rename(&temp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Stores profiles in an embedded SQLite database, writing each sentiment
+/// result as it is recorded.
+pub struct SqliteStorage {
+    path: String,
+    // rusqlite connections cannot be shared between threads on their own.
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    pub fn open(path: &str) -> StorageResult<SqliteStorage> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS profiles (
+                scope TEXT NOT NULL,
+                user_id INTEGER NOT NULL,
+                user_tag TEXT NOT NULL,
+                PRIMARY KEY (scope, user_id)
+            );
+            CREATE TABLE IF NOT EXISTS sentiment (
+                scope TEXT NOT NULL,
+                user_id INTEGER NOT NULL,
+                timestamp INTEGER NOT NULL,
+                negative REAL NOT NULL,
+                neutral REAL NOT NULL,
+                positive REAL NOT NULL,
+                compound REAL NOT NULL,
+                message_id INTEGER
+            );
+            CREATE INDEX IF NOT EXISTS sentiment_by_user
+                ON sentiment (scope, user_id, timestamp);
+            CREATE TABLE IF NOT EXISTS snapshots (
+                scope TEXT NOT NULL,
+                user_id INTEGER NOT NULL,
+                day INTEGER NOT NULL,
+                crime_coefficient REAL NOT NULL,
+                PRIMARY KEY (scope, user_id, day)
+            );
+            CREATE TABLE IF NOT EXISTS guild_settings (
+                guild_id INTEGER PRIMARY KEY,
+                settings TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS opted_out (
+                user_id INTEGER PRIMARY KEY
+            );
+            CREATE TABLE IF NOT EXISTS usage (
+                scope TEXT NOT NULL,
+                day INTEGER NOT NULL,
+                counts TEXT NOT NULL,
+                PRIMARY KEY (scope, day)
+            );",
+        )?;
+        // Databases created before results were tied to their message.
+        let has_message_id = connection
+            .prepare("SELECT message_id FROM sentiment LIMIT 0")
+            .is_ok();
+        if !has_message_id {
+            connection.execute_batch(
+                "ALTER TABLE sentiment ADD COLUMN message_id INTEGER",
+            )?;
+        }
+
+        Ok(SqliteStorage {
+            path: path.to_string(),
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn scope_key(scope: ProfileScope) -> String {
+        match scope {
+            ProfileScope::Guild(id) => id.0.to_string(),
+            ProfileScope::DirectMessages => "dm".to_string(),
+            ProfileScope::Unscoped => "unscoped".to_string(),
+        }
+    }
+
+    fn parse_scope_key(key: &str) -> Option<ProfileScope> {
+        match key {
+            "dm" => Some(ProfileScope::DirectMessages),
+            "unscoped" => Some(ProfileScope::Unscoped),
+            id => id.parse().ok().map(|id| ProfileScope::Guild(GuildId(id))),
+        }
+    }
+
+    fn upsert_profile(
+        connection: &Connection,
+        scope: &str,
+        user_id: UserId,
+        profile: &UserProfileData,
+    ) -> rusqlite::Result<()> {
+        connection.execute(
+            "INSERT INTO profiles (scope, user_id, user_tag)
+                VALUES (?1, ?2, ?3)
+                ON CONFLICT (scope, user_id)
+                DO UPDATE SET user_tag = excluded.user_tag",
+            params![scope, user_id.0 as i64, profile.user_tag()],
+        )?;
+        Ok(())
+    }
+
+    fn upsert_snapshot(
+        connection: &Connection,
+        scope: &str,
+        user_id: UserId,
+        snapshot: &CoefficientSnapshot,
+    ) -> rusqlite::Result<()> {
+        connection.execute(
+            "INSERT OR REPLACE INTO snapshots (scope, user_id, day,
+                crime_coefficient) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                scope,
+                user_id.0 as i64,
+                snapshot.day as i64,
+                snapshot.crime_coefficient
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn insert_sentiment(
+        connection: &Connection,
+        scope: &str,
+        user_id: UserId,
+        result: &SentimentResult,
+    ) -> rusqlite::Result<()> {
+        connection.execute(
+            "INSERT INTO sentiment (scope, user_id, timestamp, negative,
+                neutral, positive, compound, message_id)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                scope,
+                user_id.0 as i64,
+                result.timestamp() as i64,
+                result.negative(),
+                result.neutral(),
+                result.positive(),
+                result.compound(),
+                result.message_id().map(|id| id.0 as i64)
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+impl ProfileStorage for SqliteStorage {
+    fn name(&self) -> String {
+        format!("SQLite database '{}'", self.path)
+    }
+
+    fn size(&self) -> Option<u64> {
+        std::fs::metadata(&self.path).ok().map(|m| m.len())
+    }
+
+    fn load(&mut self) -> StorageResult<Option<DatabaseContents>> {
+        let connection = self.connection.lock().unwrap();
+
+        let mut histories: HashMap<(String, i64), Vec<SentimentResult>> =
+            HashMap::new();
+        let mut statement = connection.prepare(
+            "SELECT scope, user_id, timestamp, negative, neutral, positive,
+                compound, message_id FROM sentiment ORDER BY timestamp, rowid",
+        )?;
+        let mut rows = statement.query(params![])?;
+        while let Some(row) = rows.next()? {
+            let mut result = SentimentResult::new(
+                row.get(3)?,
+                row.get(4)?,
+                row.get(5)?,
+                row.get(6)?,
+            )
+            .recorded_at(row.get::<_, i64>(2)? as u64);
+            if let Some(message_id) = row.get::<_, Option<i64>>(7)? {
+                result = result.for_message(MessageId(message_id as u64));
+            }
+            histories
+                .entry((row.get(0)?, row.get(1)?))
+                .or_default()
+                .push(result);
+        }
+
+        let mut snapshots: HashMap<(String, i64), Vec<CoefficientSnapshot>> =
+            HashMap::new();
+        let mut statement = connection.prepare(
+            "SELECT scope, user_id, day, crime_coefficient FROM snapshots
+                ORDER BY day",
+        )?;
+        let mut rows = statement.query(params![])?;
+        while let Some(row) = rows.next()? {
+            let snapshot = CoefficientSnapshot {
+                day: row.get::<_, i64>(2)? as u64,
+                crime_coefficient: row.get(3)?,
+            };
+            snapshots
+                .entry((row.get(0)?, row.get(1)?))
+                .or_default()
+                .push(snapshot);
+        }
+
+        let mut contents = DatabaseContents::default();
+        let mut found = false;
+        let mut statement = connection
+            .prepare("SELECT scope, user_id, user_tag FROM profiles")?;
+        let mut rows = statement.query(params![])?;
+        while let Some(row) = rows.next()? {
+            found = true;
+            let key: String = row.get(0)?;
+            let user_id: i64 = row.get(1)?;
+            let user_tag: String = row.get(2)?;
+            let scope = SqliteStorage::parse_scope_key(&key)
+                .ok_or_else(|| format!("Unknown profile scope '{}'", key))?;
+
+            let history = histories
+                .remove(&(key.clone(), user_id))
+                .unwrap_or_default();
+            let mut profile = UserProfileData::from_history(&user_tag, history);
+            for snapshot in
+                snapshots.remove(&(key, user_id)).unwrap_or_default()
+            {
+                profile.record_snapshot(snapshot);
+            }
+            contents
+                .scope_as_mut(scope)
+                .insert(UserId(user_id as u64), profile);
+        }
+
+        let mut statement = connection
+            .prepare("SELECT guild_id, settings FROM guild_settings")?;
+        let mut rows = statement.query(params![])?;
+        while let Some(row) = rows.next()? {
+            found = true;
+            let guild_id: i64 = row.get(0)?;
+            let settings: String = row.get(1)?;
+            contents.guild_settings.insert(
+                GuildId(guild_id as u64),
+                serde_json::from_str(&settings)?,
+            );
+        }
+
+        let mut statement =
+            connection.prepare("SELECT user_id FROM opted_out")?;
+        let mut rows = statement.query(params![])?;
+        while let Some(row) = rows.next()? {
+            found = true;
+            let user_id: i64 = row.get(0)?;
+            contents.opted_out.insert(UserId(user_id as u64));
+        }
+
+        let mut statement =
+            connection.prepare("SELECT scope, day, counts FROM usage")?;
+        let mut rows = statement.query(params![])?;
+        while let Some(row) = rows.next()? {
+            found = true;
+            let key: String = row.get(0)?;
+            let day: i64 = row.get(1)?;
+            let counts: String = row.get(2)?;
+            let scope = SqliteStorage::parse_scope_key(&key)
+                .ok_or_else(|| format!("Unknown usage scope '{}'", key))?;
+            *contents.usage.day_mut(scope, day as u64) =
+                serde_json::from_str(&counts)?;
+        }
+
+        Ok(if found { Some(contents) } else { None })
+    }
+
+    fn record_sentiment(
+        &mut self,
+        scope: ProfileScope,
+        user_id: UserId,
+        profile: &UserProfileData,
+        result: &SentimentResult,
+    ) -> StorageResult<bool> {
+        let scope = SqliteStorage::scope_key(scope);
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+
+        SqliteStorage::upsert_profile(&transaction, &scope, user_id, profile)?;
+        SqliteStorage::insert_sentiment(&transaction, &scope, user_id, result)?;
+        if let Some(snapshot) = profile.snapshots().last() {
+            SqliteStorage::upsert_snapshot(
+                &transaction,
+                &scope,
+                user_id,
+                snapshot,
+            )?;
+        }
+        // Drop whatever the profile's ring buffer no longer holds.
+        transaction.execute(
+            "DELETE FROM sentiment WHERE rowid IN (
+                SELECT rowid FROM sentiment WHERE scope = ?1 AND user_id = ?2
+                ORDER BY timestamp DESC, rowid DESC LIMIT -1 OFFSET ?3)",
+            params![scope, user_id.0 as i64, profile.history().len() as i64],
+        )?;
+
+        transaction.commit()?;
+        Ok(true)
+    }
+
+    fn save(
+        &mut self,
+        contents: &DatabaseContents,
+        changes: &PendingChanges,
+    ) -> StorageResult<()> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+
+        for (scope, user_id) in &changes.profiles {
+            let key = SqliteStorage::scope_key(*scope);
+            transaction.execute(
+                "DELETE FROM sentiment WHERE scope = ?1 AND user_id = ?2",
+                params![key, user_id.0 as i64],
+            )?;
+            transaction.execute(
+                "DELETE FROM snapshots WHERE scope = ?1 AND user_id = ?2",
+                params![key, user_id.0 as i64],
+            )?;
+
+            match contents.get_user_profile(*scope, user_id) {
+                Some(profile) => {
+                    SqliteStorage::upsert_profile(
+                        &transaction,
+                        &key,
+                        *user_id,
+                        profile,
+                    )?;
+                    for result in profile.history() {
+                        SqliteStorage::insert_sentiment(
+                            &transaction,
+                            &key,
+                            *user_id,
+                            result,
+                        )?;
+                    }
+                    for snapshot in profile.snapshots() {
+                        SqliteStorage::upsert_snapshot(
+                            &transaction,
+                            &key,
+                            *user_id,
+                            snapshot,
+                        )?;
+                    }
+                }
+                None => {
+                    transaction.execute(
+                        "DELETE FROM profiles WHERE scope = ?1 AND user_id = ?2",
+                        params![key, user_id.0 as i64],
+                    )?;
+                }
+            }
+        }
+
+        for guild_id in &changes.guild_settings {
+            match contents.guild_settings.get(guild_id) {
+                Some(settings) => {
+                    transaction.execute(
+                        "INSERT OR REPLACE INTO guild_settings (guild_id, settings)
+                            VALUES (?1, ?2)",
+                        params![guild_id.0 as i64, serde_json::to_string(settings)?],
+                    )?;
+                }
+                None => {
+                    transaction.execute(
+                        "DELETE FROM guild_settings WHERE guild_id = ?1",
+                        params![guild_id.0 as i64],
+                    )?;
+                }
+            }
+        }
+
+        for (scope, day) in &changes.usage {
+            if let Some(usage) = contents.usage.day(*scope, *day) {
+                transaction.execute(
+                    "INSERT OR REPLACE INTO usage (scope, day, counts)
+                        VALUES (?1, ?2, ?3)",
+                    params![
+                        SqliteStorage::scope_key(*scope),
+                        *day as i64,
+                        serde_json::to_string(usage)?
+                    ],
+                )?;
+            }
+        }
+
+        for user_id in &changes.opted_out {
+            if contents.opted_out.contains(user_id) {
+                transaction.execute(
+                    "INSERT OR IGNORE INTO opted_out (user_id) VALUES (?1)",
+                    params![user_id.0 as i64],
+                )?;
+            } else {
+                transaction.execute(
+                    "DELETE FROM opted_out WHERE user_id = ?1",
+                    params![user_id.0 as i64],
+                )?;
+            }
+        }
+
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+/// Copies every profile of the JSON database at `json_path` into the SQLite
+/// database at `sqlite_path`, replacing profiles that already exist there.
+pub fn import_json(json_path: &str, sqlite_path: &str) -> StorageResult<usize> {
+    let contents = JsonFileStorage::new(json_path, 0)
+        .load()?
+        .ok_or_else(|| format!("'{}' does not exist", json_path))?;
+
+    let mut changes = PendingChanges::default();
+    changes
+        .guild_settings
+        .extend(contents.guild_settings.keys().copied());
+    changes.opted_out.extend(contents.opted_out.iter().copied());
+    for (guild_id, days) in &contents.usage.guilds {
+        let scope = ProfileScope::Guild(*guild_id);
+        changes.usage.extend(days.keys().map(|day| (scope, *day)));
+    }
+    changes.usage.extend(
+        contents
+            .usage
+            .direct_messages
+            .keys()
+            .map(|day| (ProfileScope::DirectMessages, *day)),
+    );
+    for scope in contents.scopes() {
+        if let Some(users) = contents.scope(scope) {
+            changes.profiles.extend(users.keys().map(|id| (scope, *id)));
+        }
+    }
+
+    let mut sqlite = SqliteStorage::open(sqlite_path)?;
+    sqlite.save(&contents, &changes)?;
+    info!(
+        "Imported {} profile(s) from '{}' into '{}'.",
+        changes.profiles.len(),
+        json_path,
+        sqlite_path
+    );
+    Ok(changes.profiles.len())
+}
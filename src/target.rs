@@ -0,0 +1,153 @@
+use crate::transport::{IncomingMessage, MemberInfo, Transport};
+use crate::userdb::{ProfileScope, UserProfilesDatabase};
+use serenity::model::prelude::*;
+use serenity::utils::parse_username;
+use tokio::sync::RwLock;
+
+/// The most candidates listed when a name is ambiguous.
+const MAX_CANDIDATES: usize = 10;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    Found(UserId),
+    /// Several users match, with their tags.
+    Ambiguous(Vec<(UserId, String)>),
+    NotFound,
+}
+
+/// The user a command with no target applies to: the author of the message
+/// replied to, or else the caller.
+pub fn implicit_target(msg: &IncomingMessage) -> UserId {
+    msg.referenced_author
+        .as_ref()
+        .map(|author| author.id)
+        .unwrap_or(msg.author.id)
+}
+
+/// Resolves `query` to a user, trying in order a mention, an ID, a
+/// `name#discriminator`, a guild nickname and finally a fuzzy match on the
+/// tags stored in the database.
+pub async fn resolve_target(
+    database: &RwLock<UserProfilesDatabase>,
+    transport: &dyn Transport,
+    msg: &IncomingMessage,
+    query: &str,
+) -> Target {
+    if let Some(id) = parse_username(query) {
+        return Target::Found(UserId(id));
+    }
+    if let Ok(id) = query.parse::<u64>() {
+        return Target::Found(UserId(id));
+    }
+
+    let scope = ProfileScope::from_guild(msg.guild_id);
+    let members = match msg.guild_id {
+        Some(guild_id) => {
+            // Searching is best effort, the database is still checked.
+            let name = query.split('#').next().unwrap_or(query);
+            transport
+                .search_members(guild_id, name)
+                .await
+                .unwrap_or_default()
+        }
+        None => Vec::new(),
+    };
+    let db = database.read().await;
+
+    if query.contains('#') {
+        let query = query.to_lowercase();
+        let tagged = members
+            .iter()
+            .map(|member| (member.user.id, member.user.tag()))
+            .chain(
+                db.user_profiles(scope)
+                    .map(|(id, profile)| (*id, profile.user_tag().to_string())),
+            )
+            .find(|(_, tag)| tag.to_lowercase() == query);
+        if let Some((id, _)) = tagged {
+            return Target::Found(id);
+        }
+    }
+
+    let nicknamed = candidates(members.iter().filter(|member| {
+        let nick = member.nick.as_deref().unwrap_or_default();
+        nick.eq_ignore_ascii_case(query)
+            || member.user.name.eq_ignore_ascii_case(query)
+    }));
+    if !nicknamed.is_empty() {
+        return choose(nicknamed);
+    }
+
+    let profiles: Vec<(UserId, String)> = db
+        .user_profiles(scope)
+        .map(|(id, profile)| (*id, profile.user_tag().to_string()))
+        .collect();
+    choose(fuzzy_matches(&profiles, query))
+}
+
+fn candidates<'a>(
+    members: impl Iterator<Item = &'a MemberInfo>,
+) -> Vec<(UserId, String)> {
+    members
+        .map(|member| (member.user.id, member.user.tag()))
+        .collect()
+}
+
+fn choose(mut candidates: Vec<(UserId, String)>) -> Target {
+    candidates.sort();
+    candidates.dedup_by_key(|(id, _)| *id);
+    match candidates.len() {
+        0 => Target::NotFound,
+        1 => Target::Found(candidates[0].0),
+        _ => {
+            candidates.truncate(MAX_CANDIDATES);
+            Target::Ambiguous(candidates)
+        }
+    }
+}
+
+/// The tags whose name contains `query`, or if there are none, whose name is
+/// within two edits of it.
+fn fuzzy_matches(
+    profiles: &[(UserId, String)],
+    query: &str,
+) -> Vec<(UserId, String)> {
+    let query = query.to_lowercase();
+    let name_of = |tag: &str| {
+        tag.rsplit_once('#')
+            .map_or(tag, |(name, _)| name)
+            .to_lowercase()
+    };
+
+    let containing: Vec<(UserId, String)> = profiles
+        .iter()
+        .filter(|(_, tag)| name_of(tag).contains(&query))
+        .cloned()
+        .collect();
+    if !containing.is_empty() {
+        return containing;
+    }
+
+    profiles
+        .iter()
+        .filter(|(_, tag)| edit_distance(&name_of(tag), &query) <= 2)
+        .cloned()
+        .collect()
+}
+
+/// The Levenshtein distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(
+                substitution.min(previous[j + 1] + 1).min(current[j] + 1),
+            );
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
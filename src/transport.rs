@@ -0,0 +1,358 @@
+use crate::scope::ChannelInfo;
+use serenity::async_trait;
+use serenity::builder::{CreateComponents, CreateEmbed};
+use serenity::http::{AttachmentType, Http};
+use serenity::model::interactions::message_component::ButtonStyle;
+use serenity::model::prelude::*;
+use serenity::utils::Colour;
+use std::borrow::Cow;
+use std::sync::Arc;
+
+pub type TransportResult<T> =
+    Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Embed {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub colour: Option<(u8, u8, u8)>,
+    /// Name, value and whether the field is inline.
+    pub fields: Vec<(String, String, bool)>,
+    /// An RFC 3339 timestamp.
+    pub timestamp: Option<String>,
+    /// The filename of an attachment shown as the image of the embed.
+    pub image: Option<String>,
+}
+
+impl Embed {
+    /// Fills in a serenity embed builder.
+    pub fn build<'a>(&self, e: &'a mut CreateEmbed) -> &'a mut CreateEmbed {
+        if let Some(title) = &self.title {
+            e.title(title);
+        }
+        if let Some(description) = &self.description {
+            e.description(description);
+        }
+        if let Some((red, green, blue)) = self.colour {
+            e.colour(Colour::from_rgb(red, green, blue));
+        }
+        for (name, value, inline) in self.fields.iter() {
+            e.field(name, value, *inline);
+        }
+        if let Some(timestamp) = &self.timestamp {
+            e.timestamp(timestamp.as_str());
+        }
+        if let Some(image) = &self.image {
+            e.attachment(image);
+        }
+        e
+    }
+
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _, _)| field == name)
+            .map(|(_, value, _)| value.as_str())
+    }
+}
+
+/// A file uploaded with a message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attachment {
+    pub filename: String,
+    pub data: Vec<u8>,
+}
+
+impl Attachment {
+    pub fn to_serenity(&self) -> AttachmentType<'_> {
+        AttachmentType::Bytes {
+            data: Cow::Borrowed(&self.data),
+            filename: self.filename.clone(),
+        }
+    }
+}
+
+/// A button below a message. Clicking it sends `custom_id` back to the bot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Button {
+    pub custom_id: String,
+    pub label: String,
+    pub disabled: bool,
+}
+
+impl Button {
+    /// Fills in a serenity components builder with a row of `buttons`.
+    pub fn build_row<'a>(
+        buttons: &[Button],
+        c: &'a mut CreateComponents,
+    ) -> &'a mut CreateComponents {
+        if buttons.is_empty() {
+            return c;
+        }
+        c.create_action_row(|row| {
+            for button in buttons {
+                row.create_button(|b| {
+                    b.custom_id(&button.custom_id)
+                        .label(&button.label)
+                        .style(ButtonStyle::Secondary)
+                        .disabled(button.disabled)
+                });
+            }
+            row
+        })
+    }
+}
+
+/// A message the bot sends, independent of how it reaches Discord.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OutgoingMessage {
+    pub content: String,
+    pub embed: Option<Embed>,
+    /// The message this one replies to.
+    pub reply_to: Option<MessageId>,
+    /// Whether replying pings the author of `reply_to`.
+    pub ping: bool,
+    pub buttons: Vec<Button>,
+    pub attachments: Vec<Attachment>,
+}
+
+impl OutgoingMessage {
+    pub fn new(content: impl ToString) -> OutgoingMessage {
+        OutgoingMessage {
+            content: content.to_string(),
+            ..OutgoingMessage::default()
+        }
+    }
+
+    pub fn reply(
+        msg: &IncomingMessage,
+        content: impl ToString,
+    ) -> OutgoingMessage {
+        OutgoingMessage {
+            content: content.to_string(),
+            reply_to: Some(msg.id),
+            ..OutgoingMessage::default()
+        }
+    }
+}
+
+/// A guild member found by a search.
+#[derive(Debug, Clone)]
+pub struct MemberInfo {
+    pub user: User,
+    pub nick: Option<String>,
+}
+
+/// A message received by the bot.
+#[derive(Debug, Clone)]
+pub struct IncomingMessage {
+    pub id: MessageId,
+    pub channel_id: ChannelId,
+    pub guild_id: Option<GuildId>,
+    pub author: User,
+    /// The roles of the author, if sent in a guild.
+    pub roles: Vec<RoleId>,
+    pub content: String,
+    /// An RFC 3339 timestamp.
+    pub timestamp: String,
+    /// The author of the message this one replies to.
+    pub referenced_author: Option<User>,
+}
+
+impl IncomingMessage {
+    pub fn link(&self) -> String {
+        self.id.link(self.channel_id, self.guild_id)
+    }
+}
+
+impl From<&Message> for IncomingMessage {
+    fn from(msg: &Message) -> IncomingMessage {
+        IncomingMessage {
+            id: msg.id,
+            channel_id: msg.channel_id,
+            guild_id: msg.guild_id,
+            author: msg.author.clone(),
+            roles: msg
+                .member
+                .as_ref()
+                .map(|member| member.roles.clone())
+                .unwrap_or_default(),
+            content: msg.content.clone(),
+            timestamp: msg.timestamp.to_rfc3339(),
+            referenced_author: msg
+                .referenced_message
+                .as_ref()
+                .map(|referenced| referenced.author.clone()),
+        }
+    }
+}
+
+/// Everything the bot does to Discord goes through a transport, so the bot
+/// logic can run without a connection.
+#[async_trait]
+pub trait Transport: Send + Sync {
+    async fn send(
+        &self,
+        channel_id: ChannelId,
+        message: OutgoingMessage,
+    ) -> TransportResult<()>;
+
+    async fn add_member_role(
+        &self,
+        guild_id: GuildId,
+        user_id: UserId,
+        role_id: RoleId,
+    ) -> TransportResult<()>;
+
+    /// Prevents a member from communicating for `minutes`.
+    async fn timeout_member(
+        &self,
+        guild_id: GuildId,
+        user_id: UserId,
+        minutes: u64,
+    ) -> TransportResult<()>;
+
+    /// The members whose username or nickname starts with `query`.
+    async fn search_members(
+        &self,
+        guild_id: GuildId,
+        query: &str,
+    ) -> TransportResult<Vec<MemberInfo>>;
+
+    /// The channel of the direct messages with a user, opening it if needed.
+    async fn direct_message_channel(
+        &self,
+        user_id: UserId,
+    ) -> TransportResult<ChannelId>;
+
+    /// The category and NSFW flag of a channel.
+    async fn channel_info(
+        &self,
+        channel_id: ChannelId,
+    ) -> TransportResult<ChannelInfo>;
+}
+
+impl dyn Transport + '_ {
+    /// Replies to `msg` in its channel.
+    pub async fn reply(
+        &self,
+        msg: &IncomingMessage,
+        content: impl ToString,
+    ) -> TransportResult<()> {
+        self.send(msg.channel_id, OutgoingMessage::reply(msg, content))
+            .await
+    }
+}
+
+/// Sends everything through the Discord HTTP API.
+pub struct SerenityTransport {
+    http: Arc<Http>,
+}
+
+impl SerenityTransport {
+    pub fn new(http: Arc<Http>) -> SerenityTransport {
+        SerenityTransport { http }
+    }
+}
+
+#[async_trait]
+impl Transport for SerenityTransport {
+    async fn send(
+        &self,
+        channel_id: ChannelId,
+        message: OutgoingMessage,
+    ) -> TransportResult<()> {
+        channel_id
+            .send_message(&self.http, |m| {
+                m.content(&message.content);
+                if let Some(reply_to) = message.reply_to {
+                    m.reference_message((channel_id, reply_to));
+                    m.allowed_mentions(|f| {
+                        f.replied_user(message.ping)
+                            .parse(serenity::builder::ParseValue::Everyone)
+                            .parse(serenity::builder::ParseValue::Users)
+                            .parse(serenity::builder::ParseValue::Roles)
+                    });
+                }
+                if let Some(embed) = &message.embed {
+                    m.embed(|e| embed.build(e));
+                }
+                if !message.buttons.is_empty() {
+                    m.components(|c| Button::build_row(&message.buttons, c));
+                }
+                m.add_files(
+                    message.attachments.iter().map(|a| a.to_serenity()),
+                );
+                m
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn add_member_role(
+        &self,
+        guild_id: GuildId,
+        user_id: UserId,
+        role_id: RoleId,
+    ) -> TransportResult<()> {
+        self.http
+            .add_member_role(guild_id.0, user_id.0, role_id.0)
+            .await?;
+        Ok(())
+    }
+
+    async fn timeout_member(
+        &self,
+        guild_id: GuildId,
+        user_id: UserId,
+        minutes: u64,
+    ) -> TransportResult<()> {
+        let until =
+            chrono::Utc::now() + chrono::Duration::minutes(minutes as i64);
+        let mut map = serde_json::Map::new();
+        map.insert(
+            "communication_disabled_until".to_string(),
+            serde_json::Value::String(until.to_rfc3339()),
+        );
+        self.http.edit_member(guild_id.0, user_id.0, &map).await?;
+        Ok(())
+    }
+
+    async fn search_members(
+        &self,
+        guild_id: GuildId,
+        query: &str,
+    ) -> TransportResult<Vec<MemberInfo>> {
+        let members = self
+            .http
+            .search_guild_members(guild_id.0, query, Some(25))
+            .await?;
+        Ok(members
+            .into_iter()
+            .map(|member| MemberInfo {
+                user: member.user,
+                nick: member.nick,
+            })
+            .collect())
+    }
+
+    async fn direct_message_channel(
+        &self,
+        user_id: UserId,
+    ) -> TransportResult<ChannelId> {
+        Ok(user_id.create_dm_channel(&self.http).await?.id)
+    }
+
+    async fn channel_info(
+        &self,
+        channel_id: ChannelId,
+    ) -> TransportResult<ChannelInfo> {
+        Ok(match channel_id.to_channel(&self.http).await? {
+            Channel::Guild(channel) => ChannelInfo {
+                category: channel.category_id,
+                nsfw: channel.nsfw,
+            },
+            _ => ChannelInfo::default(),
+        })
+    }
+}
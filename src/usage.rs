@@ -0,0 +1,120 @@
+//! Message and command counts per guild and per day.
+
+use crate::userdb::ProfileScope;
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// The number of days in the rolling view of `$stats`.
+pub const WEEK_DAYS: u64 = 7;
+
+/// Formats days since the unix epoch as a date.
+pub fn day_name(day: u64) -> String {
+    let time = UNIX_EPOCH + Duration::from_secs(day * 86400);
+    chrono::DateTime::<chrono::Utc>::from(time)
+        .format("%Y-%m-%d")
+        .to_string()
+}
+
+/// What was counted in one guild, or in direct messages, on one day.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct DailyUsage {
+    #[serde(default)]
+    pub messages: u64,
+    #[serde(default)]
+    pub commands: BTreeMap<String, u64>,
+}
+
+impl DailyUsage {
+    pub fn command_total(&self) -> u64 {
+        self.commands.values().sum()
+    }
+
+    fn add(&mut self, other: &DailyUsage) {
+        self.messages += other.messages;
+        for (command, count) in &other.commands {
+            *self.commands.entry(command.clone()).or_insert(0) += count;
+        }
+    }
+}
+
+/// The counts of every guild and of direct messages by day since the unix
+/// epoch.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct UsageStats {
+    #[serde(default)]
+    pub guilds: HashMap<GuildId, BTreeMap<u64, DailyUsage>>,
+    #[serde(default)]
+    pub direct_messages: BTreeMap<u64, DailyUsage>,
+}
+
+/// The counts of `$stats`: all time, today and each day of the last week
+/// from oldest to newest.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct UsageSummary {
+    pub total: DailyUsage,
+    pub today: DailyUsage,
+    pub week: Vec<(u64, DailyUsage)>,
+}
+
+impl UsageStats {
+    pub fn day(&self, scope: ProfileScope, day: u64) -> Option<&DailyUsage> {
+        match scope {
+            ProfileScope::Guild(id) => self.guilds.get(&id)?.get(&day),
+            _ => self.direct_messages.get(&day),
+        }
+    }
+
+    /// The counts of `day` in `scope`. Unscoped counts go with direct
+    /// messages.
+    pub fn day_mut(
+        &mut self,
+        scope: ProfileScope,
+        day: u64,
+    ) -> &mut DailyUsage {
+        let days = match scope {
+            ProfileScope::Guild(id) => self.guilds.entry(id).or_default(),
+            _ => &mut self.direct_messages,
+        };
+        days.entry(day).or_default()
+    }
+
+    /// Sums up the counts of one guild, or of everything without one.
+    pub fn summary(
+        &self,
+        guild_id: Option<GuildId>,
+        today: u64,
+    ) -> UsageSummary {
+        let scopes: Vec<&BTreeMap<u64, DailyUsage>> = match guild_id {
+            Some(id) => self.guilds.get(&id).into_iter().collect(),
+            None => self
+                .guilds
+                .values()
+                .chain(std::iter::once(&self.direct_messages))
+                .collect(),
+        };
+
+        let first = today.saturating_sub(WEEK_DAYS - 1);
+        let mut summary = UsageSummary {
+            week: (first..=today)
+                .map(|day| (day, DailyUsage::default()))
+                .collect(),
+            ..UsageSummary::default()
+        };
+        for days in scopes {
+            for (day, usage) in days {
+                summary.total.add(usage);
+                if *day >= first && *day <= today {
+                    summary.week[(day - first) as usize].1.add(usage);
+                }
+            }
+        }
+        summary.today = summary
+            .week
+            .last()
+            .map(|(_, usage)| usage.clone())
+            .unwrap_or_default();
+        summary
+    }
+}
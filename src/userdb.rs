@@ -1,37 +1,102 @@
+use crate::config::{GuildConfig, GuildSettings, RetentionConfig};
+use crate::storage::{PendingChanges, ProfileStorage};
+use crate::usage::UsageStats;
 use serde::{Deserialize, Serialize};
 use serenity::model::prelude::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tracing::{error, info, warn};
 
-pub fn analyze_message(msg: &str) -> SentimentResult {
-    thread_local! {
-        static ANALYZER: vader_sentiment::SentimentIntensityAnalyzer<'static> =
-                         vader_sentiment::SentimentIntensityAnalyzer::new();
-    }
-
-    ANALYZER.with(|analyzer| {
-        SentimentResult::from_hashmap(analyzer.polarity_scores(msg))
-    })
+/// Seconds since the unix epoch.
+pub fn current_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SentimentResult {
     negative: f64,
     neutral: f64,
     positive: f64,
     compound: f64,
+    // Databases written before timestamps were recorded are treated as if
+    // their history was recorded when it was first loaded.
+    #[serde(default = "current_timestamp")]
+    timestamp: u64,
+    /// The message that was analyzed, unknown for older results.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    message_id: Option<MessageId>,
 }
 
 impl SentimentResult {
+    pub fn new(
+        negative: f64,
+        neutral: f64,
+        positive: f64,
+        compound: f64,
+    ) -> SentimentResult {
+        SentimentResult {
+            negative,
+            neutral,
+            positive,
+            compound,
+            timestamp: current_timestamp(),
+            message_id: None,
+        }
+    }
+
     pub fn from_hashmap(analysis: HashMap<&str, f64>) -> SentimentResult {
+        SentimentResult::new(
+            analysis["neg"],
+            analysis["neu"],
+            analysis["pos"],
+            analysis["compound"],
+        )
+    }
+
+    /// Returns a copy of this result as if it had been recorded at
+    /// `timestamp`.
+    pub fn recorded_at(&self, timestamp: u64) -> SentimentResult {
         SentimentResult {
-            negative: analysis["neg"],
-            neutral: analysis["neu"],
-            positive: analysis["pos"],
-            compound: analysis["compound"],
+            timestamp,
+            ..self.clone()
         }
     }
 
+    /// Returns a copy of this result tied to the message it scored.
+    pub fn for_message(&self, message_id: MessageId) -> SentimentResult {
+        SentimentResult {
+            message_id: Some(message_id),
+            ..self.clone()
+        }
+    }
+
+    pub fn negative(&self) -> f64 {
+        self.negative
+    }
+
+    pub fn neutral(&self) -> f64 {
+        self.neutral
+    }
+
+    pub fn positive(&self) -> f64 {
+        self.positive
+    }
+
+    pub fn compound(&self) -> f64 {
+        self.compound
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    pub fn message_id(&self) -> Option<MessageId> {
+        self.message_id
+    }
+
     pub fn to_formatted_json(&self) -> String {
         format!(
             "{{\n  \
@@ -45,159 +110,1065 @@ impl SentimentResult {
     }
 }
 
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HueState {
+    Crystal,
+    Clear,
+    Cloudy,
+    Murky,
+    Turbid,
+}
+
+impl HueState {
+    /// Every state from the clearest to the most clouded.
+    pub const ALL: [HueState; 5] = [
+        HueState::Crystal,
+        HueState::Clear,
+        HueState::Cloudy,
+        HueState::Murky,
+        HueState::Turbid,
+    ];
+
+    fn from_clouded_level(clouded: f64) -> HueState {
+        if clouded < 0.2 {
+            HueState::Crystal
+        } else if clouded < 0.4 {
+            HueState::Clear
+        } else if clouded < 0.6 {
+            HueState::Cloudy
+        } else if clouded < 0.8 {
+            HueState::Murky
+        } else {
+            HueState::Turbid
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            HueState::Crystal => "Crystal",
+            HueState::Clear => "Clear",
+            HueState::Cloudy => "Cloudy",
+            HueState::Murky => "Murky",
+            HueState::Turbid => "Turbid",
+        }
+    }
+
+    /// The colour of the hue when it is entirely in this state.
+    pub fn color(&self) -> (u8, u8, u8) {
+        let index = HueState::ALL
+            .iter()
+            .position(|state| state == self)
+            .unwrap_or(0);
+        Hue::GRADIENT[index]
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct Hue {
+    pub state: HueState,
+    pub color: (u8, u8, u8),
+    /// How clouded the hue is, from 0.0 (crystal) to 1.0 (turbid).
+    pub clouded: f64,
+    /// Positive when the hue is clouding over, negative when clearing up.
+    pub trend: f64,
+}
+
+impl Hue {
+    // Gradient stops from a crystal pale blue to a turbid dark violet.
+    const GRADIENT: [(u8, u8, u8); 5] = [
+        (0xB3, 0xE5, 0xFC),
+        (0x4F, 0xC3, 0xF7),
+        (0x9E, 0x9D, 0x24),
+        (0x6D, 0x4C, 0x41),
+        (0x31, 0x1B, 0x3F),
+    ];
+    const TREND_WEIGHT: f64 = 0.25;
+    const TREND_STABLE_RANGE: f64 = 0.05;
+
+    /// Computes the hue from sentiment values ordered from oldest to newest.
+    fn from_sentiment_values(values: &[&SentimentResult]) -> Hue {
+        if values.is_empty() {
+            return Hue::from_clouded_level(0.0, 0.0);
+        }
+
+        let count = values.len() as f64;
+        let negative = values.iter().map(|s| s.negative).sum::<f64>() / count;
+        let neutral = values.iter().map(|s| s.neutral).sum::<f64>() / count;
+        let positive = values.iter().map(|s| s.positive).sum::<f64>() / count;
+
+        // Neutral messages dilute the negativity instead of being ignored,
+        // so a mostly neutral user stays clear.
+        let polarity = negative + positive + neutral * 0.5;
+        let base = if polarity > 0.0 {
+            negative / polarity
+        } else {
+            0.0
+        };
+
+        // Compare the older half of the history against the recent half, a
+        // drop in compound score means the hue is clouding over.
+        let trend = if values.len() >= 2 {
+            let (older, recent) = values.split_at(values.len() / 2);
+            let mean = |half: &[&SentimentResult]| {
+                half.iter().map(|s| s.compound).sum::<f64>() / half.len() as f64
+            };
+            mean(older) - mean(recent)
+        } else {
+            0.0
+        };
+
+        let clouded = (base + trend * Hue::TREND_WEIGHT).clamp(0.0, 1.0);
+        Hue::from_clouded_level(clouded, trend)
+    }
+
+    fn from_clouded_level(clouded: f64, trend: f64) -> Hue {
+        let segments = (Hue::GRADIENT.len() - 1) as f64;
+        let position = clouded * segments;
+        let index = (position.floor() as usize).min(Hue::GRADIENT.len() - 2);
+        let fraction = position - index as f64;
+
+        let (r1, g1, b1) = Hue::GRADIENT[index];
+        let (r2, g2, b2) = Hue::GRADIENT[index + 1];
+        let lerp = |a: u8, b: u8| {
+            (a as f64 + (b as f64 - a as f64) * fraction).round() as u8
+        };
+
+        Hue {
+            state: HueState::from_clouded_level(clouded),
+            color: (lerp(r1, r2), lerp(g1, g2), lerp(b1, b2)),
+            clouded,
+            trend,
+        }
+    }
+
+    pub fn trend_description(&self) -> &'static str {
+        if self.trend > Hue::TREND_STABLE_RANGE {
+            "Clouding"
+        } else if self.trend < -Hue::TREND_STABLE_RANGE {
+            "Clearing"
+        } else {
+            "Stable"
+        }
+    }
+
+    pub fn hex_string(&self) -> String {
+        let (r, g, b) = self.color;
+        format!("#{:02X}{:02X}{:02X}", r, g, b)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CymaticParameters {
+    /// Age at which a sentiment result counts half as much as a new one.
+    /// A zero half-life disables the decay.
+    pub half_life: Duration,
+    pub cc_neutral: f64,
+    pub cc_poly3: f64,
+    pub cc_poly2: f64,
+    pub cc_poly1: f64,
+    /// Fewer messages than this are not enough for a meaningful coefficient.
+    pub min_samples: usize,
+}
+
+#[derive(Serialize)]
 pub struct ComputedData {
     pub crime_coefficient: f64,
-    // TODO: Add Hue
+    pub hue: Hue,
+    /// The number of messages the data was computed from.
+    pub samples: usize,
+    /// The 95% confidence interval of the crime coefficient, lowest first.
+    pub interval: (f64, f64),
+    /// Whether there were fewer than `min_samples` messages.
+    pub insufficient_data: bool,
 }
 
 impl ComputedData {
+    // The history is pulled towards a neutral compound with the weight of a
+    // single new message, so a score recovers as the history decays.
+    const NEUTRAL_PRIOR_WEIGHT: f64 = 1.0;
+    const CONFIDENCE_Z: f64 = 1.96;
+    // A compound of -1.0 maps to an infinite coefficient.
+    const MIN_COMPOUND: f64 = -0.99;
+
+    /// Computes the cymatic data from sentiment values ordered from oldest
+    /// to newest, as of the `now` unix timestamp.
     pub fn from_sentiment_values(
-        values: &Vec<SentimentResult>,
+        values: &[&SentimentResult],
+        parameters: &CymaticParameters,
+        now: u64,
     ) -> ComputedData {
-        let mut total = 0.0;
-        for sentiment in values.iter() {
-            total += sentiment.compound;
-        }
-        let average_compound = total / values.len() as f64;
+        let weights: Vec<f64> = if parameters.half_life.as_secs() == 0 {
+            vec![1.0; values.len()]
+        } else {
+            let half_life = parameters.half_life.as_secs_f64();
+            values
+                .iter()
+                .map(|sentiment| {
+                    let age = now.saturating_sub(sentiment.timestamp) as f64;
+                    0.5f64.powf(age / half_life)
+                })
+                .collect()
+        };
+
+        let total: f64 = values
+            .iter()
+            .zip(weights.iter())
+            .map(|(sentiment, weight)| sentiment.compound * weight)
+            .sum();
+        let history_weight: f64 = weights.iter().sum();
+        let average_compound = if parameters.half_life.as_secs() == 0 {
+            // An empty history is neutral.
+            if values.is_empty() {
+                0.0
+            } else {
+                total / history_weight
+            }
+        } else {
+            total / (history_weight + ComputedData::NEUTRAL_PRIOR_WEIGHT)
+        };
+
+        // The standard error of the weighted mean, using the effective number
+        // of messages once they are weighted by age.
+        let squared_weights: f64 = weights.iter().map(|w| w * w).sum();
+        let effective_samples = if squared_weights > 0.0 {
+            history_weight * history_weight / squared_weights
+        } else {
+            0.0
+        };
+        let standard_error = if effective_samples > 1.0 {
+            let variance = values
+                .iter()
+                .zip(weights.iter())
+                .map(|(sentiment, weight)| {
+                    weight * (sentiment.compound - average_compound).powi(2)
+                })
+                .sum::<f64>()
+                / history_weight;
+            (variance / effective_samples).sqrt()
+        } else {
+            // Nothing is known about the spread, assume the widest.
+            1.0
+        };
+        let spread = standard_error * ComputedData::CONFIDENCE_Z;
+        let bound = |compound: f64| {
+            ComputedData::convert_compound_to_cc(
+                compound.clamp(ComputedData::MIN_COMPOUND, 1.0),
+                parameters,
+            )
+        };
 
         ComputedData {
             crime_coefficient: ComputedData::convert_compound_to_cc(
                 average_compound,
+                parameters,
+            ),
+            hue: Hue::from_sentiment_values(values),
+            samples: values.len(),
+            interval: (
+                bound(average_compound + spread),
+                bound(average_compound - spread),
             ),
+            insufficient_data: values.len() < parameters.min_samples,
         }
     }
 
-    pub fn convert_compound_to_cc(compound: f64) -> f64 {
-        const CC_NEUTRAL: f64 = 75.0;
-        const CC_POLY3: f64 = 1.0;
-        const CC_POLY2: f64 = 2.0;
-        const CC_POLY1: f64 = 5.0;
-
+    pub fn convert_compound_to_cc(
+        compound: f64,
+        parameters: &CymaticParameters,
+    ) -> f64 {
         if compound >= 0.0 {
-            (1.0 - compound) * CC_NEUTRAL
+            (1.0 - compound) * parameters.cc_neutral
         } else {
             let base_factor = (1.0 - compound.abs()).recip();
-            (base_factor * CC_POLY3).powi(3)
-                + (base_factor * CC_POLY2).powi(2) * CC_POLY1
-                + CC_NEUTRAL
+            (base_factor * parameters.cc_poly3).powi(3)
+                + (base_factor * parameters.cc_poly2).powi(2)
+                    * parameters.cc_poly1
+                + parameters.cc_neutral
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Days since the unix epoch.
+pub fn current_day() -> u64 {
+    current_timestamp() / 86400
+}
+
+/// The crime coefficient of a user at the end of a day.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct CoefficientSnapshot {
+    /// Days since the unix epoch.
+    pub day: u64,
+    pub crime_coefficient: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct UserProfileData {
     user_tag: String,
     sentiment_values: Vec<SentimentResult>,
     oldest_index: usize,
+    // One snapshot per day, ordered from oldest to newest.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    snapshots: Vec<CoefficientSnapshot>,
 }
 
 impl UserProfileData {
-    const MAX_USER_HISTORY: usize = 100;
+    /// Snapshots older than this many days are dropped.
+    pub const MAX_SNAPSHOTS: usize = 365;
 
     fn new(tag: &str) -> UserProfileData {
         UserProfileData {
             user_tag: tag.to_string(),
             sentiment_values: Vec::new(),
             oldest_index: 0,
+            snapshots: Vec::new(),
         }
     }
 
-    pub fn get_cymatic_data(&self) -> ComputedData {
-        ComputedData::from_sentiment_values(&self.sentiment_values)
+    /// Rebuilds a profile from its history ordered from oldest to newest.
+    pub fn from_history(
+        tag: &str,
+        history: Vec<SentimentResult>,
+    ) -> UserProfileData {
+        UserProfileData {
+            user_tag: tag.to_string(),
+            sentiment_values: history,
+            oldest_index: 0,
+            snapshots: Vec::new(),
+        }
+    }
+
+    pub fn user_tag(&self) -> &str {
+        &self.user_tag
+    }
+
+    /// Returns the recorded history ordered from oldest to newest.
+    pub fn history(&self) -> Vec<&SentimentResult> {
+        self.ordered_sentiment_values()
+    }
+
+    /// The daily coefficient snapshots ordered from oldest to newest.
+    pub fn snapshots(&self) -> &[CoefficientSnapshot] {
+        &self.snapshots
+    }
+
+    /// Records `snapshot`, replacing any snapshot of the same day.
+    pub fn record_snapshot(&mut self, snapshot: CoefficientSnapshot) {
+        match self
+            .snapshots
+            .binary_search_by_key(&snapshot.day, |existing| existing.day)
+        {
+            Ok(index) => self.snapshots[index] = snapshot,
+            Err(index) => self.snapshots.insert(index, snapshot),
+        }
+        let newest = self.snapshots.last().map_or(snapshot.day, |s| s.day);
+        let oldest =
+            newest.saturating_sub(UserProfileData::MAX_SNAPSHOTS as u64);
+        self.snapshots.retain(|existing| existing.day > oldest);
+    }
+
+    /// Records today's coefficient if there is enough data for one.
+    fn snapshot(&mut self, parameters: &CymaticParameters, day: u64) {
+        let cdata = self.get_cymatic_data(parameters);
+        if !cdata.insufficient_data {
+            self.record_snapshot(CoefficientSnapshot {
+                day,
+                crime_coefficient: cdata.crime_coefficient,
+            });
+        }
+    }
+
+    pub fn get_cymatic_data(
+        &self,
+        parameters: &CymaticParameters,
+    ) -> ComputedData {
+        ComputedData::from_sentiment_values(
+            &self.ordered_sentiment_values(),
+            parameters,
+            current_timestamp(),
+        )
+    }
+
+    /// Returns the ring buffer contents ordered from oldest to newest.
+    fn ordered_sentiment_values(&self) -> Vec<&SentimentResult> {
+        let (newer, older) = self.sentiment_values.split_at(self.oldest_index);
+        older.iter().chain(newer.iter()).collect()
     }
 
-    fn add_sentiment_result(&mut self, result: SentimentResult) {
-        if self.sentiment_values.len() < UserProfileData::MAX_USER_HISTORY {
+    fn add_sentiment_result(
+        &mut self,
+        result: SentimentResult,
+        max_history: usize,
+    ) {
+        let max_history = max_history.max(1);
+        if self.sentiment_values.len() != max_history && self.oldest_index != 0
+        {
+            // The history length was changed, straighten out the ring buffer
+            // before growing or shrinking it.
+            self.sentiment_values.rotate_left(self.oldest_index);
+            self.oldest_index = 0;
+        }
+
+        if self.sentiment_values.len() < max_history {
             self.sentiment_values.push(result);
         } else {
+            let excess = self.sentiment_values.len() - max_history;
+            self.sentiment_values.drain(..excess);
+
             // overwrite the oldest value
             self.sentiment_values[self.oldest_index] = result;
-            self.oldest_index =
-                (self.oldest_index + 1) % UserProfileData::MAX_USER_HISTORY;
+            self.oldest_index = (self.oldest_index + 1) % max_history;
         }
     }
 
+    /// The time of the most recent message, if any is recorded.
+    pub fn last_activity(&self) -> Option<u64> {
+        self.sentiment_values.iter().map(|s| s.timestamp).max()
+    }
+
+    /// Deletes the sentiment results recorded before `timestamp`, returning
+    /// how many were deleted.
+    fn expire_before(&mut self, timestamp: u64) -> usize {
+        let count = self.sentiment_values.len();
+        self.sentiment_values.rotate_left(self.oldest_index);
+        self.oldest_index = 0;
+        self.sentiment_values.retain(|s| s.timestamp >= timestamp);
+        count - self.sentiment_values.len()
+    }
+
+    /// Replaces the scores of the result of `message_id` with those of
+    /// `result`, keeping when it was recorded. Returns whether it was found.
+    fn rescore(
+        &mut self,
+        message_id: MessageId,
+        result: &SentimentResult,
+    ) -> bool {
+        match self
+            .sentiment_values
+            .iter_mut()
+            .find(|s| s.message_id == Some(message_id))
+        {
+            Some(existing) => {
+                *existing = SentimentResult {
+                    timestamp: existing.timestamp,
+                    message_id: existing.message_id,
+                    ..result.clone()
+                };
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Deletes the results of `message_ids`, returning how many were deleted.
+    fn remove_messages(&mut self, message_ids: &HashSet<MessageId>) -> usize {
+        let is_removed = |s: &SentimentResult| {
+            s.message_id.is_some_and(|id| message_ids.contains(&id))
+        };
+        if !self.sentiment_values.iter().any(is_removed) {
+            return 0;
+        }
+        let count = self.sentiment_values.len();
+        self.sentiment_values.rotate_left(self.oldest_index);
+        self.oldest_index = 0;
+        self.sentiment_values.retain(|s| !is_removed(s));
+        count - self.sentiment_values.len()
+    }
+
     pub fn delete_data(&mut self) {
         self.sentiment_values.clear();
         self.oldest_index = 0;
+        self.snapshots.clear();
+    }
+
+    /// Combines the most recent history of several profiles of one user.
+    fn merged<'a>(
+        profiles: impl Iterator<Item = &'a UserProfileData>,
+        max_history: usize,
+    ) -> Option<UserProfileData> {
+        let mut merged: Option<UserProfileData> = None;
+        let mut values = Vec::new();
+        for profile in profiles {
+            merged
+                .get_or_insert_with(|| UserProfileData::new(&profile.user_tag));
+            values.extend(profile.sentiment_values.iter().cloned());
+        }
+
+        merged.map(|mut profile| {
+            values.sort_by_key(|sentiment| sentiment.timestamp);
+            let excess = values.len().saturating_sub(max_history);
+            profile.sentiment_values = values.split_off(excess);
+            profile
+        })
+    }
+
+    fn absorb(&mut self, other: UserProfileData, max_history: usize) {
+        let mut values: Vec<SentimentResult> = self
+            .ordered_sentiment_values()
+            .into_iter()
+            .cloned()
+            .collect();
+        values.extend(other.ordered_sentiment_values().into_iter().cloned());
+        values.sort_by_key(|sentiment| sentiment.timestamp);
+
+        let snapshots = std::mem::take(&mut self.snapshots);
+        self.delete_data();
+        for value in values {
+            self.add_sentiment_result(value, max_history);
+        }
+
+        // Keep the snapshot of this profile on days both have one.
+        for snapshot in other.snapshots.into_iter().chain(snapshots) {
+            self.record_snapshot(snapshot);
+        }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct UserProfilesDatabase {
-    db: HashMap<UserId, UserProfileData>,
+/// The set of profiles a message is recorded into. Profiles in different
+/// scopes are independent of each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProfileScope {
+    Guild(GuildId),
+    DirectMessages,
+    /// Profiles recorded before they were split per guild. They only count
+    /// towards the global view until they are migrated into a guild.
+    Unscoped,
+}
+
+impl ProfileScope {
+    pub fn from_guild(guild_id: Option<GuildId>) -> ProfileScope {
+        match guild_id {
+            Some(id) => ProfileScope::Guild(id),
+            None => ProfileScope::DirectMessages,
+        }
+    }
+
+    /// The guild of the scope, if it is one.
+    pub fn guild_id(&self) -> Option<GuildId> {
+        match self {
+            ProfileScope::Guild(id) => Some(*id),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ProfileScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProfileScope::Guild(id) => write!(f, "guild {}", id),
+            ProfileScope::DirectMessages => write!(f, "direct messages"),
+            ProfileScope::Unscoped => write!(f, "unassigned profiles"),
+        }
+    }
+}
+
+/// What a retention sweep deleted.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RetentionSummary {
+    pub entries: usize,
+    pub inactive_profiles: Vec<(ProfileScope, UserId)>,
+    /// Profiles deleted since the last sweep because the member left.
+    pub departed_profiles: usize,
+}
+
+impl std::fmt::Display for RetentionSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} expired sentiment result(s), {} inactive profile(s) and {} \
+            profile(s) of departed members",
+            self.entries,
+            self.inactive_profiles.len(),
+            self.departed_profiles
+        )
+    }
+}
+
+/// Everything the database persists, in the layout of the JSON file.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct DatabaseContents {
+    #[serde(default)]
+    pub guilds: HashMap<GuildId, HashMap<UserId, UserProfileData>>,
+
+    #[serde(default)]
+    pub direct_messages: HashMap<UserId, UserProfileData>,
+
+    #[serde(default, alias = "db", skip_serializing_if = "HashMap::is_empty")]
+    pub unscoped: HashMap<UserId, UserProfileData>,
+
+    #[serde(default)]
+    pub guild_settings: HashMap<GuildId, GuildSettings>,
+
+    /// Users whose messages are not recorded.
+    #[serde(default, skip_serializing_if = "HashSet::is_empty")]
+    pub opted_out: HashSet<UserId>,
+
+    #[serde(default)]
+    pub usage: UsageStats,
+}
+
+impl DatabaseContents {
+    pub fn scope(
+        &self,
+        scope: ProfileScope,
+    ) -> Option<&HashMap<UserId, UserProfileData>> {
+        match scope {
+            ProfileScope::Guild(id) => self.guilds.get(&id),
+            ProfileScope::DirectMessages => Some(&self.direct_messages),
+            ProfileScope::Unscoped => Some(&self.unscoped),
+        }
+    }
+
+    pub fn scope_as_mut(
+        &mut self,
+        scope: ProfileScope,
+    ) -> &mut HashMap<UserId, UserProfileData> {
+        match scope {
+            ProfileScope::Guild(id) => self.guilds.entry(id).or_default(),
+            ProfileScope::DirectMessages => &mut self.direct_messages,
+            ProfileScope::Unscoped => &mut self.unscoped,
+        }
+    }
+
+    pub fn get_user_profile(
+        &self,
+        scope: ProfileScope,
+        id: &UserId,
+    ) -> Option<&UserProfileData> {
+        self.scope(scope).and_then(|profiles| profiles.get(id))
+    }
 
-    #[serde(skip)]
-    dirty: bool,
+    /// Every scope that holds profiles.
+    pub fn scopes(&self) -> Vec<ProfileScope> {
+        self.guilds
+            .keys()
+            .map(|id| ProfileScope::Guild(*id))
+            .chain(vec![ProfileScope::DirectMessages, ProfileScope::Unscoped])
+            .collect()
+    }
 }
 
-const USERDB_FILE: &str = "userccs.db";
+pub struct UserProfilesDatabase {
+    contents: DatabaseContents,
+    storage: Box<dyn ProfileStorage>,
+
+    // Changes since the last flush that the storage did not already
+    // persist as they happened.
+    changes: PendingChanges,
+
+    defaults: GuildConfig,
+
+    // Profiles of departed members deleted since the last retention sweep.
+    departed_since_sweep: usize,
+}
 
 impl UserProfilesDatabase {
-    pub fn try_create_from_disk() -> UserProfilesDatabase {
-        let result = std::fs::read_to_string(USERDB_FILE);
-
-        match result {
-            Ok(contents) => {
-                let mut db: UserProfilesDatabase =
-                    serde_json::from_str(&contents).unwrap();
-                info!("Database successfully loaded from '{}'", USERDB_FILE);
-                db.dirty = false;
-                db
+    pub fn open(
+        mut storage: Box<dyn ProfileStorage>,
+        defaults: GuildConfig,
+    ) -> UserProfilesDatabase {
+        let contents = match storage.load() {
+            Ok(Some(contents)) => {
+                info!("Database successfully loaded from {}", storage.name());
+                contents
+            }
+            Ok(None) => {
+                warn!("No database found in {}, creating new.", storage.name());
+                DatabaseContents::default()
             }
             Err(why) => {
-                if why.kind() == std::io::ErrorKind::NotFound {
-                    warn!(
-                        "Database named '{}' not found, creating new.",
-                        USERDB_FILE
-                    );
-                    UserProfilesDatabase {
-                        db: HashMap::new(),
-                        dirty: false,
-                    }
-                } else {
-                    panic!("Failed to open file '{}': {}", USERDB_FILE, why);
-                }
+                panic!(
+                    "Failed to load database from {}: {}",
+                    storage.name(),
+                    why
+                )
             }
+        };
+
+        if !contents.unscoped.is_empty() {
+            warn!(
+                "{} profile(s) are not assigned to a guild, use \
+                'debug migrate <guild id>' to assign them.",
+                contents.unscoped.len()
+            );
+        }
+
+        UserProfilesDatabase {
+            contents,
+            storage,
+            changes: PendingChanges::default(),
+            defaults,
+            departed_since_sweep: 0,
         }
     }
 
-    pub fn to_disk(&mut self) {
-        if !self.dirty {
+    /// Persists every change that was not already written as it happened.
+    pub fn flush(&mut self) {
+        if self.changes.is_empty() {
             return;
         }
 
-        let serialized = serde_json::to_string(&self).unwrap();
-        if let Err(why) = std::fs::write(USERDB_FILE, serialized) {
+        if let Err(why) = self.storage.save(&self.contents, &self.changes) {
             error!("Failed to save database: {}", why);
         } else {
             info!("Successfully saved database.");
-            self.dirty = false;
+            self.changes = PendingChanges::default();
         }
     }
 
     pub fn add_sentiment_result_for_user(
         &mut self,
+        scope: ProfileScope,
         user: &User,
         result: SentimentResult,
     ) {
+        let config = self.guild_config(scope);
         let profile_data = self
-            .db
+            .contents
+            .scope_as_mut(scope)
             .entry(user.id)
-            .or_insert(UserProfileData::new(&user.tag()));
-        profile_data.add_sentiment_result(result);
-        self.dirty = true;
+            .or_insert_with(|| UserProfileData::new(&user.tag()));
+        profile_data.add_sentiment_result(result.clone(), config.max_history);
+        profile_data.snapshot(&config.cymatic, current_day());
+
+        let persisted = self
+            .storage
+            .record_sentiment(scope, user.id, profile_data, &result)
+            .unwrap_or_else(|why| {
+                error!(
+                    "Failed to record sentiment for {}: {}",
+                    user.tag(),
+                    why
+                );
+                false
+            });
+        if !persisted {
+            self.changes.profiles.insert((scope, user.id));
+        }
+    }
+
+    /// Counts an analyzed message for today.
+    pub fn count_message(&mut self, scope: ProfileScope) {
+        let day = current_day();
+        self.contents.usage.day_mut(scope, day).messages += 1;
+        self.changes.usage.insert((scope, day));
+    }
+
+    /// Counts a use of `command` for today.
+    pub fn count_command(&mut self, scope: ProfileScope, command: &str) {
+        let day = current_day();
+        let usage = self.contents.usage.day_mut(scope, day);
+        *usage.commands.entry(command.to_string()).or_insert(0) += 1;
+        self.changes.usage.insert((scope, day));
+    }
+
+    /// The guilds with profiles, in ascending order.
+    pub fn guild_ids(&self) -> Vec<GuildId> {
+        let mut ids: Vec<GuildId> =
+            self.contents.guilds.keys().copied().collect();
+        ids.sort();
+        ids
+    }
+
+    /// The number of profiles in every scope.
+    pub fn profile_count(&self) -> usize {
+        self.contents
+            .scopes()
+            .into_iter()
+            .filter_map(|scope| self.contents.scope(scope))
+            .map(HashMap::len)
+            .sum()
+    }
+
+    /// The size in bytes of the stored database, if known.
+    pub fn storage_size(&self) -> Option<u64> {
+        self.storage.size()
+    }
+
+    pub fn usage(&self) -> &UsageStats {
+        &self.contents.usage
+    }
+
+    /// The effective settings of `scope`, with its guild overrides applied.
+    pub fn guild_config(&self, scope: ProfileScope) -> GuildConfig {
+        match scope {
+            ProfileScope::Guild(id) => {
+                match self.contents.guild_settings.get(&id) {
+                    Some(settings) => settings.apply(&self.defaults),
+                    None => self.defaults.clone(),
+                }
+            }
+            _ => self.defaults.clone(),
+        }
     }
 
-    pub fn get_user_profile(&self, id: &UserId) -> Option<&UserProfileData> {
-        self.db.get(id)
+    pub fn cymatic_parameters(&self, scope: ProfileScope) -> CymaticParameters {
+        self.guild_config(scope).cymatic
+    }
+
+    pub fn guild_settings(&self, id: GuildId) -> GuildSettings {
+        self.contents
+            .guild_settings
+            .get(&id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn set_guild_settings(&mut self, id: GuildId, settings: GuildSettings) {
+        self.contents.guild_settings.insert(id, settings);
+        self.changes.guild_settings.insert(id);
+    }
+
+    pub fn get_user_profile(
+        &self,
+        scope: ProfileScope,
+        id: &UserId,
+    ) -> Option<&UserProfileData> {
+        self.contents.get_user_profile(scope, id)
+    }
+
+    /// Every profile recorded in `scope`.
+    pub fn user_profiles(
+        &self,
+        scope: ProfileScope,
+    ) -> impl Iterator<Item = (&UserId, &UserProfileData)> {
+        self.contents.scope(scope).into_iter().flatten()
     }
 
     pub fn get_user_profile_as_mut(
         &mut self,
+        scope: ProfileScope,
         id: &UserId,
     ) -> Option<&mut UserProfileData> {
-        self.dirty = true;
-        self.db.get_mut(id)
+        self.changes.profiles.insert((scope, *id));
+        self.contents.scope_as_mut(scope).get_mut(id)
+    }
+
+    pub fn is_opted_out(&self, id: &UserId) -> bool {
+        self.contents.opted_out.contains(id)
+    }
+
+    /// Stops recording the messages of a user and deletes their profiles.
+    /// Returns the number of profiles deleted.
+    pub fn opt_out(&mut self, id: UserId) -> usize {
+        if self.contents.opted_out.insert(id) {
+            self.changes.opted_out.insert(id);
+        }
+        self.delete_user(&id)
+    }
+
+    /// Resumes recording the messages of a user, returning whether they had
+    /// opted out.
+    pub fn opt_in(&mut self, id: UserId) -> bool {
+        let opted_out = self.contents.opted_out.remove(&id);
+        if opted_out {
+            self.changes.opted_out.insert(id);
+        }
+        opted_out
+    }
+
+    /// Every profile of a user, in every scope.
+    pub fn user_data(
+        &self,
+        id: &UserId,
+    ) -> Vec<(ProfileScope, &UserProfileData)> {
+        self.contents
+            .scopes()
+            .into_iter()
+            .filter_map(|scope| {
+                self.get_user_profile(scope, id)
+                    .map(|profile| (scope, profile))
+            })
+            .collect()
+    }
+
+    /// Deletes every profile of a user, returning how many there were.
+    pub fn delete_user(&mut self, id: &UserId) -> usize {
+        let mut count = 0;
+        for scope in self.contents.scopes() {
+            if self.contents.scope_as_mut(scope).remove(id).is_some() {
+                self.changes.profiles.insert((scope, *id));
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Deletes the profile of a user in one scope, returning whether there
+    /// was one.
+    pub fn delete_profile(&mut self, scope: ProfileScope, id: &UserId) -> bool {
+        if self.get_user_profile(scope, id).is_none() {
+            return false;
+        }
+        self.contents.scope_as_mut(scope).remove(id);
+        self.changes.profiles.insert((scope, *id));
+        true
+    }
+
+    /// Deletes the profile of a member who left a guild, returning whether
+    /// there was one.
+    pub fn remove_departed_member(
+        &mut self,
+        guild_id: GuildId,
+        user_id: UserId,
+    ) -> bool {
+        let removed =
+            self.delete_profile(ProfileScope::Guild(guild_id), &user_id);
+        if removed {
+            self.departed_since_sweep += 1;
+        }
+        removed
+    }
+
+    /// Re-scores an edited message with `result`, returning the author if
+    /// the message was recorded in `scope`.
+    pub fn rescore_message(
+        &mut self,
+        scope: ProfileScope,
+        message_id: MessageId,
+        result: &SentimentResult,
+    ) -> Option<UserId> {
+        let parameters = self.cymatic_parameters(scope);
+        let (user_id, profile) =
+            self.contents.scope_as_mut(scope).iter_mut().find_map(
+                |(user_id, profile)| {
+                    profile
+                        .rescore(message_id, result)
+                        .then_some((user_id, profile))
+                },
+            )?;
+        profile.snapshot(&parameters, current_day());
+        let user_id = *user_id;
+        self.changes.profiles.insert((scope, user_id));
+        Some(user_id)
+    }
+
+    /// Deletes the results of deleted messages in `scope`, returning how
+    /// many were recorded.
+    pub fn remove_messages(
+        &mut self,
+        scope: ProfileScope,
+        message_ids: &[MessageId],
+    ) -> usize {
+        let parameters = self.cymatic_parameters(scope);
+        let message_ids: HashSet<MessageId> =
+            message_ids.iter().copied().collect();
+        let mut removed = 0;
+        for (user_id, profile) in self.contents.scope_as_mut(scope).iter_mut() {
+            let count = profile.remove_messages(&message_ids);
+            if count > 0 {
+                profile.snapshot(&parameters, current_day());
+                self.changes.profiles.insert((scope, *user_id));
+                removed += count;
+            }
+        }
+        removed
+    }
+
+    /// Deletes the sentiment results and profiles `retention` no longer
+    /// keeps as of the `now` unix timestamp.
+    pub fn apply_retention(
+        &mut self,
+        retention: &RetentionConfig,
+        now: u64,
+    ) -> RetentionSummary {
+        const DAY: u64 = 86400;
+        let mut summary = RetentionSummary {
+            departed_profiles: std::mem::take(&mut self.departed_since_sweep),
+            ..RetentionSummary::default()
+        };
+
+        for scope in self.contents.scopes() {
+            let profiles = self.contents.scope_as_mut(scope);
+            let mut expired = Vec::new();
+            for (id, profile) in profiles.iter_mut() {
+                if retention.entry_days > 0 {
+                    let oldest = now.saturating_sub(retention.entry_days * DAY);
+                    let count = profile.expire_before(oldest);
+                    if count > 0 {
+                        summary.entries += count;
+                        self.changes.profiles.insert((scope, *id));
+                    }
+                }
+
+                if retention.inactive_days > 0 {
+                    let cutoff =
+                        now.saturating_sub(retention.inactive_days * DAY);
+                    if profile.last_activity().unwrap_or(0) < cutoff {
+                        expired.push(*id);
+                    }
+                }
+            }
+
+            for id in expired {
+                profiles.remove(&id);
+                self.changes.profiles.insert((scope, id));
+                summary.inactive_profiles.push((scope, id));
+            }
+        }
+        summary
+    }
+
+    /// Builds a profile from the user's most recent history across every
+    /// scope, including profiles not yet assigned to a guild.
+    pub fn get_global_profile(&self, id: &UserId) -> Option<UserProfileData> {
+        let contents = &self.contents;
+        let profiles = contents
+            .guilds
+            .values()
+            .chain(std::iter::once(&contents.direct_messages))
+            .chain(std::iter::once(&contents.unscoped))
+            .filter_map(|profiles| profiles.get(id));
+        UserProfileData::merged(profiles, self.defaults.max_history)
+    }
+
+    /// Records today's coefficient of every profile without a snapshot for
+    /// today, so days without messages still show the decay. Returns the
+    /// number of snapshots taken.
+    pub fn take_snapshots(&mut self) -> usize {
+        let day = current_day();
+        let mut scopes: Vec<ProfileScope> = self
+            .contents
+            .guilds
+            .keys()
+            .map(|id| ProfileScope::Guild(*id))
+            .collect();
+        scopes.push(ProfileScope::DirectMessages);
+
+        let mut count = 0;
+        for scope in scopes {
+            let parameters = self.cymatic_parameters(scope);
+            for (id, profile) in self.contents.scope_as_mut(scope).iter_mut() {
+                let taken = profile.snapshots.last().map(|s| s.day);
+                if taken == Some(day) {
+                    continue;
+                }
+                profile.snapshot(&parameters, day);
+                if profile.snapshots.last().map(|s| s.day) != taken {
+                    self.changes.profiles.insert((scope, *id));
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+
+    /// Moves every profile recorded before per-guild isolation into `scope`,
+    /// merging them with any history already recorded there.
+    pub fn migrate_unscoped_profiles(&mut self, scope: ProfileScope) -> usize {
+        let max_history = self.guild_config(scope).max_history;
+        let unscoped = std::mem::take(&mut self.contents.unscoped);
+        let count = unscoped.len();
+        let profiles = self.contents.scope_as_mut(scope);
+        for (id, profile) in unscoped {
+            match profiles.get_mut(&id) {
+                Some(existing) => existing.absorb(profile, max_history),
+                None => {
+                    profiles.insert(id, profile);
+                }
+            }
+            self.changes.profiles.insert((ProfileScope::Unscoped, id));
+            self.changes.profiles.insert((scope, id));
+        }
+        count
     }
 }
@@ -0,0 +1,369 @@
+//! Signed JSON notifications POSTed to the configured endpoints. Each
+//! notification waits in a queue file until it is delivered, so it survives
+//! restarts, and failed deliveries are retried with exponential backoff.
+//! The queue file is written by the delivery task, never while queueing.
+
+use crate::config::{WebhookConfig, WebhookEndpoint};
+use crate::dominator::EnforcementAction;
+use crate::userdb::current_timestamp;
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::{error, info, warn};
+
+/// `sha256=` followed by the hex HMAC-SHA256 of `<timestamp>.<body>`.
+pub const SIGNATURE_HEADER: &str = "X-Sibyl-Signature";
+/// The unix timestamp the payload was signed at.
+pub const TIMESTAMP_HEADER: &str = "X-Sibyl-Timestamp";
+pub const EVENT_HEADER: &str = "X-Sibyl-Event";
+
+/// Something that happened to a user. Ids are strings, as in the API.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum WebhookEvent {
+    ThresholdCrossed {
+        guild_id: String,
+        user_id: String,
+        user_tag: String,
+        boundary: f64,
+        old: f64,
+        new: f64,
+        message_link: String,
+    },
+    Enforced {
+        guild_id: String,
+        user_id: String,
+        user_tag: String,
+        action: EnforcementAction,
+        crime_coefficient: f64,
+    },
+    OptedOut {
+        user_id: String,
+        profiles_deleted: usize,
+    },
+    /// Profiles of a user deleted in one guild or, without one, outside of
+    /// guilds. Requested and API purges without a guild cover every scope.
+    Purged {
+        user_id: String,
+        guild_id: Option<String>,
+        profiles_deleted: usize,
+        reason: PurgeReason,
+    },
+}
+
+/// Why profiles were deleted.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PurgeReason {
+    /// The user asked with `forgetme`.
+    Requested,
+    Api,
+    /// An owner reset the history with `debug user reset`.
+    Reset,
+    /// The member left the guild.
+    Departed,
+    /// The retention sweep found no recent messages.
+    Inactive,
+}
+
+impl WebhookEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            WebhookEvent::ThresholdCrossed { .. } => "threshold_crossed",
+            WebhookEvent::Enforced { .. } => "enforced",
+            WebhookEvent::OptedOut { .. } => "opted_out",
+            WebhookEvent::Purged { .. } => "purged",
+        }
+    }
+}
+
+/// The body of a notification.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Payload {
+    /// Unique to the notification and kept across retries.
+    pub id: String,
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub event: WebhookEvent,
+}
+
+/// A notification waiting to be sent to one endpoint.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Delivery {
+    id: String,
+    url: String,
+    event: String,
+    body: String,
+    attempts: u32,
+    next_attempt: u64,
+}
+
+/// Signs a payload for the receiver to check with the shared secret.
+pub fn sign(secret: &str, timestamp: u64, body: &str) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let tag = hmac::sign(&key, format!("{}.{}", timestamp, body).as_bytes());
+    tag.as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn random_id() -> String {
+    let mut bytes = [0u8; 16];
+    if SystemRandom::new().fill(&mut bytes).is_err() {
+        // Ids only need to tell notifications apart.
+        bytes[..8].copy_from_slice(&current_timestamp().to_be_bytes());
+    }
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub struct Webhooks {
+    config: WebhookConfig,
+    queue: Mutex<Vec<Delivery>>,
+    /// Whether the queue changed since it was last saved.
+    changed: AtomicBool,
+    /// Held while saving, so older queues never overwrite newer ones.
+    saving: tokio::sync::Mutex<()>,
+    wake: Notify,
+    client: reqwest::Client,
+}
+
+impl Default for Webhooks {
+    /// Webhooks without endpoints, which drop every event.
+    fn default() -> Webhooks {
+        Webhooks::open(WebhookConfig::default())
+    }
+}
+
+impl Webhooks {
+    /// Loads the notifications left in the queue file.
+    pub fn open(config: WebhookConfig) -> Webhooks {
+        let queue = if config.endpoints.is_empty() {
+            Vec::new()
+        } else {
+            match std::fs::read_to_string(&config.queue_file) {
+                Ok(contents) => {
+                    serde_json::from_str(&contents).unwrap_or_else(|why| {
+                        error!(
+                            "Webhook queue '{}' is unreadable: {}",
+                            config.queue_file, why
+                        );
+                        Vec::new()
+                    })
+                }
+                Err(_) => Vec::new(),
+            }
+        };
+        if !queue.is_empty() {
+            info!("Loaded {} undelivered webhook(s).", queue.len());
+        }
+        Webhooks {
+            config,
+            queue: Mutex::new(queue),
+            changed: AtomicBool::new(false),
+            saving: tokio::sync::Mutex::new(()),
+            wake: Notify::new(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// The number of notifications waiting to be delivered.
+    pub fn pending(&self) -> usize {
+        self.queue.lock().unwrap().len()
+    }
+
+    /// Queues `event` for every endpoint that wants it, to be delivered and
+    /// saved by `run`.
+    pub fn notify(&self, event: WebhookEvent) {
+        let endpoints: Vec<&WebhookEndpoint> = self
+            .config
+            .endpoints
+            .iter()
+            .filter(|endpoint| {
+                endpoint.events.is_empty()
+                    || endpoint.events.iter().any(|e| e == event.name())
+            })
+            .collect();
+        if endpoints.is_empty() {
+            return;
+        }
+
+        let name = event.name();
+        let payload = Payload {
+            id: random_id(),
+            timestamp: current_timestamp(),
+            event,
+        };
+        let body = match serde_json::to_string(&payload) {
+            Ok(body) => body,
+            Err(why) => {
+                error!("Failed to serialize a webhook payload: {}", why);
+                return;
+            }
+        };
+
+        let mut queue = self.queue.lock().unwrap();
+        for endpoint in endpoints {
+            queue.push(Delivery {
+                id: payload.id.clone(),
+                url: endpoint.url.clone(),
+                event: name.to_string(),
+                body: body.clone(),
+                attempts: 0,
+                next_attempt: 0,
+            });
+        }
+        drop(queue);
+        self.changed.store(true, Ordering::SeqCst);
+        self.wake.notify_one();
+    }
+
+    /// Tries once to send every notification that is due and saves the
+    /// queue, returning how many were delivered.
+    pub async fn deliver_due(&self) -> usize {
+        let now = current_timestamp();
+        let due: Vec<Delivery> = self
+            .queue
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|delivery| delivery.next_attempt <= now)
+            .cloned()
+            .collect();
+
+        let mut delivered = 0;
+        for delivery in due {
+            let result = self.send(&delivery).await;
+
+            let mut queue = self.queue.lock().unwrap();
+            let index = match queue
+                .iter()
+                .position(|d| d.id == delivery.id && d.url == delivery.url)
+            {
+                Some(index) => index,
+                None => continue,
+            };
+            match result {
+                Ok(()) => {
+                    queue.remove(index);
+                    delivered += 1;
+                }
+                Err(why) => {
+                    let entry = &mut queue[index];
+                    entry.attempts += 1;
+                    if entry.attempts >= self.config.max_attempts {
+                        error!(
+                            "Dropping webhook {} to {} after {} attempts: {}",
+                            entry.id, entry.url, entry.attempts, why
+                        );
+                        queue.remove(index);
+                    } else {
+                        let delay = self.config.retry_delay(entry.attempts);
+                        warn!(
+                            "Webhook {} to {} failed, retrying in {}s: {}",
+                            entry.id, entry.url, delay, why
+                        );
+                        entry.next_attempt = current_timestamp() + delay;
+                    }
+                }
+            }
+            self.changed.store(true, Ordering::SeqCst);
+        }
+        self.save().await;
+        delivered
+    }
+
+    /// Delivers notifications as they are queued and retries failed ones
+    /// until the program exits.
+    pub async fn run(self: Arc<Self>) {
+        loop {
+            self.deliver_due().await;
+            let next = self
+                .queue
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|delivery| delivery.next_attempt)
+                .min();
+            match next {
+                Some(next) => {
+                    let delay = next.saturating_sub(current_timestamp()).max(1);
+                    tokio::select! {
+                        _ = self.wake.notified() => {}
+                        _ = tokio::time::sleep(Duration::from_secs(delay)) => {}
+                    }
+                }
+                None => self.wake.notified().await,
+            }
+        }
+    }
+
+    async fn send(&self, delivery: &Delivery) -> Result<(), String> {
+        let endpoint = self
+            .config
+            .endpoints
+            .iter()
+            .find(|endpoint| endpoint.url == delivery.url)
+            .ok_or("the endpoint is no longer configured")?;
+
+        let timestamp = current_timestamp();
+        let signature = sign(&endpoint.secret, timestamp, &delivery.body);
+        self.client
+            .post(&endpoint.url)
+            .timeout(Duration::from_secs(self.config.timeout_secs))
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(EVENT_HEADER, &delivery.event)
+            .header(TIMESTAMP_HEADER, timestamp.to_string())
+            .header(SIGNATURE_HEADER, format!("sha256={}", signature))
+            .body(delivery.body.clone())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map(|_| ())
+            .map_err(|why| why.to_string())
+    }
+
+    /// Writes the queue to the queue file if it changed, off the async
+    /// workers.
+    pub async fn save(&self) {
+        let _saving = self.saving.lock().await;
+        if !self.changed.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        let serialized = serde_json::to_string(&*self.queue.lock().unwrap());
+        let path = self.config.queue_file.clone();
+        let result = match serialized {
+            Ok(serialized) => tokio::task::spawn_blocking(move || {
+                write_queue(&path, &serialized)
+            })
+            .await
+            .map_err(std::io::Error::other)
+            .and_then(|result| result),
+            Err(why) => Err(why.into()),
+        };
+        if let Err(why) = result {
+            error!(
+                "Failed to save the webhook queue '{}': {}",
+                self.config.queue_file, why
+            );
+            // Tried again on the next save.
+            self.changed.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Writes the queue to a temporary file first so a crash never leaves it
+/// half written.
+fn write_queue(path: &str, serialized: &str) -> std::io::Result<()> {
+    let temp_path = format!("{}.tmp", path);
+    let mut file = File::create(&temp_path)?;
+    file.write_all(serialized.as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(&temp_path, path)
+}
@@ -0,0 +1,172 @@
+mod common;
+
+use common::*;
+use hyper::header::AUTHORIZATION;
+use hyper::{Body, Method, Request, StatusCode};
+use serde_json::Value;
+use serenity::model::prelude::MessageId;
+use sibyl_system::api;
+use sibyl_system::bot::BotState;
+use sibyl_system::userdb::{ProfileScope, SentimentResult};
+
+const TOKEN: &str = "secret";
+
+async fn call(
+    bot: &BotState,
+    method: Method,
+    path: &str,
+    token: Option<&str>,
+) -> (StatusCode, Value) {
+    let mut request = Request::builder().method(method).uri(path);
+    if let Some(token) = token {
+        request = request.header(AUTHORIZATION, format!("Bearer {}", token));
+    }
+    let response =
+        api::handle(bot, TOKEN, request.body(Body::empty()).unwrap()).await;
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+async fn get(bot: &BotState, path: &str) -> (StatusCode, Value) {
+    call(bot, Method::GET, path, Some(TOKEN)).await
+}
+
+async fn populated_bot() -> BotState {
+    let bot = bot();
+    let transport = MockTransport::default();
+    for (id, name, content) in [
+        (1, "alice", "good"),
+        (2, "bob", "awful"),
+        (3, "carol", "hello"),
+    ] {
+        let author = user(id, name);
+        for _ in 0..5 {
+            bot.record_message(&transport, &message(&author, content))
+                .await;
+        }
+    }
+    bot
+}
+
+#[tokio::test]
+async fn requires_the_token() {
+    let bot = populated_bot().await;
+    let path = format!("/guilds/{}/users/1", GUILD);
+    let (status, body) = call(&bot, Method::GET, &path, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["error"], "Invalid token");
+    let (status, _) = call(&bot, Method::GET, &path, Some("wrong")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = call(&bot, Method::DELETE, "/users/1", None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert!(bot
+        .database
+        .read()
+        .await
+        .get_user_profile(ProfileScope::Guild(GUILD), &user(1, "alice").id)
+        .is_some());
+}
+
+#[tokio::test]
+async fn returns_computed_data_and_history() {
+    let bot = populated_bot().await;
+    {
+        let mut db = bot.database.write().await;
+        db.add_sentiment_result_for_user(
+            ProfileScope::Guild(GUILD),
+            &user(1, "alice"),
+            SentimentResult::new(0.0, 0.5, 0.5, 0.5).for_message(MessageId(42)),
+        );
+    }
+
+    let (status, body) = get(&bot, &format!("/guilds/{}/users/1", GUILD)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["user_id"], "1");
+    assert_eq!(body["guild_id"], GUILD.to_string());
+    assert_eq!(body["user_tag"], "alice#0001");
+    assert_eq!(body["computed"]["samples"], 6);
+    assert_eq!(body["computed"]["insufficient_data"], false);
+    assert!(body["computed"]["crime_coefficient"].is_number());
+    assert!(body["computed"]["hue"]["state"].is_string());
+    let history = body["history"].as_array().unwrap();
+    assert_eq!(history.len(), 6);
+    assert_eq!(history[5]["compound"], 0.5);
+    assert_eq!(history[5]["message_id"], 42);
+
+    let (status, body) = get(&bot, &format!("/guilds/{}/users/9", GUILD)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["error"], "No such profile");
+    let (status, _) = get(&bot, "/guilds/x/users/1").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn pages_the_leaderboard() {
+    let bot = populated_bot().await;
+    let path = format!("/guilds/{}/leaderboard", GUILD);
+
+    let (status, body) = get(&bot, &path).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["order"], "highest");
+    let tags: Vec<&str> = body["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| entry["user_tag"].as_str().unwrap())
+        .collect();
+    assert_eq!(tags, vec!["bob#0001", "carol#0001", "alice#0001"]);
+
+    let (_, body) =
+        get(&bot, &format!("{}?order=lowest&per_page=2", path)).await;
+    assert_eq!(body["pages"], 2);
+    assert_eq!(body["entries"][0]["user_tag"], "alice#0001");
+    let (_, body) =
+        get(&bot, &format!("{}?order=lowest&per_page=2&page=2", path)).await;
+    assert_eq!(body["entries"][0]["rank"], 3);
+    assert_eq!(body["entries"][0]["user_id"], "2");
+
+    let (status, _) = get(&bot, &format!("{}?order=sideways", path)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = get(&bot, "/guilds/1/leaderboard").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn purges_profiles() {
+    let bot = populated_bot().await;
+    bot.record_message(
+        &MockTransport::default(),
+        &direct_message(&user(1, "alice"), "hi"),
+    )
+    .await;
+
+    let path = format!("/guilds/{}/users/3", GUILD);
+    let (status, body) = call(&bot, Method::DELETE, &path, Some(TOKEN)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["deleted"], 1);
+    let (status, _) = get(&bot, &path).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = call(&bot, Method::DELETE, &path, Some(TOKEN)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, body) =
+        call(&bot, Method::DELETE, "/users/1", Some(TOKEN)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["deleted"], 2);
+    let (status, _) = get(&bot, &format!("/guilds/{}/users/1", GUILD)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn describes_every_route() {
+    let bot = bot();
+    let (status, spec) = call(&bot, Method::GET, "/openapi.json", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(spec["openapi"], "3.0.3");
+    let paths = &spec["paths"];
+    assert!(paths["/guilds/{guild_id}/users/{user_id}"]["get"].is_object());
+    assert!(paths["/guilds/{guild_id}/users/{user_id}"]["delete"].is_object());
+    assert!(paths["/users/{user_id}"]["delete"].is_object());
+    assert!(paths["/guilds/{guild_id}/leaderboard"]["get"].is_object());
+}
@@ -0,0 +1,38 @@
+use sibyl_system::chart;
+use sibyl_system::userdb::SentimentResult;
+use std::path::Path;
+
+const NOW: u64 = 1_600_000_000;
+
+fn at(timestamp: u64, compound: f64) -> SentimentResult {
+    SentimentResult::new(0.2, 0.6, 0.2, compound).recorded_at(timestamp)
+}
+
+fn render(history: &[SentimentResult]) -> Vec<u8> {
+    let history: Vec<&SentimentResult> = history.iter().collect();
+    chart::render("alice#0001", &history).unwrap().data
+}
+
+#[test]
+fn plots_results_at_their_time() {
+    let early =
+        render(&[at(NOW, 0.5), at(NOW + 60, -0.5), at(NOW + 3600, 0.5)]);
+    let late =
+        render(&[at(NOW, 0.5), at(NOW + 3540, -0.5), at(NOW + 3600, 0.5)]);
+    assert_ne!(early, late);
+    // Results recorded together are drawn at the same time.
+    render(&[at(NOW, 0.5), at(NOW, -0.5)]);
+}
+
+#[test]
+fn loads_a_default_font_if_installed() {
+    assert!(chart::load_font("missing.ttf").is_err());
+    assert!(!chart::has_labels());
+
+    let installed = chart::DEFAULT_FONTS
+        .iter()
+        .any(|path| Path::new(path).exists());
+    assert_eq!(chart::load_default_font().is_some(), installed);
+    assert_eq!(chart::has_labels(), installed);
+    render(&[at(NOW, 0.5), at(NOW + 60, -0.5)]);
+}
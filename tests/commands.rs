@@ -0,0 +1,827 @@
+mod common;
+
+use common::*;
+use serenity::model::prelude::User;
+use sibyl_system::bot::BotState;
+use sibyl_system::chart;
+use sibyl_system::commands;
+use sibyl_system::leaderboard;
+use sibyl_system::usage::{self, UsageStats};
+use sibyl_system::userdb::{current_day, CoefficientSnapshot, ProfileScope};
+
+#[tokio::test]
+async fn analyze_replies_with_the_scores() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    let msg = message(&alice, "$analyze a great day");
+
+    commands::analyze(&bot, &transport, &msg, args("a great day"))
+        .await
+        .unwrap();
+
+    let sent = transport.sent();
+    assert_eq!(sent.len(), 1);
+    let (channel, reply) = &sent[0];
+    assert_eq!(*channel, CHANNEL);
+    assert!(reply.content.starts_with("**Sentiment Analysis**"));
+    assert!(reply.content.contains("\"compound\""));
+    let embed = reply.embed.as_ref().unwrap();
+    assert_eq!(embed.title.as_deref(), Some("alice#0001"));
+    assert_eq!(embed.description.as_deref(), Some("a great day"));
+
+    // Analyzing does not record anything.
+    let db = bot.database.read().await;
+    assert!(db
+        .get_user_profile(ProfileScope::Guild(GUILD), &alice.id)
+        .is_none());
+}
+
+#[tokio::test]
+async fn psycho_pass_scans_recorded_users() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    let bob = user(2, "bob");
+    record(&bot, &transport, &bob, "awful awful", 5).await;
+
+    let msg = message(&alice, "$psycho_pass 2");
+    commands::psycho_pass(&bot, &transport, &msg, args("2"))
+        .await
+        .unwrap();
+
+    let (_, reply) = transport.sent().pop().unwrap();
+    assert_eq!(reply.reply_to, Some(msg.id));
+    assert!(reply
+        .content
+        .starts_with("bob#0001 has a crime coefficient of: "));
+    let embed = reply.embed.unwrap();
+    assert_eq!(embed.title.as_deref(), Some("Psycho-Pass"));
+    let coefficient: f64 =
+        embed.field("Crime Coefficient").unwrap().parse().unwrap();
+    assert!(coefficient > 75.0);
+    assert!(embed.colour.is_some());
+    assert!(embed
+        .field("Confidence")
+        .unwrap()
+        .ends_with("from 5 message(s)"));
+}
+
+#[tokio::test]
+async fn psycho_pass_reports_insufficient_data() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    record(&bot, &transport, &alice, "good", 2).await;
+
+    let msg = message(&alice, "$psycho_pass");
+    commands::psycho_pass(&bot, &transport, &msg, args(""))
+        .await
+        .unwrap();
+    let (_, reply) = transport.sent().pop().unwrap();
+    assert_eq!(
+        reply.content,
+        "alice#0001 has too little data for a crime coefficient"
+    );
+    let embed = reply.embed.unwrap();
+    assert_eq!(embed.field("Crime Coefficient"), Some("Insufficient data"));
+
+    commands::config_set(&bot, &transport, &msg, args("min_samples 2"))
+        .await
+        .unwrap();
+    commands::psycho_pass(&bot, &transport, &msg, args(""))
+        .await
+        .unwrap();
+    assert!(transport
+        .last_content()
+        .starts_with("alice#0001 has a crime coefficient of: "));
+}
+
+#[tokio::test]
+async fn psycho_pass_handles_reset_profiles() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    record(&bot, &transport, &alice, "bad", 5).await;
+    let msg = message(&alice, "$psycho_pass");
+    // Without the decay an empty history used to average to NaN.
+    commands::config_set(&bot, &transport, &msg, args("half_life_hours 0"))
+        .await
+        .unwrap();
+    commands::debug_user_reset(&bot, &transport, &msg, args("1"))
+        .await
+        .unwrap();
+
+    commands::psycho_pass(&bot, &transport, &msg, args(""))
+        .await
+        .unwrap();
+    let (_, reply) = transport.sent().pop().unwrap();
+    let confidence = reply
+        .embed
+        .unwrap()
+        .field("Confidence")
+        .unwrap()
+        .to_string();
+    assert!(!confidence.contains("NaN"), "{}", confidence);
+    assert!(confidence.ends_with("from 0 message(s)"));
+}
+
+#[tokio::test]
+async fn config_set_rejects_huge_half_lives() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    let msg = message(&alice, "$config set half_life_hours 1e16");
+
+    commands::config_set(&bot, &transport, &msg, args("half_life_hours 1e16"))
+        .await
+        .unwrap();
+    assert!(transport
+        .last_content()
+        .starts_with("The half-life cannot be"));
+    assert!(bot
+        .database
+        .read()
+        .await
+        .guild_settings(GUILD)
+        .half_life_hours
+        .is_none());
+
+    // Overrides stored before the limit fall back to the default.
+    {
+        let mut db = bot.database.write().await;
+        let mut settings = db.guild_settings(GUILD);
+        settings.half_life_hours = Some(1e16);
+        db.set_guild_settings(GUILD, settings);
+    }
+    record(&bot, &transport, &alice, "bad", 1).await;
+    let config = bot
+        .database
+        .read()
+        .await
+        .guild_config(ProfileScope::Guild(GUILD));
+    assert_eq!(config.cymatic.half_life.as_secs(), 72 * 3600);
+}
+
+#[tokio::test]
+async fn psycho_pass_reports_errors() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    let msg = message(&alice, "$psycho_pass");
+
+    commands::psycho_pass(&bot, &transport, &msg, args(""))
+        .await
+        .unwrap();
+    assert_eq!(transport.last_content(), "No data recorded for <@1>");
+
+    commands::psycho_pass(&bot, &transport, &msg, args("nobody"))
+        .await
+        .unwrap();
+    assert_eq!(transport.last_content(), "Failed to find user nobody");
+
+    commands::psycho_pass(&bot, &transport, &msg, args("42"))
+        .await
+        .unwrap();
+    assert_eq!(transport.last_content(), "No data recorded for <@42>");
+
+    commands::psycho_pass(&bot, &transport, &msg, args("42 local"))
+        .await
+        .unwrap();
+    assert_eq!(transport.last_content(), "Unknown option local");
+}
+
+#[tokio::test]
+async fn psycho_pass_global_merges_guilds() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    bot.record_message(&transport, &direct_message(&alice, "good"))
+        .await;
+
+    let msg = message(&alice, "$psycho_pass 1 global");
+    commands::psycho_pass(&bot, &transport, &msg, args("1"))
+        .await
+        .unwrap();
+    assert_eq!(transport.last_content(), "No data recorded for <@1>");
+
+    commands::psycho_pass(&bot, &transport, &msg, args("global"))
+        .await
+        .unwrap();
+    let (_, reply) = transport.sent().pop().unwrap();
+    let embed = reply.embed.unwrap();
+    assert_eq!(embed.title.as_deref(), Some("Psycho-Pass (all servers)"));
+}
+
+/// Records a message for each user and scans `query`, returning the reply.
+async fn scan(
+    transport: &MockTransport,
+    users: &[&serenity::model::user::User],
+    msg: &sibyl_system::transport::IncomingMessage,
+    query: &str,
+) -> String {
+    let bot = bot();
+    for user in users {
+        bot.record_message(transport, &message(user, "good")).await;
+    }
+    transport.clear();
+    commands::psycho_pass(&bot, transport, msg, args(query))
+        .await
+        .unwrap();
+    transport.last_content()
+}
+
+#[tokio::test]
+async fn psycho_pass_resolves_targets() {
+    let alice = user(1, "alice");
+    let bob = user(2, "bob");
+    let robert = user(3, "robert");
+    let mut transport = MockTransport::default();
+    transport.members = vec![
+        member(&alice, None),
+        member(&bob, Some("Bobby")),
+        member(&robert, Some("Rob")),
+    ];
+    let users = [&alice, &bob, &robert];
+    let msg = message(&alice, "$psycho_pass");
+
+    for query in ["<@2>", "<@!2>", "2", "bob#0001", "BOBBY", "bobb"] {
+        let reply = scan(&transport, &users, &msg, query).await;
+        assert!(reply.starts_with("bob#0001 has"), "{}: {}", query, reply);
+    }
+
+    let reply = scan(&transport, &users, &msg, "rob").await;
+    assert!(reply.starts_with("robert#0001 has"), "{}", reply);
+
+    let reply = scan(&transport, &users, &msg, "alise").await;
+    assert!(reply.starts_with("alice#0001 has"), "{}", reply);
+}
+
+#[tokio::test]
+async fn psycho_pass_lists_ambiguous_targets() {
+    let alice = user(1, "alice");
+    let alicia = user(2, "alicia");
+    let transport = MockTransport::default();
+    let msg = message(&alice, "$psycho_pass ali");
+
+    let reply = scan(&transport, &[&alice, &alicia], &msg, "ali").await;
+    assert!(reply.starts_with("ali matches several users"));
+    assert!(reply.contains("alice#0001 (1)"));
+    assert!(reply.contains("alicia#0001 (2)"));
+}
+
+#[tokio::test]
+async fn psycho_pass_defaults_to_the_replied_to_author() {
+    let alice = user(1, "alice");
+    let bob = user(2, "bob");
+    let transport = MockTransport::default();
+    let mut msg = message(&alice, "$psycho_pass");
+
+    let reply = scan(&transport, &[&alice, &bob], &msg, "").await;
+    assert!(reply.starts_with("alice#0001 has"));
+
+    msg.referenced_author = Some(bob.clone());
+    let reply = scan(&transport, &[&alice, &bob], &msg, "").await;
+    assert!(reply.starts_with("bob#0001 has"));
+}
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+#[tokio::test]
+async fn psycho_pass_attaches_a_chart() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    record(&bot, &transport, &alice, "good", 3).await;
+    record(&bot, &transport, &alice, "awful", 3).await;
+
+    let msg = message(&alice, "$psycho_pass alice --chart");
+    commands::psycho_pass(&bot, &transport, &msg, args("alice --chart"))
+        .await
+        .unwrap();
+    let (_, reply) = transport.sent().pop().unwrap();
+    assert_eq!(reply.attachments.len(), 1);
+    let attachment = &reply.attachments[0];
+    assert_eq!(attachment.filename, "chart.png");
+    assert!(attachment.data.starts_with(PNG_SIGNATURE));
+    assert_eq!(reply.embed.unwrap().image.as_deref(), Some("chart.png"));
+    assert!(reply.content.ends_with(chart::MISSING_FONT));
+
+    commands::psycho_pass(&bot, &transport, &msg, args("alice"))
+        .await
+        .unwrap();
+    let (_, reply) = transport.sent().pop().unwrap();
+    assert!(reply.attachments.is_empty());
+}
+
+#[tokio::test]
+async fn chart_renders_the_history() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    let msg = message(&alice, "$chart");
+
+    commands::chart(&bot, &transport, &msg, args(""))
+        .await
+        .unwrap();
+    assert_eq!(transport.last_content(), "No data recorded for <@1>");
+
+    // A single message still makes a chart.
+    record(&bot, &transport, &alice, "bad", 1).await;
+    commands::chart(&bot, &transport, &msg, args(""))
+        .await
+        .unwrap();
+    let (_, reply) = transport.sent().pop().unwrap();
+    // No font is loaded in the tests.
+    assert_eq!(
+        reply.content,
+        format!("Sentiment history of alice#0001\n{}", chart::MISSING_FONT)
+    );
+    assert!(reply.attachments[0].data.starts_with(PNG_SIGNATURE));
+
+    let msg = message(&alice, "$debug user reset 1");
+    commands::debug_user_reset(&bot, &transport, &msg, args("1"))
+        .await
+        .unwrap();
+    commands::chart(&bot, &transport, &msg, args(""))
+        .await
+        .unwrap();
+    assert_eq!(transport.last_content(), "There is no history to chart");
+}
+
+#[tokio::test]
+async fn recording_snapshots_the_coefficient_daily() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    record(&bot, &transport, &alice, "good", 4).await;
+    {
+        let db = bot.database.read().await;
+        let profile = db
+            .get_user_profile(ProfileScope::Guild(GUILD), &alice.id)
+            .unwrap();
+        assert!(profile.snapshots().is_empty());
+    }
+
+    record(&bot, &transport, &alice, "good", 2).await;
+    let mut db = bot.database.write().await;
+    let profile = db
+        .get_user_profile(ProfileScope::Guild(GUILD), &alice.id)
+        .unwrap();
+    assert_eq!(profile.snapshots().len(), 1);
+    assert_eq!(profile.snapshots()[0].day, current_day());
+    assert_eq!(db.take_snapshots(), 0);
+}
+
+#[tokio::test]
+async fn history_shows_the_trend() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    let bob = user(2, "bob");
+    record(&bot, &transport, &bob, "good", 1).await;
+    let today = current_day();
+    {
+        let mut db = bot.database.write().await;
+        let profile = db
+            .get_user_profile_as_mut(ProfileScope::Guild(GUILD), &bob.id)
+            .unwrap();
+        for (days_ago, crime_coefficient) in
+            [(20, 10.0), (9, 50.0), (7, 60.0), (1, 100.0), (0, 90.0)]
+        {
+            profile.record_snapshot(CoefficientSnapshot {
+                day: today - days_ago,
+                crime_coefficient,
+            });
+        }
+    }
+
+    let msg = message(&alice, "$history bob 14d");
+    commands::history(&bot, &transport, &msg, args("bob 14d"))
+        .await
+        .unwrap();
+    let content = transport.last_content();
+    assert!(content.starts_with("**bob#0001, last 14 day(s)**"));
+    assert!(
+        content.contains("```\n    ▁ ▂     █▇\n```\n"),
+        "{}",
+        content
+    );
+    assert!(content.contains("From 50.0 to 90.0, lowest 50.0, highest 100.0"));
+    assert!(content.contains("Since last week: +30.0"));
+
+    // A lone user is not taken for a period.
+    let msg = message(&alice, "$history bob");
+    commands::history(&bot, &transport, &msg, args("bob"))
+        .await
+        .unwrap();
+    assert!(transport
+        .last_content()
+        .starts_with("**bob#0001, last 30 day(s)**"));
+
+    let msg = message(&bob, "$history 2w");
+    commands::history(&bot, &transport, &msg, args("2w"))
+        .await
+        .unwrap();
+    assert!(transport
+        .last_content()
+        .starts_with("**bob#0001, last 14 day(s)**"));
+
+    commands::history(&bot, &transport, &msg, args("bob fortnight"))
+        .await
+        .unwrap();
+    assert_eq!(transport.last_content(), "Unknown period fortnight");
+
+    let msg = message(&alice, "$history");
+    commands::history(&bot, &transport, &msg, args(""))
+        .await
+        .unwrap();
+    assert_eq!(transport.last_content(), "No data recorded for <@1>");
+}
+
+#[tokio::test]
+async fn stats_counts_messages_and_commands() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    bot.record_message(&transport, &message(&alice, "good"))
+        .await;
+    bot.record_message(&transport, &message(&alice, "bad"))
+        .await;
+    bot.record_message(&transport, &direct_message(&alice, "good"))
+        .await;
+    bot.count_command(Some(GUILD), "stats").await;
+    bot.count_command(None, "analyze").await;
+
+    let msg = message(&alice, "$stats");
+    commands::stats(&bot, &transport, &msg, args(""))
+        .await
+        .unwrap();
+    let content = transport.last_content();
+    assert!(
+        content.contains("System has analyzed 2 user message(s) in this guild")
+    );
+    assert!(content.contains("Today: 2 message(s), 1 command(s)"));
+    assert!(content.contains(&format!(
+        "{:<12}{:>9}{:>9}",
+        usage::day_name(current_day()),
+        2,
+        1
+    )));
+    assert!(content.contains("__stats__: 1 (1 today)"));
+    assert!(!content.contains("__analyze__"));
+
+    commands::stats(&bot, &transport, &msg, args("global"))
+        .await
+        .unwrap();
+    let content = transport.last_content();
+    assert!(content.contains("System has analyzed 3 user message(s)\n"));
+    assert!(content.contains("__analyze__: 1 (1 today)"));
+
+    commands::msg_count(&bot, &transport, &msg).await.unwrap();
+    assert_eq!(
+        transport.last_content(),
+        "System has analyzed 3 user message(s)"
+    );
+}
+
+#[test]
+fn usage_summary_covers_the_last_week() {
+    let mut stats = UsageStats::default();
+    let scope = ProfileScope::Guild(GUILD);
+    let today = 20_000;
+    stats.day_mut(scope, today - 10).messages = 5;
+    stats.day_mut(scope, today - 6).messages = 3;
+    stats.day_mut(scope, today).messages = 1;
+    stats.day_mut(ProfileScope::DirectMessages, today).messages = 2;
+
+    let summary = stats.summary(Some(GUILD), today);
+    assert_eq!(summary.total.messages, 9);
+    assert_eq!(summary.today.messages, 1);
+    let week: Vec<(u64, u64)> = summary
+        .week
+        .iter()
+        .map(|(day, usage)| (*day, usage.messages))
+        .collect();
+    assert_eq!(week.len(), 7);
+    assert_eq!(week[0], (today - 6, 3));
+    assert_eq!(week[6], (today, 1));
+    assert_eq!(stats.summary(None, today).today.messages, 3);
+    assert_eq!(usage::day_name(today), "2024-10-04");
+}
+
+/// Records `count` messages of `text` by `author` in the test guild.
+async fn record(
+    bot: &BotState,
+    transport: &MockTransport,
+    author: &User,
+    text: &str,
+    count: usize,
+) {
+    for _ in 0..count {
+        bot.record_message(transport, &message(author, text)).await;
+    }
+}
+
+#[tokio::test]
+async fn leaderboard_ranks_users_with_enough_messages() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    let bob = user(2, "bob");
+    let carol = user(3, "carol");
+    record(&bot, &transport, &alice, "great", 5).await;
+    record(&bot, &transport, &bob, "awful", 5).await;
+    record(&bot, &transport, &carol, "awful", 2).await;
+
+    let msg = message(&alice, "$leaderboard");
+    commands::leaderboard(&bot, &transport, &msg, args(""))
+        .await
+        .unwrap();
+    let (_, reply) = transport.sent().pop().unwrap();
+    let description = reply.embed.unwrap().description.unwrap();
+    let bob_line = description.find("bob#0001").unwrap();
+    let alice_line = description.find("alice#0001").unwrap();
+    assert!(bob_line < alice_line);
+    assert!(!description.contains("carol#0001"));
+    assert!(description.contains("1 user(s) with fewer than 5 messages"));
+    assert!(reply.buttons.is_empty());
+
+    commands::leaderboard(&bot, &transport, &msg, args("lowest"))
+        .await
+        .unwrap();
+    let (_, reply) = transport.sent().pop().unwrap();
+    let description = reply.embed.unwrap().description.unwrap();
+    assert!(description.starts_with("`  1.` alice#0001"));
+
+    commands::leaderboard(&bot, &transport, &msg, args("2"))
+        .await
+        .unwrap();
+    assert_eq!(transport.last_content(), "There are only 1 page(s)");
+}
+
+#[tokio::test]
+async fn leaderboard_pages_with_buttons() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    for id in 1..=12 {
+        let author = user(id, &format!("user{}", id));
+        record(&bot, &transport, &author, "good", 5).await;
+    }
+
+    let msg = message(&user(1, "user1"), "$leaderboard 2");
+    commands::leaderboard(&bot, &transport, &msg, args("2"))
+        .await
+        .unwrap();
+    let (_, reply) = transport.sent().pop().unwrap();
+    let description = reply.embed.unwrap().description.unwrap();
+    assert!(description.contains("` 11.`"));
+    assert!(description.contains("Page 2 of 2"));
+    let buttons: Vec<_> = reply
+        .buttons
+        .iter()
+        .map(|b| (b.custom_id.as_str(), b.disabled))
+        .collect();
+    assert_eq!(
+        buttons,
+        vec![
+            ("leaderboard:highest:1", false),
+            ("leaderboard:highest:3", true)
+        ]
+    );
+
+    let (order, page) =
+        leaderboard::parse_page_button("leaderboard:highest:1").unwrap();
+    let first = commands::leaderboard_page(&bot, GUILD, order, page).await;
+    let description = first.embed.unwrap().description.unwrap();
+    assert!(description.contains("` 10.`"));
+    assert!(!description.contains("` 11.`"));
+}
+
+#[tokio::test]
+async fn distribution_renders_a_histogram() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    let bob = user(2, "bob");
+    record(&bot, &transport, &alice, "good", 5).await;
+    record(&bot, &transport, &bob, "good", 1).await;
+
+    let msg = message(&alice, "$distribution");
+    commands::distribution(&bot, &transport, &msg, args(""))
+        .await
+        .unwrap();
+    let content = transport.last_content();
+    assert!(content.starts_with("**Crime coefficients of 1 user(s)**"));
+    assert_eq!(content.matches('|').count(), 12);
+    assert!(content.contains("#################### 1"));
+    assert!(content.contains("1 user(s) with fewer than 5 messages left out"));
+}
+
+#[tokio::test]
+async fn optout_purges_and_stops_recording() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    bot.record_message(&transport, &message(&alice, "good"))
+        .await;
+    bot.record_message(&transport, &direct_message(&alice, "good"))
+        .await;
+
+    let msg = message(&alice, "$optout");
+    commands::optout(&bot, &transport, &msg, args(""))
+        .await
+        .unwrap();
+    assert!(transport
+        .last_content()
+        .contains("2 profile(s) were deleted"));
+    bot.record_message(&transport, &message(&alice, "bad"))
+        .await;
+    assert!(bot.database.read().await.user_data(&alice.id).is_empty());
+
+    commands::optin(&bot, &transport, &msg, args(""))
+        .await
+        .unwrap();
+    assert_eq!(transport.last_content(), "Your messages are analyzed again");
+    bot.record_message(&transport, &message(&alice, "bad"))
+        .await;
+    assert_eq!(bot.database.read().await.user_data(&alice.id).len(), 1);
+
+    commands::optin(&bot, &transport, &msg, args(""))
+        .await
+        .unwrap();
+    assert_eq!(transport.last_content(), "You have not opted out");
+}
+
+#[tokio::test]
+async fn mydata_sends_the_profiles_in_a_direct_message() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    let bob = user(2, "bob");
+    bot.record_message(&transport, &message(&alice, "good"))
+        .await;
+    bot.record_message(&transport, &message(&bob, "bad")).await;
+
+    let msg = message(&alice, "$mydata");
+    commands::mydata(&bot, &transport, &msg, args(""))
+        .await
+        .unwrap();
+    let sent = transport.sent();
+    let (channel, export) = &sent[sent.len() - 2];
+    assert_eq!(*channel, dm_channel(alice.id));
+    let data: serde_json::Value =
+        serde_json::from_slice(&export.attachments[0].data).unwrap();
+    assert_eq!(data["profiles"]["guild 1000"]["user_tag"], "alice#0001");
+    assert_eq!(data["opted_out"], false);
+    assert!(!String::from_utf8_lossy(&export.attachments[0].data)
+        .contains("bob#0001"));
+    assert_eq!(
+        transport.last_content(),
+        "Sent your data in a direct message"
+    );
+}
+
+#[tokio::test]
+async fn forgetme_deletes_the_profiles() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    bot.record_message(&transport, &message(&alice, "good"))
+        .await;
+
+    let msg = message(&alice, "$forgetme");
+    commands::forgetme(&bot, &transport, &msg, args(""))
+        .await
+        .unwrap();
+    assert!(transport
+        .last_content()
+        .starts_with("Deleted 1 profile(s)."));
+    assert!(bot.database.read().await.user_data(&alice.id).is_empty());
+
+    bot.record_message(&transport, &message(&alice, "good"))
+        .await;
+    assert_eq!(bot.database.read().await.user_data(&alice.id).len(), 1);
+}
+
+#[tokio::test]
+async fn scope_manages_the_lists() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let owner = user(1, "owner");
+    let run = |text: &str| message(&owner, text);
+
+    let msg = run("$scope deny channel <#2001>");
+    commands::scope_deny(&bot, &transport, &msg, args("channel <#2001>"))
+        .await
+        .unwrap();
+    assert_eq!(transport.last_content(), "<#2001> is denied");
+
+    let msg = run("$scope allow role 4000");
+    commands::scope_allow(&bot, &transport, &msg, args("role 4000"))
+        .await
+        .unwrap();
+    let msg = run("$scope allow channel 2001");
+    commands::scope_allow(&bot, &transport, &msg, args("channel 2001"))
+        .await
+        .unwrap();
+    let msg = run("$scope nsfw off");
+    commands::scope_nsfw(&bot, &transport, &msg, args("off"))
+        .await
+        .unwrap();
+    let msg = run("$scope remove role 4000");
+    commands::scope_remove(&bot, &transport, &msg, args("role 4000"))
+        .await
+        .unwrap();
+    assert_eq!(transport.last_content(), "<@&4000> is no longer listed");
+
+    let msg = run("$scope allow server 1");
+    commands::scope_allow(&bot, &transport, &msg, args("server 1"))
+        .await
+        .unwrap();
+    assert!(transport.last_content().starts_with("Usage:"));
+
+    let msg = run("$scope show");
+    commands::scope_show(&bot, &transport, &msg, args(""))
+        .await
+        .unwrap();
+    let shown = transport.last_content();
+    assert!(shown.contains("Allowed channels: <#2001>"));
+    assert!(shown.contains("Denied channels: none"));
+    assert!(shown.contains("Allowed roles: none"));
+    assert!(shown.contains("NSFW channels: skipped"));
+}
+
+#[tokio::test]
+async fn debug_user_dumps_and_resets_profiles() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    bot.record_message(&transport, &message(&alice, "good"))
+        .await;
+    let msg = message(&alice, "$debug user dump 1");
+
+    commands::debug_user_dump(&bot, &transport, &msg, args("1"))
+        .await
+        .unwrap();
+    let dump = transport.last_content();
+    assert!(dump.starts_with("```json"));
+    assert!(dump.contains("alice#0001"));
+
+    commands::debug_user_reset(&bot, &transport, &msg, args("1"))
+        .await
+        .unwrap();
+    assert_eq!(transport.last_content(), "1 profile reset");
+    let db = bot.database.read().await;
+    let profile = db
+        .get_user_profile(ProfileScope::Guild(GUILD), &alice.id)
+        .unwrap();
+    assert!(profile.history().is_empty());
+}
+
+#[tokio::test]
+async fn debug_migrate_moves_unscoped_profiles() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    {
+        let mut db = bot.database.write().await;
+        db.add_sentiment_result_for_user(
+            ProfileScope::Unscoped,
+            &alice,
+            bot.analyzer.analyze("good"),
+        );
+    }
+
+    let msg = message(&alice, "$debug migrate");
+    commands::debug_migrate(&bot, &transport, &msg, args(""))
+        .await
+        .unwrap();
+    assert_eq!(transport.last_content(), "Migrated 1 profile(s) to 1000");
+
+    let db = bot.database.read().await;
+    assert!(db
+        .get_user_profile(ProfileScope::Guild(GUILD), &alice.id)
+        .is_some());
+}
+
+#[tokio::test]
+async fn test_convertcc_uses_the_guild_parameters() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    let msg = message(&alice, "$debug convert 0");
+
+    commands::test_convertcc(&bot, &transport, &msg, args("0"))
+        .await
+        .unwrap();
+    assert_eq!(transport.last_content(), "75.0");
+
+    commands::config_set(&bot, &transport, &msg, args("cc_neutral 50"))
+        .await
+        .unwrap();
+    commands::test_convertcc(&bot, &transport, &msg, args("0"))
+        .await
+        .unwrap();
+    assert_eq!(transport.last_content(), "50.0");
+}
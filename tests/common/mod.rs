@@ -0,0 +1,237 @@
+//! A stand-in for Discord: a transport that records everything the bot sends
+//! and helpers to build the messages it receives.
+
+#![allow(dead_code)]
+
+use serenity::async_trait;
+use serenity::framework::standard::{Args, Delimiter};
+use serenity::model::prelude::*;
+use sibyl_system::analyzer::LexiconAnalyzer;
+use sibyl_system::bot::BotState;
+use sibyl_system::config::Config;
+use sibyl_system::scope::ChannelInfo;
+use sibyl_system::storage::{PendingChanges, ProfileStorage, StorageResult};
+use sibyl_system::transport::{
+    IncomingMessage, MemberInfo, OutgoingMessage, Transport, TransportResult,
+};
+use sibyl_system::userdb::{
+    current_day, DatabaseContents, UserProfilesDatabase,
+};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::RwLock;
+
+pub const GUILD: GuildId = GuildId(1000);
+pub const CHANNEL: ChannelId = ChannelId(2000);
+
+/// Something the bot did through the transport.
+#[derive(Debug, Clone, PartialEq)]
+#[allow(clippy::large_enum_variant)]
+pub enum Call {
+    Send(ChannelId, OutgoingMessage),
+    AddRole(GuildId, UserId, RoleId),
+    Timeout(GuildId, UserId, u64),
+}
+
+#[derive(Default)]
+pub struct MockTransport {
+    actions: Mutex<Vec<Call>>,
+    /// The guild members known to `search_members`.
+    pub members: Vec<MemberInfo>,
+    /// The channels known to `channel_info`, others have no category.
+    pub channels: HashMap<ChannelId, ChannelInfo>,
+    channel_lookups: AtomicU64,
+}
+
+impl MockTransport {
+    pub fn actions(&self) -> Vec<Call> {
+        self.actions.lock().unwrap().clone()
+    }
+
+    /// The messages sent so far, in order.
+    pub fn sent(&self) -> Vec<(ChannelId, OutgoingMessage)> {
+        self.actions()
+            .into_iter()
+            .filter_map(|call| match call {
+                Call::Send(channel, message) => Some((channel, message)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The content of the last message sent.
+    pub fn last_content(&self) -> String {
+        let sent = self.sent();
+        let (_, message) = sent.last().expect("No message was sent");
+        message.content.clone()
+    }
+
+    /// How many times `channel_info` was called.
+    pub fn channel_lookups(&self) -> u64 {
+        self.channel_lookups.load(Ordering::SeqCst)
+    }
+
+    pub fn clear(&self) {
+        self.actions.lock().unwrap().clear();
+    }
+
+    fn record(&self, call: Call) -> TransportResult<()> {
+        self.actions.lock().unwrap().push(call);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Transport for MockTransport {
+    async fn send(
+        &self,
+        channel_id: ChannelId,
+        message: OutgoingMessage,
+    ) -> TransportResult<()> {
+        self.record(Call::Send(channel_id, message))
+    }
+
+    async fn add_member_role(
+        &self,
+        guild_id: GuildId,
+        user_id: UserId,
+        role_id: RoleId,
+    ) -> TransportResult<()> {
+        self.record(Call::AddRole(guild_id, user_id, role_id))
+    }
+
+    async fn timeout_member(
+        &self,
+        guild_id: GuildId,
+        user_id: UserId,
+        minutes: u64,
+    ) -> TransportResult<()> {
+        self.record(Call::Timeout(guild_id, user_id, minutes))
+    }
+
+    async fn search_members(
+        &self,
+        _guild_id: GuildId,
+        query: &str,
+    ) -> TransportResult<Vec<MemberInfo>> {
+        let query = query.to_lowercase();
+        Ok(self
+            .members
+            .iter()
+            .filter(|member| {
+                member.user.name.to_lowercase().starts_with(&query)
+                    || member.nick.as_ref().is_some_and(|nick| {
+                        nick.to_lowercase().starts_with(&query)
+                    })
+            })
+            .cloned()
+            .collect())
+    }
+
+    async fn direct_message_channel(
+        &self,
+        user_id: UserId,
+    ) -> TransportResult<ChannelId> {
+        Ok(dm_channel(user_id))
+    }
+
+    async fn channel_info(
+        &self,
+        channel_id: ChannelId,
+    ) -> TransportResult<ChannelInfo> {
+        self.channel_lookups.fetch_add(1, Ordering::SeqCst);
+        Ok(self.channels.get(&channel_id).copied().unwrap_or_default())
+    }
+}
+
+/// The channel of the direct messages with a user.
+pub fn dm_channel(user_id: UserId) -> ChannelId {
+    ChannelId(100_000 + user_id.0)
+}
+
+/// Keeps the database in memory only.
+pub struct MemoryStorage;
+
+impl ProfileStorage for MemoryStorage {
+    fn name(&self) -> String {
+        "memory".to_string()
+    }
+
+    fn load(&mut self) -> StorageResult<Option<DatabaseContents>> {
+        Ok(None)
+    }
+
+    fn save(
+        &mut self,
+        _contents: &DatabaseContents,
+        _changes: &PendingChanges,
+    ) -> StorageResult<()> {
+        Ok(())
+    }
+}
+
+const LEXICON: &str = "good\t2.0\ngreat\t3.0\nbad\t-2.5\nawful\t-3.5\n";
+
+pub fn bot() -> BotState {
+    let database = UserProfilesDatabase::open(
+        Box::new(MemoryStorage),
+        Config::default().guild_defaults(),
+    );
+    BotState::new(
+        Arc::new(RwLock::new(database)),
+        Arc::new(LexiconAnalyzer::from_lexicon(LEXICON)),
+    )
+}
+
+/// The number of messages analyzed everywhere.
+pub async fn analyzed(bot: &BotState) -> u64 {
+    let db = bot.database.read().await;
+    db.usage().summary(None, current_day()).total.messages
+}
+
+pub fn member(user: &User, nick: Option<&str>) -> MemberInfo {
+    MemberInfo {
+        user: user.clone(),
+        nick: nick.map(str::to_string),
+    }
+}
+
+pub fn user(id: u64, name: &str) -> User {
+    // The default user is a bot.
+    let mut user = User::default();
+    user.id = UserId(id);
+    user.bot = false;
+    user.avatar = None;
+    user.name = name.to_string();
+    user.discriminator = 1;
+    user
+}
+
+static NEXT_MESSAGE_ID: AtomicU64 = AtomicU64::new(1);
+
+/// A message sent in the test guild.
+pub fn message(author: &User, content: &str) -> IncomingMessage {
+    IncomingMessage {
+        id: MessageId(NEXT_MESSAGE_ID.fetch_add(1, Ordering::SeqCst)),
+        channel_id: CHANNEL,
+        guild_id: Some(GUILD),
+        author: author.clone(),
+        roles: Vec::new(),
+        content: content.to_string(),
+        timestamp: "2021-06-01T12:00:00+00:00".to_string(),
+        referenced_author: None,
+    }
+}
+
+/// A direct message.
+pub fn direct_message(author: &User, content: &str) -> IncomingMessage {
+    IncomingMessage {
+        guild_id: None,
+        ..message(author, content)
+    }
+}
+
+pub fn args(text: &str) -> Args {
+    Args::new(text, &[Delimiter::Single(' ')])
+}
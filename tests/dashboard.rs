@@ -0,0 +1,138 @@
+#![cfg(feature = "dashboard")]
+
+mod common;
+
+use common::*;
+use hyper::header::{AUTHORIZATION, COOKIE, LOCATION, SET_COOKIE};
+use hyper::{Body, Request, Response, StatusCode};
+use sibyl_system::bot::BotState;
+use sibyl_system::dashboard;
+
+const TOKEN: &str = "secret";
+
+async fn body(response: Response<Body>) -> String {
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    String::from_utf8_lossy(&body).into_owned()
+}
+
+async fn get(bot: &BotState, path: &str) -> (StatusCode, String) {
+    let request = Request::get(path)
+        .header(AUTHORIZATION, format!("Bearer {}", TOKEN))
+        .body(Body::empty())
+        .unwrap();
+    let response = dashboard::handle(bot, TOKEN, request).await;
+    (response.status(), body(response).await)
+}
+
+async fn crossing_bot() -> BotState {
+    let bot = bot();
+    let transport = MockTransport::default();
+    {
+        let mut db = bot.database.write().await;
+        let mut settings = db.guild_settings(GUILD);
+        settings.audit_log.boundaries = vec![80.0];
+        settings.min_samples = Some(1);
+        db.set_guild_settings(GUILD, settings);
+    }
+    let alice = user(1, "alice");
+    bot.record_message(&transport, &message(&alice, "hello"))
+        .await;
+    bot.record_message(&transport, &message(&alice, "awful awful awful"))
+        .await;
+    bot.record_message(&transport, &message(&user(2, "<b>bob</b>"), "good"))
+        .await;
+    bot
+}
+
+#[tokio::test]
+async fn requires_the_token() {
+    let bot = bot();
+    let request = Request::get("/").body(Body::empty()).unwrap();
+    let response = dashboard::handle(&bot, TOKEN, request).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let request = Request::get("/")
+        .header(AUTHORIZATION, "Bearer wrong")
+        .body(Body::empty())
+        .unwrap();
+    let response = dashboard::handle(&bot, TOKEN, request).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let (status, _) = get(&bot, "/").await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn signs_in_with_a_cookie() {
+    let bot = bot();
+    let request = Request::post("/login")
+        .body(Body::from("token=wrong"))
+        .unwrap();
+    let response = dashboard::handle(&bot, TOKEN, request).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(response.headers().get(SET_COOKIE).is_none());
+
+    let request = Request::post("/login")
+        .body(Body::from(format!("token={}", TOKEN)))
+        .unwrap();
+    let response = dashboard::handle(&bot, TOKEN, request).await;
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert_eq!(response.headers()[LOCATION], "/");
+    let cookie = response.headers()[SET_COOKIE].to_str().unwrap();
+    assert!(cookie.contains("HttpOnly"));
+    let cookie = cookie.split(';').next().unwrap().to_string();
+
+    let request = Request::get("/")
+        .header(COOKIE, cookie)
+        .body(Body::empty())
+        .unwrap();
+    let response = dashboard::handle(&bot, TOKEN, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn shows_guilds_leaderboards_and_crossings() {
+    let bot = crossing_bot().await;
+
+    let (status, page) = get(&bot, "/").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(page.contains(&format!("href=\"/guilds/{0}\">{0}</a>", GUILD)));
+    assert!(page.contains("3 message(s) analyzed"));
+    assert!(page.contains("Recent threshold crossings"));
+    assert!(page.contains("alice#0001"));
+
+    let (status, page) = get(&bot, &format!("/guilds/{}", GUILD)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(page.contains("Leaderboard (highest)"));
+
+    let (status, page) = get(&bot, &format!("/guilds/{}/users/2", GUILD)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(page.contains("&lt;b&gt;bob&lt;/b&gt;"));
+    assert!(!page.contains("<b>bob</b>"));
+
+    let (status, _) = get(&bot, "/guilds/1").await;
+
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn shows_user_history_and_chart() {
+    let bot = crossing_bot().await;
+
+    let (status, page) = get(&bot, &format!("/guilds/{}/users/1", GUILD)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(page.contains("<h1>alice#0001</h1>"));
+    assert!(page.contains("Sentiment history"));
+    assert!(page.contains(&format!("/guilds/{}/users/1/chart.png", GUILD)));
+
+    let request = Request::get(format!("/guilds/{}/users/1/chart.png", GUILD))
+        .header(AUTHORIZATION, format!("Bearer {}", TOKEN))
+        .body(Body::empty())
+        .unwrap();
+    let response = dashboard::handle(&bot, TOKEN, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "image/png");
+
+    let (status, _) = get(&bot, &format!("/guilds/{}/users/99", GUILD)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
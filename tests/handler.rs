@@ -0,0 +1,463 @@
+mod common;
+
+use common::{Call, *};
+use serenity::model::prelude::*;
+use sibyl_system::analyzer::SentimentAnalyzer;
+use sibyl_system::bot::BotState;
+use sibyl_system::config::{WebhookConfig, WebhookEndpoint};
+use sibyl_system::dominator::{EnforcementAction, EnforcementTier};
+use sibyl_system::scope::{ChannelInfo, ScopeTarget};
+use sibyl_system::transport::IncomingMessage;
+use sibyl_system::userdb::{ProfileScope, SentimentResult};
+use sibyl_system::webhook::Webhooks;
+use std::sync::Arc;
+
+#[tokio::test]
+async fn records_messages_in_the_guild_scope() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+
+    bot.record_message(&transport, &message(&alice, "what a good day"))
+        .await;
+    bot.record_message(&transport, &message(&alice, "great"))
+        .await;
+
+    let db = bot.database.read().await;
+    let profile = db
+        .get_user_profile(ProfileScope::Guild(GUILD), &alice.id)
+        .expect("Profile was not recorded");
+    assert_eq!(profile.history().len(), 2);
+    assert_eq!(profile.user_tag(), "alice#0001");
+    assert!(db
+        .get_user_profile(ProfileScope::DirectMessages, &alice.id)
+        .is_none());
+    assert_eq!(analyzed(&bot).await, 2);
+    assert!(transport.actions().is_empty());
+}
+
+#[tokio::test]
+async fn records_direct_messages_separately() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+
+    bot.record_message(&transport, &direct_message(&alice, "bad"))
+        .await;
+
+    let db = bot.database.read().await;
+    assert!(db
+        .get_user_profile(ProfileScope::DirectMessages, &alice.id)
+        .is_some());
+    assert!(db
+        .get_user_profile(ProfileScope::Guild(GUILD), &alice.id)
+        .is_none());
+}
+
+/// Fails the test if anything is analyzed.
+struct NoAnalyzer;
+
+impl SentimentAnalyzer for NoAnalyzer {
+    fn name(&self) -> &str {
+        "none"
+    }
+
+    fn analyze(&self, msg: &str) -> SentimentResult {
+        panic!("'{}' was analyzed", msg);
+    }
+}
+
+#[tokio::test]
+async fn skips_opted_out_users_before_analyzing() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    bot.database.write().await.opt_out(alice.id);
+    let bot = BotState::new(bot.database.clone(), Arc::new(NoAnalyzer));
+
+    bot.record_message(&transport, &message(&alice, "awful"))
+        .await;
+
+    let db = bot.database.read().await;
+    assert!(db.user_data(&alice.id).is_empty());
+    assert_eq!(analyzed(&bot).await, 0);
+}
+
+#[tokio::test]
+async fn only_analyzes_messages_in_scope() {
+    let bot = bot();
+    let category = ChannelId(3000);
+    let memes = ChannelId(2001);
+    let nsfw = ChannelId(2002);
+    let staff = RoleId(4000);
+    let mut transport = MockTransport::default();
+    transport.channels.insert(
+        CHANNEL,
+        ChannelInfo {
+            category: Some(category),
+            nsfw: false,
+        },
+    );
+    transport.channels.insert(
+        nsfw,
+        ChannelInfo {
+            category: Some(category),
+            nsfw: true,
+        },
+    );
+    let alice = user(1, "alice");
+    {
+        let mut db = bot.database.write().await;
+        let mut settings = db.guild_settings(GUILD);
+        settings.scope.deny(ScopeTarget::Channel(memes));
+        settings.scope.deny(ScopeTarget::Role(staff));
+        db.set_guild_settings(GUILD, settings);
+    }
+    let in_channel = |channel_id, roles: &[RoleId]| IncomingMessage {
+        channel_id,
+        roles: roles.to_vec(),
+        ..message(&alice, "good")
+    };
+
+    // No lookups are needed without category rules or the NSFW toggle.
+    bot.record_message(&transport, &in_channel(memes, &[]))
+        .await;
+    bot.record_message(&transport, &in_channel(CHANNEL, &[staff]))
+        .await;
+    bot.record_message(&transport, &in_channel(CHANNEL, &[]))
+        .await;
+    assert_eq!(analyzed(&bot).await, 1);
+    assert_eq!(transport.channel_lookups(), 0);
+
+    {
+        let mut db = bot.database.write().await;
+        let mut settings = db.guild_settings(GUILD);
+        settings.scope.allow(ScopeTarget::Category(category));
+        settings.scope.nsfw = false;
+        db.set_guild_settings(GUILD, settings);
+    }
+    bot.record_message(&transport, &in_channel(nsfw, &[])).await;
+    bot.record_message(&transport, &in_channel(ChannelId(2003), &[]))
+        .await;
+    bot.record_message(&transport, &in_channel(CHANNEL, &[]))
+        .await;
+    bot.record_message(&transport, &in_channel(CHANNEL, &[]))
+        .await;
+    assert_eq!(analyzed(&bot).await, 3);
+    // Channels are only looked up once.
+    assert_eq!(transport.channel_lookups(), 3);
+
+    bot.forget_channel(CHANNEL).await;
+    bot.record_message(&transport, &in_channel(CHANNEL, &[]))
+        .await;
+    assert_eq!(transport.channel_lookups(), 4);
+
+    // Direct messages have no scope.
+    bot.record_message(&transport, &direct_message(&alice, "good"))
+        .await;
+    assert_eq!(analyzed(&bot).await, 5);
+}
+
+#[tokio::test]
+async fn edits_rescore_and_deletions_remove_messages() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    let hostile = message(&alice, "awful");
+    let polite = message(&alice, "good");
+    let other = message(&alice, "bad");
+    for msg in [&hostile, &polite, &other] {
+        bot.record_message(&transport, msg).await;
+    }
+    let compounds = || async {
+        let db = bot.database.read().await;
+        db.get_user_profile(ProfileScope::Guild(GUILD), &alice.id)
+            .unwrap()
+            .history()
+            .iter()
+            .map(|s| (s.message_id().unwrap(), s.compound()))
+            .collect::<Vec<_>>()
+    };
+    let before = compounds().await;
+    assert!(before[0].1 < 0.0);
+
+    bot.record_edit(Some(GUILD), hostile.id, "great").await;
+    // Edits in another scope are not applied.
+    bot.record_edit(None, other.id, "great").await;
+    let after = compounds().await;
+    assert_eq!(after[0].0, hostile.id);
+    assert!(after[0].1 > 0.0);
+    assert_eq!(after[1..], before[1..]);
+
+    bot.record_deletions(Some(GUILD), &[polite.id]).await;
+    bot.record_deletions(Some(GUILD), &[other.id, MessageId(999_999)])
+        .await;
+    let remaining = compounds().await;
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].0, hostile.id);
+}
+
+#[tokio::test]
+async fn ignores_bots_and_commands() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let mut robot = user(2, "robot");
+    robot.bot = true;
+    let alice = user(1, "alice");
+
+    bot.record_message(&transport, &message(&robot, "awful"))
+        .await;
+    bot.record_message(&transport, &message(&alice, "$stats"))
+        .await;
+
+    let db = bot.database.read().await;
+    assert!(db
+        .get_user_profile(ProfileScope::Guild(GUILD), &robot.id)
+        .is_none());
+    assert!(db
+        .get_user_profile(ProfileScope::Guild(GUILD), &alice.id)
+        .is_none());
+    assert_eq!(analyzed(&bot).await, 0);
+}
+
+#[tokio::test]
+async fn uses_the_guild_prefix() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    {
+        let mut db = bot.database.write().await;
+        let mut settings = db.guild_settings(GUILD);
+        settings.set("prefix", "!").unwrap();
+        db.set_guild_settings(GUILD, settings);
+    }
+
+    bot.record_message(&transport, &message(&alice, "!stats"))
+        .await;
+    bot.record_message(&transport, &message(&alice, "$5 is good"))
+        .await;
+
+    let db = bot.database.read().await;
+    let profile = db
+        .get_user_profile(ProfileScope::Guild(GUILD), &alice.id)
+        .unwrap();
+    assert_eq!(profile.history().len(), 1);
+}
+
+#[tokio::test]
+async fn reports_boundary_crossings_to_the_audit_log() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    let audit_channel = ChannelId(3000);
+    {
+        let mut db = bot.database.write().await;
+        let mut settings = db.guild_settings(GUILD);
+        settings.audit_log.channel = Some(audit_channel);
+        settings.audit_log.boundaries = vec![80.0];
+        settings.min_samples = Some(1);
+        db.set_guild_settings(GUILD, settings);
+    }
+
+    bot.record_message(&transport, &message(&alice, "hello"))
+        .await;
+    assert!(transport.sent().is_empty());
+
+    let trigger = message(&alice, "awful awful awful");
+    bot.record_message(&transport, &trigger).await;
+
+    let sent = transport.sent();
+    assert_eq!(sent.len(), 1);
+    let (channel, report) = &sent[0];
+    assert_eq!(*channel, audit_channel);
+    let embed = report.embed.as_ref().expect("Expected an embed");
+    assert_eq!(embed.field("User"), Some("alice#0001"));
+    assert_eq!(embed.field("Old Coefficient"), Some("75.0"));
+    assert_eq!(embed.field("Message"), Some("awful awful awful"));
+    assert_eq!(
+        embed.field("Jump"),
+        Some(format!("[Go to message]({})", trigger.link()).as_str())
+    );
+    assert!(embed
+        .description
+        .as_ref()
+        .unwrap()
+        .contains("rose above 80.0"));
+
+    transport.clear();
+    bot.record_message(&transport, &message(&alice, "awful"))
+        .await;
+    assert!(transport.sent().is_empty());
+}
+
+async fn enable_enforcement(
+    bot: &sibyl_system::bot::BotState,
+    action: EnforcementAction,
+) {
+    let mut db = bot.database.write().await;
+    let mut settings = db.guild_settings(GUILD);
+    settings.enforcement.enabled = true;
+    settings.enforcement.dry_run = false;
+    settings.enforcement.set_tier(EnforcementTier {
+        min_coefficient: 80.0,
+        action,
+    });
+    // Judge users from their first message.
+    settings.min_samples = Some(1);
+    db.set_guild_settings(GUILD, settings);
+}
+
+#[tokio::test]
+async fn waits_for_enough_messages_before_judging() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    enable_enforcement(&bot, EnforcementAction::Timeout { minutes: 10 }).await;
+    {
+        let mut db = bot.database.write().await;
+        let mut settings = db.guild_settings(GUILD);
+        settings.min_samples = Some(3);
+        settings.audit_log.channel = Some(ChannelId(3000));
+        settings.audit_log.boundaries = vec![80.0];
+        db.set_guild_settings(GUILD, settings);
+    }
+
+    bot.record_message(&transport, &message(&alice, "good"))
+        .await;
+    bot.record_message(&transport, &message(&alice, "awful awful awful"))
+        .await;
+    assert!(transport.actions().is_empty());
+    assert!(bot.recent_crossings(Some(GUILD)).await.is_empty());
+
+    bot.record_message(&transport, &message(&alice, "awful awful awful"))
+        .await;
+    assert_eq!(
+        transport.actions(),
+        vec![Call::Timeout(GUILD, alice.id, 10)]
+    );
+}
+
+#[tokio::test]
+async fn reports_no_crossing_from_insufficient_data() {
+    let queue = std::env::temp_dir().join(format!(
+        "sibyl-webhooks-insufficient-{}.json",
+        std::process::id()
+    ));
+    let bot = bot().with_webhooks(Webhooks::open(WebhookConfig {
+        endpoints: vec![WebhookEndpoint {
+            url: "http://127.0.0.1:9/hook".to_string(),
+            secret: "secret".to_string(),
+            events: Vec::new(),
+        }],
+        queue_file: queue.to_str().unwrap().to_string(),
+        ..WebhookConfig::default()
+    }));
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    {
+        let mut db = bot.database.write().await;
+        let mut settings = db.guild_settings(GUILD);
+        settings.min_samples = Some(2);
+        settings.audit_log.channel = Some(ChannelId(3000));
+        settings.audit_log.boundaries = vec![80.0];
+        db.set_guild_settings(GUILD, settings);
+    }
+
+    // The second message rises above the boundary from the first one,
+    // which was too little to judge by.
+    bot.record_message(&transport, &message(&alice, "hello"))
+        .await;
+    bot.record_message(&transport, &message(&alice, "awful awful awful"))
+        .await;
+    assert!(transport.sent().is_empty());
+    assert!(bot.recent_crossings(Some(GUILD)).await.is_empty());
+    assert_eq!(bot.webhooks.pending(), 0);
+    let _ = std::fs::remove_file(&queue);
+}
+
+#[tokio::test]
+async fn warns_users_above_a_tier_once_per_cooldown() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    enable_enforcement(&bot, EnforcementAction::Warn).await;
+
+    bot.record_message(&transport, &message(&alice, "good"))
+        .await;
+    assert!(transport.actions().is_empty());
+
+    let trigger = message(&alice, "awful awful awful");
+    bot.record_message(&transport, &trigger).await;
+    bot.record_message(&transport, &message(&alice, "awful"))
+        .await;
+
+    let sent = transport.sent();
+    assert_eq!(sent.len(), 1);
+    let (channel, warning) = &sent[0];
+    assert_eq!(*channel, CHANNEL);
+    assert_eq!(warning.reply_to, Some(trigger.id));
+    assert!(warning.ping);
+    assert!(warning.content.contains("Please moderate your behavior"));
+}
+
+#[tokio::test]
+async fn assigns_roles() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    enable_enforcement(&bot, EnforcementAction::AssignRole { role: RoleId(7) })
+        .await;
+
+    bot.record_message(&transport, &message(&alice, "awful awful awful"))
+        .await;
+
+    assert_eq!(
+        transport.actions(),
+        vec![Call::AddRole(GUILD, alice.id, RoleId(7))]
+    );
+}
+
+#[tokio::test]
+async fn times_out_users() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    enable_enforcement(&bot, EnforcementAction::Timeout { minutes: 10 }).await;
+
+    bot.record_message(&transport, &message(&alice, "awful awful awful"))
+        .await;
+
+    assert_eq!(
+        transport.actions(),
+        vec![Call::Timeout(GUILD, alice.id, 10)]
+    );
+}
+
+#[tokio::test]
+async fn dry_run_and_exemptions_take_no_action() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    let bob = user(2, "bob");
+    enable_enforcement(&bot, EnforcementAction::Timeout { minutes: 5 }).await;
+    {
+        let mut db = bot.database.write().await;
+        let mut settings = db.guild_settings(GUILD);
+        settings.enforcement.exempt_users.insert(alice.id);
+        db.set_guild_settings(GUILD, settings);
+    }
+
+    bot.record_message(&transport, &message(&alice, "awful awful awful"))
+        .await;
+    assert!(transport.actions().is_empty());
+
+    {
+        let mut db = bot.database.write().await;
+        let mut settings = db.guild_settings(GUILD);
+        settings.enforcement.dry_run = true;
+        db.set_guild_settings(GUILD, settings);
+    }
+    bot.record_message(&transport, &message(&bob, "awful awful awful"))
+        .await;
+    assert!(transport.actions().is_empty());
+}
@@ -0,0 +1,162 @@
+mod common;
+
+use common::*;
+use serde_json::json;
+use serenity::model::interactions::application_command::ApplicationCommandInteractionDataOption;
+use serenity::model::prelude::UserId;
+use sibyl_system::bot::BotState;
+use sibyl_system::interaction;
+use sibyl_system::leaderboard::{self, Order};
+use sibyl_system::userdb::ProfileScope;
+use std::collections::HashSet;
+
+const OWNER: u64 = 9;
+
+/// Options as Discord sends them.
+fn options(
+    value: serde_json::Value,
+) -> Vec<ApplicationCommandInteractionDataOption> {
+    serde_json::from_value(value).unwrap()
+}
+
+fn owners() -> HashSet<UserId> {
+    vec![UserId(OWNER)].into_iter().collect()
+}
+
+async fn history(bot: &BotState) -> usize {
+    let db = bot.database.read().await;
+    db.get_user_profile(ProfileScope::Guild(GUILD), &UserId(1))
+        .unwrap()
+        .history()
+        .len()
+}
+
+#[test]
+fn maps_options_to_command_arguments() {
+    let given = options(json!([
+        {"name": "user", "type": 6, "value": "42"},
+        {"name": "global", "type": 5, "value": true},
+    ]));
+    let (name, given) = interaction::invocation("psycho-pass", &given);
+    assert_eq!(name, "psycho-pass");
+    assert_eq!(interaction::option_args(given), "42 global");
+
+    let given = options(json!([
+        {"name": "user", "type": 6, "value": "42"},
+        {"name": "global", "type": 5, "value": false},
+    ]));
+    assert_eq!(interaction::option_args(&given), "42");
+
+    let given = options(json!([
+        {"name": "text", "type": 3, "value": "a great day"},
+    ]));
+    assert_eq!(interaction::option_args(&given), "a great day");
+}
+
+#[test]
+fn names_subcommands() {
+    let given = options(json!([{
+        "name": "convert",
+        "type": 1,
+        "options": [{"name": "value", "type": 10, "value": -0.5}],
+    }]));
+    let (name, given) = interaction::invocation("debug", &given);
+    assert_eq!(name, "debug convert");
+    assert_eq!(interaction::option_args(given), "-0.5");
+
+    let given = options(json!([{"name": "migrate", "type": 1}]));
+    let (name, given) = interaction::invocation("debug", &given);
+    assert_eq!(name, "debug migrate");
+    assert_eq!(interaction::option_args(given), "");
+}
+
+#[tokio::test]
+async fn only_owners_run_debug_commands() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    for _ in 0..3 {
+        bot.record_message(&transport, &message(&alice, "awful"))
+            .await;
+    }
+
+    let msg = message(&alice, "/debug user-reset 1");
+    interaction::run(
+        &bot,
+        &transport,
+        &msg,
+        "debug user-reset",
+        "1",
+        &owners(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(transport.last_content(), "Only the bot owners can do this");
+    assert_eq!(history(&bot).await, 3);
+
+    // Not every debug command is for the owners.
+    let msg = message(&alice, "/debug convert 0");
+    interaction::run(&bot, &transport, &msg, "debug convert", "0", &owners())
+        .await
+        .unwrap();
+    assert_eq!(transport.last_content(), "75.0");
+
+    let msg = message(&user(OWNER, "owner"), "/debug user-reset 1");
+    interaction::run(
+        &bot,
+        &transport,
+        &msg,
+        "debug user-reset",
+        "1",
+        &owners(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(transport.last_content(), "1 profile reset");
+    assert_eq!(history(&bot).await, 0);
+}
+
+#[tokio::test]
+async fn rejects_unknown_commands() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let msg = message(&user(OWNER, "owner"), "/nope");
+    assert!(
+        interaction::run(&bot, &transport, &msg, "nope", "", &owners())
+            .await
+            .is_err()
+    );
+    assert!(transport.sent().is_empty());
+}
+
+#[tokio::test]
+async fn pages_the_leaderboard_from_buttons() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    for id in 1..=15 {
+        let author = user(id, &format!("user{}", id));
+        for _ in 0..5 {
+            bot.record_message(&transport, &message(&author, "good"))
+                .await;
+        }
+    }
+
+    let button = leaderboard::page_button_id(Order::Highest, 2);
+    let page = interaction::component(&bot, Some(GUILD), &button)
+        .await
+        .expect("a leaderboard page");
+    let expected = sibyl_system::commands::leaderboard_page(
+        &bot,
+        GUILD,
+        Order::Highest,
+        2,
+    )
+    .await;
+    assert_eq!(page, expected);
+    assert!(!page.buttons.is_empty());
+
+    assert!(interaction::component(&bot, None, &button).await.is_none());
+    assert!(interaction::component(&bot, Some(GUILD), "other:1")
+        .await
+        .is_none());
+}
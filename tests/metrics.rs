@@ -0,0 +1,58 @@
+mod common;
+
+use common::*;
+use hyper::{Body, Request, StatusCode};
+use sibyl_system::metrics;
+use std::time::Duration;
+
+async fn get(
+    bot: &sibyl_system::bot::BotState,
+    path: &str,
+) -> (StatusCode, String) {
+    let request = Request::get(path).body(Body::empty()).unwrap();
+    let response = metrics::handle(bot, request).await;
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+#[tokio::test]
+async fn exposes_counters_and_histograms() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    bot.record_message(&transport, &message(&alice, "good"))
+        .await;
+    bot.record_message(&transport, &message(&user(2, "bob"), "bad"))
+        .await;
+    bot.count_command(Some(GUILD), "stats").await;
+    bot.count_command(None, "stats").await;
+    bot.metrics.flush_duration.observe(Duration::from_millis(3));
+    bot.metrics.set_shard_latency(0, Duration::from_millis(40));
+
+    let (status, body) = get(&bot, "/metrics").await;
+    assert_eq!(status, StatusCode::OK);
+    let lines: Vec<&str> = body.lines().collect();
+    for expected in [
+        "# TYPE sibyl_messages_analyzed_total counter",
+        "sibyl_messages_analyzed_total 2",
+        "sibyl_analysis_duration_seconds_count 2",
+        "sibyl_analysis_duration_seconds_bucket{le=\"+Inf\"} 2",
+        "sibyl_commands_total{command=\"stats\"} 2",
+        "sibyl_database_flush_duration_seconds_bucket{le=\"0.0025\"} 0",
+        "sibyl_database_flush_duration_seconds_bucket{le=\"0.005\"} 1",
+        "sibyl_database_flush_duration_seconds_sum 0.003",
+        "sibyl_shard_latency_seconds{shard=\"0\"} 0.04",
+        "sibyl_profiles 2",
+    ] {
+        assert!(
+            lines.contains(&expected),
+            "missing '{}' in\n{}",
+            expected,
+            body
+        );
+    }
+
+    let (status, _) = get(&bot, "/").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,79 @@
+mod common;
+
+use common::*;
+use sibyl_system::config::RetentionConfig;
+use sibyl_system::userdb::{
+    current_timestamp, ProfileScope, RetentionSummary, SentimentResult,
+};
+
+const DAY: u64 = 86400;
+
+#[tokio::test]
+async fn expires_old_entries_and_inactive_profiles() {
+    let bot = bot();
+    let alice = user(1, "alice");
+    let bob = user(2, "bob");
+    let now = current_timestamp();
+    let scope = ProfileScope::Guild(GUILD);
+    let result = SentimentResult::new(0.0, 0.5, 0.5, 0.5);
+
+    let mut db = bot.database.write().await;
+    for age in [40, 10, 1] {
+        let recorded = result.recorded_at(now - age * DAY);
+        db.add_sentiment_result_for_user(scope, &alice, recorded);
+    }
+    db.add_sentiment_result_for_user(
+        scope,
+        &bob,
+        result.recorded_at(now - 100 * DAY),
+    );
+
+    // Nothing is deleted by default.
+    let summary = db.apply_retention(&RetentionConfig::default(), now);
+    assert_eq!(summary, RetentionSummary::default());
+
+    let retention = RetentionConfig {
+        entry_days: 30,
+        inactive_days: 60,
+        ..RetentionConfig::default()
+    };
+    let summary = db.apply_retention(&retention, now);
+    assert_eq!(
+        summary,
+        RetentionSummary {
+            entries: 2,
+            inactive_profiles: vec![(scope, bob.id)],
+            departed_profiles: 0,
+        }
+    );
+    let history = db.get_user_profile(scope, &alice.id).unwrap().history();
+    assert_eq!(history.len(), 2);
+    assert!(history.iter().all(|s| s.timestamp() >= now - 30 * DAY));
+    assert!(db.get_user_profile(scope, &bob.id).is_none());
+}
+
+#[tokio::test]
+async fn forgets_departed_members() {
+    let bot = bot();
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    bot.record_message(&transport, &message(&alice, "good"))
+        .await;
+    bot.record_message(&transport, &direct_message(&alice, "good"))
+        .await;
+
+    let mut db = bot.database.write().await;
+    assert!(db.remove_departed_member(GUILD, alice.id));
+    assert!(!db.remove_departed_member(GUILD, alice.id));
+    assert!(db
+        .get_user_profile(ProfileScope::Guild(GUILD), &alice.id)
+        .is_none());
+    assert!(db
+        .get_user_profile(ProfileScope::DirectMessages, &alice.id)
+        .is_some());
+
+    let now = current_timestamp();
+    let retention = RetentionConfig::default();
+    assert_eq!(db.apply_retention(&retention, now).departed_profiles, 1);
+    assert_eq!(db.apply_retention(&retention, now).departed_profiles, 0);
+}
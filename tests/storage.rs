@@ -0,0 +1,304 @@
+mod common;
+
+use common::*;
+use serenity::model::prelude::{MessageId, UserId};
+use sibyl_system::config::Config;
+use sibyl_system::storage::{
+    import_json, JsonFileStorage, PendingChanges, ProfileStorage, SqliteStorage,
+};
+use sibyl_system::userdb::{
+    current_day, current_timestamp, DatabaseContents, ProfileScope,
+    SentimentResult, UserProfilesDatabase,
+};
+use std::path::Path;
+
+#[test]
+fn sqlite_keeps_message_ids() {
+    let path = std::env::temp_dir()
+        .join(format!("sibyl-storage-test-{}.sqlite", std::process::id()));
+    let path = path.to_str().unwrap();
+    let _ = std::fs::remove_file(path);
+    let open = || {
+        UserProfilesDatabase::open(
+            Box::new(SqliteStorage::open(path).unwrap()),
+            Config::default().guild_defaults(),
+        )
+    };
+    let scope = ProfileScope::Guild(GUILD);
+    let alice = user(1, "alice");
+    let result = SentimentResult::new(0.0, 0.5, 0.5, 0.5);
+
+    let mut db = open();
+    db.add_sentiment_result_for_user(scope, &alice, result.clone());
+    db.add_sentiment_result_for_user(
+        scope,
+        &alice,
+        result.for_message(MessageId(42)),
+    );
+    drop(db);
+
+    let mut db = open();
+    let ids: Vec<Option<MessageId>> = db
+        .get_user_profile(scope, &alice.id)
+        .unwrap()
+        .history()
+        .iter()
+        .map(|s| s.message_id())
+        .collect();
+    assert_eq!(ids, vec![None, Some(MessageId(42))]);
+
+    // Removals are written on the next flush.
+    assert_eq!(db.remove_messages(scope, &[MessageId(42)]), 1);
+    db.flush();
+    drop(db);
+    let db = open();
+    let history = db.get_user_profile(scope, &alice.id).unwrap().history();
+    assert_eq!(history.len(), 1);
+    let _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn sqlite_keeps_usage_counts() {
+    let path = std::env::temp_dir()
+        .join(format!("sibyl-usage-test-{}.sqlite", std::process::id()));
+    let path = path.to_str().unwrap();
+    let _ = std::fs::remove_file(path);
+    let open = || {
+        UserProfilesDatabase::open(
+            Box::new(SqliteStorage::open(path).unwrap()),
+            Config::default().guild_defaults(),
+        )
+    };
+
+    let mut db = open();
+    db.count_message(ProfileScope::Guild(GUILD));
+    db.count_message(ProfileScope::DirectMessages);
+    db.count_command(ProfileScope::Guild(GUILD), "stats");
+    db.flush();
+    drop(db);
+
+    let db = open();
+    let summary = db.usage().summary(Some(GUILD), current_day());
+    assert_eq!(summary.today.messages, 1);
+    assert_eq!(summary.today.commands.get("stats"), Some(&1));
+    assert_eq!(db.usage().summary(None, current_day()).total.messages, 2);
+    let _ = std::fs::remove_file(path);
+}
+
+fn temp_path(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!(
+        "sibyl-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let path = path.to_str().unwrap().to_string();
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn imports_json_into_sqlite() {
+    let json_path = temp_path("import.db");
+    let sqlite_path = temp_path("import.sqlite");
+    let defaults = Config::default().guild_defaults();
+    let alice = user(1, "alice");
+    let bob = user(2, "bob");
+
+    let mut json = UserProfilesDatabase::open(
+        Box::new(JsonFileStorage::new(&json_path, 0)),
+        defaults.clone(),
+    );
+    let now = current_timestamp();
+    for (index, compound) in [0.5, -0.25, 0.75].iter().enumerate() {
+        let result = SentimentResult::new(0.1, 0.6, 0.3, *compound)
+            .recorded_at(now - 100 + index as u64)
+            .for_message(MessageId(10 + index as u64));
+        json.add_sentiment_result_for_user(
+            ProfileScope::Guild(GUILD),
+            &alice,
+            result,
+        );
+    }
+    json.add_sentiment_result_for_user(
+        ProfileScope::DirectMessages,
+        &bob,
+        SentimentResult::new(0.0, 1.0, 0.0, 0.0).recorded_at(now - 50),
+    );
+    let mut settings = json.guild_settings(GUILD);
+    settings.prefix = Some("!".to_string());
+    json.set_guild_settings(GUILD, settings);
+    json.opt_out(user(3, "carol").id);
+    json.flush();
+
+    assert_eq!(import_json(&json_path, &sqlite_path).unwrap(), 2);
+
+    let sqlite = UserProfilesDatabase::open(
+        Box::new(SqliteStorage::open(&sqlite_path).unwrap()),
+        defaults,
+    );
+    for scope in [ProfileScope::Guild(GUILD), ProfileScope::DirectMessages] {
+        let expected: Vec<_> = json.user_profiles(scope).collect();
+        let imported: Vec<_> = sqlite.user_profiles(scope).collect();
+        assert_eq!(expected.len(), imported.len());
+        for (id, profile) in expected {
+            let copy = sqlite.get_user_profile(scope, id).unwrap();
+            assert_eq!(copy.user_tag(), profile.user_tag());
+            let history = |p: &sibyl_system::userdb::UserProfileData| {
+                p.history()
+                    .iter()
+                    .map(|s| (s.timestamp(), s.compound(), s.message_id()))
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(history(copy), history(profile));
+            assert_eq!(copy.snapshots(), profile.snapshots());
+        }
+    }
+    assert_eq!(sqlite.guild_settings(GUILD).prefix.as_deref(), Some("!"));
+    assert!(sqlite.is_opted_out(&user(3, "carol").id));
+    let _ = std::fs::remove_file(&json_path);
+    let _ = std::fs::remove_file(&sqlite_path);
+}
+
+#[test]
+fn sqlite_keeps_only_the_newest_history() {
+    let path = temp_path("trim.sqlite");
+    let mut config = Config::default();
+    config.profile.max_history = 3;
+    let open = || {
+        UserProfilesDatabase::open(
+            Box::new(SqliteStorage::open(&path).unwrap()),
+            config.guild_defaults(),
+        )
+    };
+    let scope = ProfileScope::Guild(GUILD);
+    let alice = user(1, "alice");
+    let now = current_timestamp();
+
+    let mut db = open();
+    for index in 0..5u64 {
+        let result = SentimentResult::new(0.0, 1.0, 0.0, index as f64 / 10.0)
+            .recorded_at(now - 10 + index);
+        db.add_sentiment_result_for_user(scope, &alice, result);
+    }
+    drop(db);
+
+    let rows: i64 = rusqlite::Connection::open(&path)
+        .unwrap()
+        .query_row(
+            "SELECT COUNT(*) FROM sentiment",
+            rusqlite::NO_PARAMS,
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(rows, 3);
+    let db = open();
+    let compounds: Vec<f64> = db
+        .get_user_profile(scope, &alice.id)
+        .unwrap()
+        .history()
+        .iter()
+        .map(|s| s.compound())
+        .collect();
+    assert_eq!(compounds, vec![0.2, 0.3, 0.4]);
+    let _ = std::fs::remove_file(&path);
+}
+
+/// Database contents told apart by the one user who opted out.
+fn version(number: u64) -> DatabaseContents {
+    let mut contents = DatabaseContents::default();
+    contents.opted_out.insert(UserId(number));
+    contents
+}
+
+fn version_of(contents: &DatabaseContents) -> Vec<u64> {
+    contents.opted_out.iter().map(|id| id.0).collect()
+}
+
+fn version_in(path: &str) -> Option<Vec<u64>> {
+    let file = std::fs::read_to_string(path).ok()?;
+    Some(version_of(&serde_json::from_str(&file).unwrap()))
+}
+
+fn remove_json_files(path: &str, backups: usize) {
+    let _ = std::fs::remove_file(path);
+    let _ = std::fs::remove_file(format!("{}.corrupt", path));
+    for index in 1..=backups + 1 {
+        let _ = std::fs::remove_file(format!("{}.{}", path, index));
+    }
+}
+
+#[test]
+fn json_keeps_the_configured_number_of_backups() {
+    let path = temp_path("rotate.db");
+    remove_json_files(&path, 2);
+    let mut storage = JsonFileStorage::new(&path, 2);
+    for number in 1..=4 {
+        storage
+            .save(&version(number), &PendingChanges::default())
+            .unwrap();
+    }
+
+    assert_eq!(version_in(&path), Some(vec![4]));
+    assert_eq!(version_in(&format!("{}.1", path)), Some(vec![3]));
+    assert_eq!(version_in(&format!("{}.2", path)), Some(vec![2]));
+    assert!(!Path::new(&format!("{}.3", path)).exists());
+    assert!(!Path::new(&format!("{}.tmp", path)).exists());
+    remove_json_files(&path, 2);
+}
+
+#[test]
+fn json_recovers_a_corrupt_file_from_the_newest_backup() {
+    let path = temp_path("corrupt.db");
+    remove_json_files(&path, 2);
+    let mut storage = JsonFileStorage::new(&path, 2);
+    storage
+        .save(&version(1), &PendingChanges::default())
+        .unwrap();
+    storage
+        .save(&version(2), &PendingChanges::default())
+        .unwrap();
+
+    // A crash halfway through writing the file in place.
+    let written = std::fs::read(&path).unwrap();
+    std::fs::write(&path, &written[..written.len() / 2]).unwrap();
+
+    let recovered = storage.load().unwrap().expect("Expected a backup");
+    assert_eq!(version_of(&recovered), vec![1]);
+    assert!(!Path::new(&path).exists());
+    let corrupt = std::fs::read(format!("{}.corrupt", path)).unwrap();
+    assert_eq!(corrupt, &written[..written.len() / 2]);
+    remove_json_files(&path, 2);
+}
+
+#[test]
+fn json_recovers_a_missing_file_from_backups() {
+    let path = temp_path("missing.db");
+    remove_json_files(&path, 2);
+    let mut storage = JsonFileStorage::new(&path, 2);
+    storage
+        .save(&version(1), &PendingChanges::default())
+        .unwrap();
+    storage
+        .save(&version(2), &PendingChanges::default())
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let recovered = storage.load().unwrap().expect("Expected a backup");
+    assert_eq!(version_of(&recovered), vec![1]);
+    remove_json_files(&path, 2);
+}
+
+#[test]
+fn json_starts_empty_without_a_file_or_backups() {
+    let path = temp_path("empty.db");
+    remove_json_files(&path, 2);
+    let mut storage = JsonFileStorage::new(&path, 2);
+    assert!(storage.load().unwrap().is_none());
+
+    let db = UserProfilesDatabase::open(
+        Box::new(JsonFileStorage::new(&path, 2)),
+        Config::default().guild_defaults(),
+    );
+    assert_eq!(db.profile_count(), 0);
+    assert!(db.guild_ids().is_empty());
+}
@@ -0,0 +1,189 @@
+use serenity::model::prelude::UserId;
+use sibyl_system::config::Config;
+use sibyl_system::storage::JsonFileStorage;
+use sibyl_system::userdb::{
+    current_timestamp, ComputedData, CymaticParameters, HueState, ProfileScope,
+    SentimentResult, UserProfilesDatabase,
+};
+use std::time::Duration;
+
+const NOW: u64 = 1_600_000_000;
+
+fn parameters() -> CymaticParameters {
+    Config::default().guild_defaults().cymatic
+}
+
+fn result(negative: f64, positive: f64, compound: f64) -> SentimentResult {
+    let neutral = 1.0 - negative - positive;
+    SentimentResult::new(negative, neutral, positive, compound).recorded_at(NOW)
+}
+
+/// A message with only a compound score, recorded `age` seconds before
+/// `NOW`.
+fn aged(compound: f64, age: u64) -> SentimentResult {
+    SentimentResult::new(0.0, 1.0, 0.0, compound).recorded_at(NOW - age)
+}
+
+fn coefficient_at(
+    history: &[SentimentResult],
+    parameters: &CymaticParameters,
+    now: u64,
+) -> f64 {
+    let values: Vec<&SentimentResult> = history.iter().collect();
+    ComputedData::from_sentiment_values(&values, parameters, now)
+        .crime_coefficient
+}
+
+fn compute(history: &[SentimentResult]) -> ComputedData {
+    let values: Vec<&SentimentResult> = history.iter().collect();
+    ComputedData::from_sentiment_values(&values, &parameters(), NOW)
+}
+
+#[test]
+fn hue_colors_match_the_boundary_states() {
+    let crystal = compute(&[result(0.0, 1.0, 0.8), result(0.0, 1.0, 0.8)]).hue;
+    assert_eq!(crystal.state, HueState::Crystal);
+    assert_eq!(crystal.clouded, 0.0);
+    assert_eq!(crystal.color, HueState::Crystal.color());
+    assert_eq!(crystal.hex_string(), "#B3E5FC");
+
+    let turbid = compute(&[result(1.0, 0.0, -0.8), result(1.0, 0.0, -0.8)]).hue;
+    assert_eq!(turbid.state, HueState::Turbid);
+    assert_eq!(turbid.clouded, 1.0);
+    assert_eq!(turbid.color, HueState::Turbid.color());
+
+    let cloudy = compute(&[result(0.5, 0.5, 0.0), result(0.5, 0.5, 0.0)]).hue;
+    assert_eq!(cloudy.state, HueState::Cloudy);
+    assert_eq!(cloudy.color, HueState::Cloudy.color());
+    assert_eq!(cloudy.trend_description(), "Stable");
+}
+
+#[test]
+fn hue_interpolates_between_states() {
+    // Halfway between the clear and cloudy stops.
+    let hue = compute(&[result(0.375, 0.625, 0.0)]).hue;
+    assert_eq!(hue.state, HueState::Clear);
+    let (clear, cloudy) = (HueState::Clear.color(), HueState::Cloudy.color());
+    let mid = |a: u8, b: u8| ((a as f64 + b as f64) / 2.0).round() as u8;
+    assert_eq!(
+        hue.color,
+        (
+            mid(clear.0, cloudy.0),
+            mid(clear.1, cloudy.1),
+            mid(clear.2, cloudy.2)
+        )
+    );
+}
+
+#[test]
+fn hue_trend_follows_the_coefficient() {
+    let rising = compute(&[
+        result(0.0, 0.6, 0.6),
+        result(0.0, 0.6, 0.6),
+        result(0.6, 0.0, -0.6),
+        result(0.6, 0.0, -0.6),
+    ]);
+    assert!(rising.hue.trend > 0.0);
+    assert_eq!(rising.hue.trend_description(), "Clouding");
+
+    let falling = compute(&[
+        result(0.6, 0.0, -0.6),
+        result(0.6, 0.0, -0.6),
+        result(0.0, 0.6, 0.6),
+        result(0.0, 0.6, 0.6),
+    ]);
+    assert!(falling.hue.trend < 0.0);
+    assert_eq!(falling.hue.trend_description(), "Clearing");
+    // The same messages cloud the hue more when the recent ones are worse.
+    assert!(rising.hue.clouded > falling.hue.clouded);
+}
+
+#[test]
+fn half_life_weights_recent_messages_more() {
+    let parameters = CymaticParameters {
+        half_life: Duration::from_secs(3600),
+        ..parameters()
+    };
+    // One half-life old, then new: weights of 0.5 and 1.0 plus the neutral
+    // prior of 1.0.
+    let history = [aged(-0.5, 3600), aged(0.5, 0)];
+    let expected = ComputedData::convert_compound_to_cc(
+        (0.5 * -0.5 + 0.5) / 2.5,
+        &parameters,
+    );
+    let actual = coefficient_at(&history, &parameters, NOW);
+    assert!(
+        (actual - expected).abs() < 1e-9,
+        "{} != {}",
+        actual,
+        expected
+    );
+
+    let old_negative = [aged(-0.8, 10 * 3600), aged(0.2, 0)];
+    let new_negative = [aged(0.2, 10 * 3600), aged(-0.8, 0)];
+    assert!(
+        coefficient_at(&old_negative, &parameters, NOW)
+            < coefficient_at(&new_negative, &parameters, NOW)
+    );
+
+    // Without a half-life the order does not matter.
+    let flat = CymaticParameters {
+        half_life: Duration::from_secs(0),
+        ..parameters
+    };
+    assert_eq!(
+        coefficient_at(&old_negative, &flat, NOW),
+        coefficient_at(&new_negative, &flat, NOW)
+    );
+}
+
+#[test]
+fn coefficient_recovers_towards_neutral_over_time() {
+    let parameters = CymaticParameters {
+        half_life: Duration::from_secs(3600),
+        ..parameters()
+    };
+    let history = [aged(-0.9, 0), aged(-0.9, 0)];
+    let coefficients: Vec<f64> = [0, 1, 4, 40]
+        .iter()
+        .map(|half_lives| {
+            coefficient_at(&history, &parameters, NOW + half_lives * 3600)
+        })
+        .collect();
+    assert!(coefficients.windows(2).all(|pair| pair[0] > pair[1]));
+    assert!(coefficients[0] > parameters.cc_neutral + 100.0);
+    // The prior pulls the compound up to zero, approached from below.
+    let neutral = ComputedData::convert_compound_to_cc(-1e-9, &parameters);
+    assert!((coefficients[3] - neutral).abs() < 0.01);
+}
+
+#[test]
+fn loads_legacy_databases_without_timestamps() {
+    let path = std::env::temp_dir()
+        .join(format!("sibyl-legacy-test-{}.db", std::process::id()));
+    std::fs::copy("tests/fixtures/legacy_userccs.db", &path).unwrap();
+    let before = current_timestamp();
+
+    let db = UserProfilesDatabase::open(
+        Box::new(JsonFileStorage::new(path.to_str().unwrap(), 0)),
+        Config::default().guild_defaults(),
+    );
+    let alice = db
+        .get_user_profile(ProfileScope::Unscoped, &UserId(1))
+        .expect("legacy profiles are kept unscoped");
+    assert_eq!(alice.user_tag(), "alice#0001");
+    let history = alice.history();
+    let compounds: Vec<f64> = history.iter().map(|s| s.compound()).collect();
+    assert_eq!(compounds, vec![0.5, -0.6]);
+    // Results without a timestamp count as recorded when loaded.
+    assert!(history.iter().all(|s| s.timestamp() >= before));
+    assert!(history.iter().all(|s| s.message_id().is_none()));
+    assert_eq!(
+        db.get_user_profile(ProfileScope::Unscoped, &UserId(2))
+            .unwrap()
+            .history()
+            .len(),
+        1
+    );
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,372 @@
+mod common;
+
+use common::*;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use serde_json::Value;
+use sibyl_system::config::{RetentionConfig, WebhookConfig, WebhookEndpoint};
+use sibyl_system::userdb::current_timestamp;
+use sibyl_system::webhook::{self, Payload, WebhookEvent, Webhooks};
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+
+const SECRET: &str = "shared secret";
+
+#[derive(Debug, Clone)]
+struct Received {
+    event: String,
+    timestamp: String,
+    signature: String,
+    body: String,
+}
+
+/// A local endpoint that answers with `statuses` in turn, then with 200.
+struct Listener {
+    url: String,
+    received: Arc<Mutex<Vec<Received>>>,
+}
+
+fn listen(statuses: Vec<u16>) -> Listener {
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let statuses = Arc::new(Mutex::new(statuses));
+    let log = received.clone();
+    let make_service = make_service_fn(move |_| {
+        let log = log.clone();
+        let statuses = statuses.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                let log = log.clone();
+                let statuses = statuses.clone();
+                async move {
+                    let header = |name: &str| {
+                        request.headers()[name].to_str().unwrap().to_string()
+                    };
+                    let (event, timestamp, signature) = (
+                        header(webhook::EVENT_HEADER),
+                        header(webhook::TIMESTAMP_HEADER),
+                        header(webhook::SIGNATURE_HEADER),
+                    );
+                    let body = hyper::body::to_bytes(request.into_body())
+                        .await
+                        .unwrap();
+                    log.lock().unwrap().push(Received {
+                        event,
+                        timestamp,
+                        signature,
+                        body: String::from_utf8(body.to_vec()).unwrap(),
+                    });
+                    let mut statuses = statuses.lock().unwrap();
+                    let status = if statuses.is_empty() {
+                        200
+                    } else {
+                        statuses.remove(0)
+                    };
+                    let mut response = Response::new(Body::empty());
+                    *response.status_mut() =
+                        StatusCode::from_u16(status).unwrap();
+                    Ok::<_, Infallible>(response)
+                }
+            }))
+        }
+    });
+    let server =
+        Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make_service);
+    let url = format!("http://{}/hook", server.local_addr());
+    tokio::spawn(server);
+    Listener { url, received }
+}
+
+fn config(url: &str, queue_file: &str, events: &[&str]) -> WebhookConfig {
+    WebhookConfig {
+        endpoints: vec![WebhookEndpoint {
+            url: url.to_string(),
+            secret: SECRET.to_string(),
+            events: events.iter().map(|e| e.to_string()).collect(),
+        }],
+        queue_file: queue_file.to_string(),
+        retry_secs: 0,
+        max_retry_secs: 0,
+        ..WebhookConfig::default()
+    }
+}
+
+fn queue_file(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!(
+        "sibyl-webhooks-{}-{}.json",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    path.to_str().unwrap().to_string()
+}
+
+fn opted_out(user_id: &str) -> WebhookEvent {
+    WebhookEvent::OptedOut {
+        user_id: user_id.to_string(),
+        profiles_deleted: 1,
+    }
+}
+
+#[tokio::test]
+async fn posts_signed_threshold_crossings() {
+    let listener = listen(Vec::new());
+    let queue = queue_file("crossing");
+    let bot = bot().with_webhooks(Webhooks::open(config(
+        &listener.url,
+        &queue,
+        &["threshold_crossed"],
+    )));
+    {
+        let mut db = bot.database.write().await;
+        let mut settings = db.guild_settings(GUILD);
+        settings.audit_log.boundaries = vec![80.0];
+        settings.min_samples = Some(1);
+        db.set_guild_settings(GUILD, settings);
+    }
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    bot.record_message(&transport, &message(&alice, "hello"))
+        .await;
+    let trigger = message(&alice, "awful awful awful");
+    bot.record_message(&transport, &trigger).await;
+    // Not an event this endpoint wants.
+    bot.webhooks.notify(opted_out("1"));
+
+    assert_eq!(bot.webhooks.pending(), 1);
+    assert_eq!(bot.webhooks.deliver_due().await, 1);
+    assert_eq!(bot.webhooks.pending(), 0);
+
+    let received = listener.received.lock().unwrap().clone();
+    assert_eq!(received.len(), 1);
+    let request = &received[0];
+    assert_eq!(request.event, "threshold_crossed");
+    let timestamp: u64 = request.timestamp.parse().unwrap();
+    assert_eq!(
+        request.signature,
+        format!("sha256={}", webhook::sign(SECRET, timestamp, &request.body))
+    );
+    assert_ne!(
+        request.signature,
+        format!(
+            "sha256={}",
+            webhook::sign("wrong", timestamp, &request.body)
+        )
+    );
+
+    let body: Value = serde_json::from_str(&request.body).unwrap();
+    assert_eq!(body["event"], "threshold_crossed");
+    assert_eq!(body["guild_id"], GUILD.to_string());
+    assert_eq!(body["user_id"], "1");
+    assert_eq!(body["user_tag"], "alice#0001");
+    assert_eq!(body["boundary"], 80.0);
+    assert_eq!(body["old"], 75.0);
+    assert_eq!(body["message_link"], trigger.link());
+    let payload: Payload = serde_json::from_str(&request.body).unwrap();
+    assert_eq!(payload.event.name(), "threshold_crossed");
+    let _ = std::fs::remove_file(&queue);
+}
+
+#[tokio::test]
+async fn retries_failures_across_restarts() {
+    let listener = listen(vec![503, 500]);
+    let queue = queue_file("retry");
+    let webhooks = Webhooks::open(config(&listener.url, &queue, &[]));
+    webhooks.notify(opted_out("7"));
+    assert_eq!(webhooks.deliver_due().await, 0);
+    assert_eq!(webhooks.pending(), 1);
+    drop(webhooks);
+
+    let webhooks = Webhooks::open(config(&listener.url, &queue, &[]));
+    assert_eq!(webhooks.pending(), 1);
+    assert_eq!(webhooks.deliver_due().await, 0);
+    assert_eq!(webhooks.deliver_due().await, 1);
+    assert_eq!(webhooks.pending(), 0);
+
+    let received = listener.received.lock().unwrap().clone();
+    assert_eq!(received.len(), 3);
+    // Every attempt carries the same notification.
+    assert!(received.iter().all(|r| r.body == received[0].body));
+    drop(webhooks);
+    assert_eq!(
+        Webhooks::open(config(&listener.url, &queue, &[])).pending(),
+        0
+    );
+    let _ = std::fs::remove_file(&queue);
+}
+
+#[tokio::test]
+async fn saves_the_queue_outside_notify() {
+    let listener = listen(vec![500]);
+    let queue = queue_file("save");
+    let webhooks = Webhooks::open(config(&listener.url, &queue, &[]));
+    webhooks.notify(opted_out("7"));
+    assert!(!std::path::Path::new(&queue).exists());
+
+    webhooks.save().await;
+    assert_eq!(
+        Webhooks::open(config(&listener.url, &queue, &[])).pending(),
+        1
+    );
+    assert!(listener.received.lock().unwrap().is_empty());
+    let _ = std::fs::remove_file(&queue);
+}
+
+#[tokio::test]
+async fn drops_notifications_after_max_attempts() {
+    let listener = listen(vec![500, 500, 500]);
+    let queue = queue_file("drop");
+    let webhooks = Webhooks::open(WebhookConfig {
+        max_attempts: 2,
+        ..config(&listener.url, &queue, &[])
+    });
+    webhooks.notify(opted_out("7"));
+    webhooks.deliver_due().await;
+    assert_eq!(webhooks.pending(), 1);
+    webhooks.deliver_due().await;
+    assert_eq!(webhooks.pending(), 0);
+    assert_eq!(listener.received.lock().unwrap().len(), 2);
+    let _ = std::fs::remove_file(&queue);
+}
+
+#[tokio::test]
+async fn waits_for_the_backoff() {
+    let listener = listen(vec![500]);
+    let queue = queue_file("backoff");
+    let webhooks = Webhooks::open(WebhookConfig {
+        retry_secs: 60,
+        max_retry_secs: 3600,
+        ..config(&listener.url, &queue, &[])
+    });
+    webhooks.notify(opted_out("7"));
+    webhooks.deliver_due().await;
+    webhooks.deliver_due().await;
+    assert_eq!(webhooks.pending(), 1);
+    assert_eq!(listener.received.lock().unwrap().len(), 1);
+    let _ = std::fs::remove_file(&queue);
+}
+
+#[test]
+fn doubles_the_retry_delay() {
+    let config = WebhookConfig {
+        retry_secs: 30,
+        max_retry_secs: 200,
+        ..WebhookConfig::default()
+    };
+    let delays: Vec<u64> = (1..=5).map(|n| config.retry_delay(n)).collect();
+    assert_eq!(delays, vec![30, 60, 120, 200, 200]);
+    assert_eq!(config.retry_delay(u32::MAX), 200);
+}
+
+#[tokio::test]
+async fn reports_opt_outs_and_purges() {
+    let listener = listen(Vec::new());
+    let queue = queue_file("commands");
+    let bot = bot().with_webhooks(Webhooks::open(config(
+        &listener.url,
+        &queue,
+        &["opted_out", "purged"],
+    )));
+    let transport = MockTransport::default();
+    let alice = user(1, "alice");
+    bot.record_message(&transport, &message(&alice, "hello"))
+        .await;
+    sibyl_system::commands::forgetme(
+        &bot,
+        &transport,
+        &message(&alice, "$forgetme"),
+        args(""),
+    )
+    .await
+    .unwrap();
+    sibyl_system::commands::optout(
+        &bot,
+        &transport,
+        &message(&alice, "$optout"),
+        args(""),
+    )
+    .await
+    .unwrap();
+    assert_eq!(bot.webhooks.deliver_due().await, 2);
+
+    let events: Vec<Value> = listener
+        .received
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|r| serde_json::from_str(&r.body).unwrap())
+        .collect();
+    assert_eq!(events[0]["event"], "purged");
+    assert_eq!(events[0]["profiles_deleted"], 1);
+    assert_eq!(events[0]["guild_id"], Value::Null);
+    assert_eq!(events[1]["event"], "opted_out");
+    assert_eq!(events[1]["user_id"], "1");
+    let _ = std::fs::remove_file(&queue);
+}
+
+#[tokio::test]
+async fn reports_resets_departures_and_inactive_profiles() {
+    let listener = listen(Vec::new());
+    let queue = queue_file("purges");
+    let bot = bot().with_webhooks(Webhooks::open(config(
+        &listener.url,
+        &queue,
+        &["purged"],
+    )));
+    let transport = MockTransport::default();
+    let (alice, bob, carol) =
+        (user(1, "alice"), user(2, "bob"), user(3, "carol"));
+    for author in [&alice, &bob, &carol] {
+        bot.record_message(&transport, &message(author, "hello"))
+            .await;
+    }
+
+    sibyl_system::commands::debug_user_reset(
+        &bot,
+        &transport,
+        &message(&user(9, "owner"), "$debug user reset 1"),
+        args("1"),
+    )
+    .await
+    .unwrap();
+    assert!(bot.remove_departed_member(GUILD, bob.id).await);
+    assert!(!bot.remove_departed_member(GUILD, bob.id).await);
+    let retention = RetentionConfig {
+        inactive_days: 1,
+        ..RetentionConfig::default()
+    };
+    let later = current_timestamp() + 2 * 86400;
+    let summary = bot.apply_retention(&retention, later).await;
+    assert_eq!(summary.departed_profiles, 1);
+    assert_eq!(summary.inactive_profiles.len(), 2);
+    assert_eq!(bot.webhooks.deliver_due().await, 4);
+
+    let mut events: Vec<(String, String)> = listener
+        .received
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|r| {
+            let body: Value = serde_json::from_str(&r.body).unwrap();
+            assert_eq!(body["guild_id"], GUILD.to_string());
+            (
+                body["user_id"].as_str().unwrap().to_string(),
+                body["reason"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect();
+    events.sort();
+    let expected = [
+        ("1", "inactive"),
+        ("1", "reset"),
+        ("2", "departed"),
+        ("3", "inactive"),
+    ];
+    assert_eq!(
+        events,
+        expected
+            .iter()
+            .map(|(id, reason)| (id.to_string(), reason.to_string()))
+            .collect::<Vec<_>>()
+    );
+    let _ = std::fs::remove_file(&queue);
+}